@@ -20,14 +20,14 @@ fn main() -> Result<()> {
 	if let Ok(file3) = fs.get_node("dir1/dir2/file3") {
 		println!(
 			"\nFound file at: {}",
-			file3.borrow().relative_location().display()
+			file3.relative_location().display()
 		);
 	}
 
 	// List all nodes
 	println!("\nAll nodes:");
 	for node in fs.nodes() {
-		println!("- {}", node.borrow().relative_location().display());
+		println!("- {}", node.relative_location().display());
 	}
 
 	Ok(())
@@ -0,0 +1,589 @@
+use std::{
+	collections::HashMap,
+	fmt,
+	io::{self, Cursor, Read, Write},
+	path::{Component, Path, PathBuf},
+	str::FromStr,
+};
+
+use flate2::{Compression, write::GzEncoder};
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+	error::{Error, Result},
+	filesystem::FileSystem,
+	fsnode,
+	journal::JOURNAL_DIR,
+	safety,
+	target::{ExtractTarget, MemoryEntry},
+};
+
+// Permission bits tar gives a symlink, which are never used
+const LINK_MODE: u32 = 0o777;
+
+/// Kinds of archive a filesystem can be written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+	/// An uncompressed tar archive
+	Tar,
+	/// A tar archive compressed with gzip
+	TarGz,
+	/// A zip archive, each file compressed with deflate
+	Zip,
+}
+
+impl ArchiveFormat {
+	/// Returns the name the format is given by
+	pub fn name(&self) -> &'static str {
+		match self {
+			ArchiveFormat::Tar => "tar",
+			ArchiveFormat::TarGz => "tar.gz",
+			ArchiveFormat::Zip => "zip",
+		}
+	}
+
+	/// Picks the format from the extension of a path like `out.tar.gz`, or
+	/// `None` if it is not one of theirs
+	pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+		let name = path.as_ref().file_name()?.to_str()?.to_lowercase();
+		if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+			Some(ArchiveFormat::TarGz)
+		}
+		else if name.ends_with(".tar") {
+			Some(ArchiveFormat::Tar)
+		}
+		else if name.ends_with(".zip") {
+			Some(ArchiveFormat::Zip)
+		}
+		else {
+			None
+		}
+	}
+
+	/// Recognizes an archive from its first bytes, or returns `None` for
+	/// anything else, bundles included
+	///
+	/// Gzip data is taken to be a compressed tar. A tar archive is only
+	/// recognized from its first 262 bytes.
+	pub fn detect(bytes: &[u8]) -> Option<Self> {
+		if bytes.starts_with(&[0x1f, 0x8b]) {
+			Some(ArchiveFormat::TarGz)
+		}
+		else if bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(b"PK\x05\x06") {
+			Some(ArchiveFormat::Zip)
+		}
+		else if bytes.get(257..262) == Some(b"ustar") {
+			Some(ArchiveFormat::Tar)
+		}
+		else {
+			None
+		}
+	}
+}
+
+impl fmt::Display for ArchiveFormat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+impl FromStr for ArchiveFormat {
+	type Err = Error;
+
+	fn from_str(name: &str) -> Result<Self> {
+		match name.to_lowercase().as_str() {
+			"tar" => Ok(ArchiveFormat::Tar),
+			"tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+			"zip" => Ok(ArchiveFormat::Zip),
+			_ => Err(Error::Archive(format!("unknown archive format: {}", name))),
+		}
+	}
+}
+
+/// How entries are stamped in a written archive
+///
+/// Every entry gets the same time and is owned by user and group 0 with no
+/// names, so the same files always give the same bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArchiveOptions {
+	/// Modification time of every entry, in seconds since the Unix epoch
+	pub mtime: u64,
+}
+
+impl ArchiveOptions {
+	/// Takes the time from `SOURCE_DATE_EPOCH` when it is set, as
+	/// reproducible builds do, and the Unix epoch otherwise. An empty value
+	/// counts as unset.
+	pub fn from_env() -> Result<Self> {
+		let mtime = match std::env::var("SOURCE_DATE_EPOCH") {
+			Ok(epoch) if !epoch.trim().is_empty() => epoch.trim().parse().map_err(|_| {
+				Error::Archive(format!("SOURCE_DATE_EPOCH is not a timestamp: {}", epoch))
+			})?,
+			_ => 0,
+		};
+		Ok(ArchiveOptions { mtime })
+	}
+}
+
+/// Writes directories, files and symlinks into a tar, tar.gz or zip archive
+///
+/// Entries are kept until `finish`, so a mode can still be set on any of
+/// them, and are then written in the order they were first added. Paths are
+/// relative and use `/`; `..`, absolute paths and links out of the archive
+/// are refused like they are on extraction. Files get 0o644 and
+/// directories 0o755 unless given a mode.
+pub struct ArchiveWriter<W: Write> {
+	writer: W,
+	format: ArchiveFormat,
+	options: ArchiveOptions,
+	entries: Vec<(PathBuf, MemoryEntry)>,
+	// Index of each path in entries
+	index: HashMap<PathBuf, usize>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+	/// Starts an archive of the format on the writer
+	pub fn new(writer: W, format: ArchiveFormat, options: ArchiveOptions) -> Self {
+		ArchiveWriter {
+			writer,
+			format,
+			options,
+			entries: Vec::new(),
+			index: HashMap::new(),
+		}
+	}
+
+	/// Adds a directory entry
+	pub fn add_directory(&mut self, path: &Path, mode: u32) -> Result<()> {
+		safety::check_path(path)?;
+		self.stage(path, MemoryEntry::Directory { mode: Some(mode) });
+		Ok(())
+	}
+
+	/// Adds a file entry with the bytes as its contents
+	pub fn add_file(&mut self, path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+		safety::check_path(path)?;
+		self.stage(
+			path,
+			MemoryEntry::File {
+				contents: contents.to_vec(),
+				mode: Some(mode),
+			},
+		);
+		Ok(())
+	}
+
+	/// Adds a symlink entry pointing at the target
+	pub fn add_symlink(&mut self, path: &Path, target: &str) -> Result<()> {
+		safety::check_link(path, target)?;
+		self.stage(
+			path,
+			MemoryEntry::Symlink {
+				target: target.to_string(),
+			},
+		);
+		Ok(())
+	}
+
+	// Keeps an entry to write, replacing one already at the path in place
+	fn stage(&mut self, path: &Path, entry: MemoryEntry) {
+		match self.index.get(path) {
+			Some(&i) => self.entries[i].1 = entry,
+			None => {
+				self.index.insert(path.to_path_buf(), self.entries.len());
+				self.entries.push((path.to_path_buf(), entry));
+			}
+		}
+	}
+
+	// Returns the entry kept for the path, if any
+	fn staged(&self, path: &Path) -> Option<&MemoryEntry> {
+		self.index.get(path).map(|&i| &self.entries[i].1)
+	}
+
+	/// Writes every entry and the end of the archive, and returns the writer
+	pub fn finish(self) -> Result<W> {
+		let ArchiveWriter {
+			writer,
+			format,
+			options,
+			entries,
+			..
+		} = self;
+		match format {
+			ArchiveFormat::Tar => write_tar(tar::Builder::new(writer), &options, &entries),
+			// The gzip header carries no time or name unless given one
+			ArchiveFormat::TarGz => {
+				let builder = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+				Ok(write_tar(builder, &options, &entries)?.finish()?)
+			}
+			ArchiveFormat::Zip => write_zip(writer, &options, &entries),
+		}
+	}
+}
+
+impl<W: Write> ExtractTarget for ArchiveWriter<W> {
+	fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+		safety::check_path(path)?;
+		let mut current = PathBuf::new();
+		for component in path.components() {
+			current.push(component);
+			match self.staged(&current) {
+				Some(MemoryEntry::Directory { .. }) => {}
+				Some(_) => return Err(Error::AlreadyExists(current.display().to_string())),
+				None => self.stage(&current, MemoryEntry::Directory { mode: None }),
+			}
+		}
+		Ok(())
+	}
+
+	fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<()> {
+		safety::check_path(path)?;
+		// Refused like a directory on disk or a MemoryTarget would
+		let mode = match self.staged(path) {
+			None => None,
+			Some(MemoryEntry::File { mode, .. }) => *mode,
+			Some(MemoryEntry::Symlink { .. }) => {
+				return Err(Error::UnsafePath(path.display().to_string()));
+			}
+			Some(MemoryEntry::Directory { .. }) => {
+				return Err(Error::AlreadyExists(path.display().to_string()));
+			}
+		};
+		self.stage(
+			path,
+			MemoryEntry::File {
+				contents: contents.to_vec(),
+				mode,
+			},
+		);
+		Ok(())
+	}
+
+	fn set_mode(&mut self, path: &Path, new_mode: u32) -> Result<()> {
+		let i = *self
+			.index
+			.get(path)
+			.ok_or_else(|| Error::NodeNotFound(path.display().to_string()))?;
+		match &mut self.entries[i].1 {
+			MemoryEntry::File { mode, .. } | MemoryEntry::Directory { mode } => {
+				*mode = Some(new_mode & 0o7777);
+				Ok(())
+			}
+			MemoryEntry::Symlink { .. } => Err(Error::UnsafePath(path.display().to_string())),
+		}
+	}
+
+	fn symlink(&mut self, path: &Path, target: &str) -> Result<()> {
+		match self.staged(path) {
+			None | Some(MemoryEntry::Symlink { .. }) => self.add_symlink(path, target),
+			Some(_) => Err(Error::AlreadyExists(path.display().to_string())),
+		}
+	}
+
+	fn remove_file(&mut self, path: &Path) -> Result<()> {
+		match self.staged(path) {
+			Some(MemoryEntry::File { .. }) => {
+				self.entries.retain(|(staged, _)| staged != path);
+				self.index = (self.entries.iter().enumerate())
+					.map(|(i, (path, _))| (path.clone(), i))
+					.collect();
+				Ok(())
+			}
+			Some(MemoryEntry::Symlink { .. }) => Err(Error::UnsafePath(path.display().to_string())),
+			Some(MemoryEntry::Directory { .. }) => Err(Error::InvalidNodeType),
+			None => Err(Error::NodeNotFound(path.display().to_string())),
+		}
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		self.index.contains_key(path)
+	}
+
+	fn read_file(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+		match self.staged(path) {
+			Some(MemoryEntry::File { contents, .. }) => Ok(Some(contents.clone())),
+			_ => Ok(None),
+		}
+	}
+}
+
+/// Writes every node of the filesystem into an archive on the writer
+///
+/// Entries are laid out like `write_to_disk` would, under the root
+/// directory's name if it has one.
+pub fn write_archive<W: Write>(
+	fs: &FileSystem<'_>,
+	writer: W,
+	format: ArchiveFormat,
+	options: ArchiveOptions,
+) -> Result<W> {
+	let mut archive = ArchiveWriter::new(writer, format, options);
+	fs.extract_to(&mut archive)?;
+	archive.finish()
+}
+
+// Writes the entries to a tar archive, each with a fixed time and owner
+fn write_tar<W: Write>(
+	mut builder: tar::Builder<W>,
+	options: &ArchiveOptions,
+	entries: &[(PathBuf, MemoryEntry)],
+) -> Result<W> {
+	for (path, entry) in entries {
+		let name = archive_path(path);
+		let mut header = tar::Header::new_gnu();
+		header.set_mtime(options.mtime);
+		header.set_uid(0);
+		header.set_gid(0);
+		match entry {
+			MemoryEntry::Directory { mode } => {
+				header.set_entry_type(tar::EntryType::Directory);
+				header.set_mode(mode.unwrap_or(fsnode::DEFAULT_EXECUTABLE_MODE));
+				header.set_size(0);
+				builder.append_data(&mut header, format!("{}/", name), io::empty())?;
+			}
+			MemoryEntry::File { contents, mode } => {
+				header.set_entry_type(tar::EntryType::Regular);
+				header.set_mode(mode.unwrap_or(fsnode::DEFAULT_FILE_MODE));
+				header.set_size(contents.len() as u64);
+				builder.append_data(&mut header, name, contents.as_slice())?;
+			}
+			MemoryEntry::Symlink { target } => {
+				header.set_entry_type(tar::EntryType::Symlink);
+				header.set_mode(LINK_MODE);
+				header.set_size(0);
+				builder.append_link(&mut header, name, target)?;
+			}
+		}
+	}
+	Ok(builder.into_inner()?)
+}
+
+// Writes the entries to a zip archive. Zip needs to seek back over what it
+// wrote, so the archive is built in memory and copied out at the end.
+fn write_zip<W: Write>(
+	mut writer: W,
+	options: &ArchiveOptions,
+	entries: &[(PathBuf, MemoryEntry)],
+) -> Result<W> {
+	let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+	let stamped = SimpleFileOptions::default().last_modified_time(zip_time(options.mtime));
+	for (path, entry) in entries {
+		let name = archive_path(path);
+		match entry {
+			MemoryEntry::Directory { mode } => {
+				let mode = mode.unwrap_or(fsnode::DEFAULT_EXECUTABLE_MODE);
+				zip.add_directory(name, stamped.unix_permissions(mode))
+					.map_err(zip_error)?;
+			}
+			MemoryEntry::File { contents, mode } => {
+				let options = stamped
+					.compression_method(CompressionMethod::Deflated)
+					.unix_permissions(mode.unwrap_or(fsnode::DEFAULT_FILE_MODE));
+				zip.start_file(name, options).map_err(zip_error)?;
+				zip.write_all(contents)?;
+			}
+			MemoryEntry::Symlink { target } => {
+				zip.add_symlink(name, target, stamped.unix_permissions(LINK_MODE))
+					.map_err(zip_error)?;
+			}
+		}
+	}
+	let archive = zip.finish().map_err(zip_error)?;
+	writer.write_all(archive.get_ref())?;
+	Ok(writer)
+}
+
+/// Reads an archive into a filesystem, in the order of its entries
+///
+/// Directories, files and symlinks keep their modes and targets; times and
+/// owners are dropped, as are hard links, devices and other special entries.
+/// Directories an entry is in are created if the archive does not list them,
+/// and a later entry for the same path replaces an earlier one. Paths that
+/// would leave the directory the archive is extracted to, like `../x` or
+/// `/etc/passwd`, and symlinks pointing out of it fail with
+/// `Error::UnsafePath`.
+pub fn read_archive(mut reader: impl Read, format: ArchiveFormat) -> Result<FileSystem<'static>> {
+	let mut fs = FileSystem::new();
+	match format {
+		ArchiveFormat::Tar => read_tar(&mut fs, reader)?,
+		ArchiveFormat::TarGz => read_tar(&mut fs, flate2::read::GzDecoder::new(reader))?,
+		ArchiveFormat::Zip => {
+			// The zip index is at the end, so the whole archive is needed
+			let mut bytes = Vec::new();
+			reader.read_to_end(&mut bytes)?;
+			read_zip(&mut fs, bytes)?;
+		}
+	}
+	Ok(fs)
+}
+
+// Adds the entries of a tar archive
+fn read_tar(fs: &mut FileSystem<'static>, reader: impl Read) -> Result<()> {
+	let mut archive = tar::Archive::new(reader);
+	for entry in archive.entries()? {
+		let mut entry = entry?;
+		let path = entry.path()?.into_owned();
+		// Some writers leave the mode of a symlink blank
+		let mode = entry
+			.header()
+			.mode()
+			.ok()
+			.map(|mode| mode & fsnode::UNTRUSTED_MODE_MASK);
+		let read = match entry.header().entry_type() {
+			tar::EntryType::Directory => ReadEntry::Directory,
+			tar::EntryType::Regular | tar::EntryType::Continuous => {
+				let mut contents = Vec::new();
+				entry.read_to_end(&mut contents)?;
+				ReadEntry::File(contents)
+			}
+			tar::EntryType::Symlink => match entry.link_name()? {
+				Some(target) => ReadEntry::Symlink(target.to_string_lossy().into_owned()),
+				None => continue,
+			},
+			_ => continue,
+		};
+		add_entry(fs, &path, read, mode)?;
+	}
+	Ok(())
+}
+
+// Adds the entries of a zip archive
+fn read_zip(fs: &mut FileSystem<'static>, bytes: Vec<u8>) -> Result<()> {
+	let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(zip_error)?;
+	for i in 0..archive.len() {
+		let mut file = archive.by_index(i).map_err(zip_error)?;
+		let path = PathBuf::from(file.name());
+		let mode = file
+			.unix_mode()
+			.map(|mode| mode & fsnode::UNTRUSTED_MODE_MASK);
+		let read = if file.is_dir() {
+			ReadEntry::Directory
+		}
+		else {
+			let mut contents = Vec::new();
+			file.read_to_end(&mut contents)?;
+			if file.is_symlink() {
+				ReadEntry::Symlink(String::from_utf8_lossy(&contents).into_owned())
+			}
+			else {
+				ReadEntry::File(contents)
+			}
+		};
+		add_entry(fs, &path, read, mode)?;
+	}
+	Ok(())
+}
+
+// What an entry read from an archive holds
+enum ReadEntry {
+	Directory,
+	File(Vec<u8>),
+	Symlink(String),
+}
+
+// Adds an entry read from an archive at its path, creating the directories
+// it is in
+fn add_entry(
+	fs: &mut FileSystem<'static>,
+	path: &Path,
+	entry: ReadEntry,
+	mode: Option<u32>,
+) -> Result<()> {
+	// Tar often writes relative paths as `./src/main.rs`
+	let relative: PathBuf = path
+		.components()
+		.filter(|component| !matches!(component, Component::CurDir))
+		.collect();
+	// An archive of an output directory may carry its extraction journal,
+	// which is left out like it is when packing
+	if relative.as_os_str().is_empty() || relative.starts_with(JOURNAL_DIR) {
+		return Ok(());
+	}
+	safety::check_path(&relative)?;
+	let path = archive_path(&relative);
+
+	match entry {
+		ReadEntry::Directory => {
+			let id = fs.create_dir_all(&path)?;
+			fs.set_mode(id, mode)
+		}
+		ReadEntry::File(contents) => {
+			make_room(fs, &path)?;
+			let id = fs.add_file(&path, None)?;
+			fs.set_bytes(id, contents)?;
+			fs.set_mode(id, mode)
+		}
+		ReadEntry::Symlink(target) => {
+			safety::check_link(&relative, &target)?;
+			make_room(fs, &path)?;
+			fs.add_symlink(&path, &target)?;
+			Ok(())
+		}
+	}
+}
+
+// Removes whatever an earlier entry left at the path and creates the
+// directories the path is in
+fn make_room(fs: &mut FileSystem<'static>, path: &str) -> Result<()> {
+	if fs.get_node(path).is_ok() {
+		fs.remove(path)?;
+	}
+	if let Some((parent, _)) = path.rsplit_once('/') {
+		fs.create_dir_all(parent)?;
+	}
+	Ok(())
+}
+
+// Converts seconds since the Unix epoch to a zip timestamp, which only covers
+// 1980 to 2107
+fn zip_time(mtime: u64) -> DateTime {
+	let days = mtime / 86400;
+	let seconds = mtime % 86400;
+
+	// Days to a civil date, after Howard Hinnant's `civil_from_days`
+	let z = days as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let day_of_era = z - era * 146097;
+	let year_of_era =
+		(day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+	let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+	let month_index = (5 * day_of_year + 2) / 153;
+	let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+	let month = if month_index < 10 {
+		month_index + 3
+	}
+	else {
+		month_index - 9
+	};
+	let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+	match u16::try_from(year) {
+		Ok(year) if year < 1980 => DateTime::default(),
+		Ok(year) if year <= 2107 => DateTime::from_date_and_time(
+			year,
+			month as u8,
+			day as u8,
+			(seconds / 3600) as u8,
+			(seconds / 60 % 60) as u8,
+			(seconds % 60) as u8,
+		)
+		.unwrap_or_default(),
+		_ => DateTime::from_date_and_time(2107, 12, 31, 23, 59, 58).unwrap_or_default(),
+	}
+}
+
+// Joins the components of a relative path with '/', whatever the platform
+fn archive_path(path: &Path) -> String {
+	path.components()
+		.filter_map(|component| match component {
+			Component::Normal(name) => Some(name.to_string_lossy()),
+			_ => None,
+		})
+		.collect::<Vec<_>>()
+		.join("/")
+}
+
+fn zip_error(err: zip::result::ZipError) -> Error {
+	Error::Archive(err.to_string())
+}
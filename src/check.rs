@@ -0,0 +1,219 @@
+use crate::{
+	diagnostics::{Diagnostic, DiagnosticKind, Severity, column_of_name},
+	filesystem::{FileSystem, ParseOptions},
+	rust::{self, RustIssueKind},
+	safety,
+};
+
+// Characters Windows does not allow in file names
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+// Device names Windows reserves regardless of extension
+const RESERVED_NAMES: &[&str] = &[
+	"CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+	"COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Checks whether a bundle is coherent before it is extracted
+///
+/// Reports everything `FileSystem::parse_with_diagnostics` does, along with
+/// empty files, files that look cut off part way through, names that
+/// cannot be used on common filesystems, symlinks out of the output
+/// directory and the Rust problems found by
+/// `rust::check_crates`. Diagnostics are sorted by line.
+pub fn check(input: &str, comment_prefix: Option<&str>) -> Vec<Diagnostic> {
+	check_with_options(input, comment_prefix, ParseOptions::default())
+}
+
+/// Checks a bundle like `check`, parsing it with the given options
+pub fn check_with_options(
+	input: &str,
+	comment_prefix: Option<&str>,
+	options: ParseOptions,
+) -> Vec<Diagnostic> {
+	let (fs, mut diagnostics) =
+		FileSystem::parse_with_options(input, comment_prefix, false, options);
+	let lines: Vec<&str> = input.lines().collect();
+	let line_at = |line_number: usize| lines.get(line_number - 1).copied().unwrap_or_default();
+
+	for node in fs.nodes() {
+		if node.is_root() {
+			continue;
+		}
+
+		if let Some(line_number) = node.span().header_line
+			&& let Some((severity, reason)) = check_name(node.name())
+		{
+			let line = line_at(line_number);
+			diagnostics.push(Diagnostic {
+				severity,
+				..Diagnostic::warning(
+					DiagnosticKind::InvalidName,
+					format!("`{}` {}", node.relative_location().display(), reason),
+					line,
+					line_number,
+					column_of_name(line, node.name()),
+				)
+			});
+		}
+
+		// Links out of the output directory are refused on extraction
+		if let Some(target) = node.link_target()
+			&& let Some(line_number) = node.span().header_line
+			&& safety::check_link(&node.relative_location(), target).is_err()
+		{
+			let line = line_at(line_number);
+			diagnostics.push(Diagnostic {
+				severity: Severity::Error,
+				..Diagnostic::warning(
+					DiagnosticKind::UnsafeLink,
+					format!(
+						"`{}` links to `{}`, outside the output directory",
+						node.relative_location().display(),
+						target
+					),
+					line,
+					line_number,
+					column_of_name(line, node.name()),
+				)
+			});
+		}
+
+		// Blank sections are left without contents but with a span
+		let Some(body_lines) = &node.span().body_lines
+		else {
+			continue;
+		};
+		match node.bytes() {
+			None if !node.is_directory() => {
+				let marker_line = body_lines.start - 1;
+				let line = line_at(marker_line);
+				diagnostics.push(Diagnostic::warning(
+					DiagnosticKind::EmptyFile,
+					format!("`{}` is empty", node.relative_location().display()),
+					line,
+					marker_line,
+					column_of_name(line, node.name()),
+				));
+			}
+			Some(_) => {
+				let last_line = body_lines.end.saturating_sub(1).max(body_lines.start);
+				let line = line_at(last_line);
+				if let Some(reason) = node.truncation() {
+					diagnostics.push(Diagnostic::warning(
+						DiagnosticKind::TruncatedFile,
+						format!(
+							"`{}` looks truncated: {}",
+							node.relative_location().display(),
+							reason
+						),
+						line,
+						last_line,
+						line.chars().count() + 1,
+					));
+				}
+				// Common in answers and harmless, but `extract` adds one
+				else if node.lacks_final_newline() {
+					diagnostics.push(Diagnostic {
+						severity: Severity::Note,
+						..Diagnostic::warning(
+							DiagnosticKind::NoFinalNewline,
+							format!(
+								"`{}` has no trailing newline",
+								node.relative_location().display()
+							),
+							line,
+							last_line,
+							line.chars().count() + 1,
+						)
+					});
+				}
+			}
+			None => {}
+		}
+	}
+
+	for issue in rust::check_crates(&fs) {
+		let Some(node) = fs
+			.nodes()
+			.find(|node| node.relative_location() == issue.path)
+		else {
+			continue;
+		};
+
+		// Problems inside a file point into its section, others at its entry
+		let (line_number, column) = match (issue.line, &node.span().body_lines) {
+			(Some(line), Some(body_lines)) => (body_lines.start + line - 1, issue.column),
+			_ => match node.span().header_line {
+				Some(header_line) => (
+					header_line,
+					column_of_name(line_at(header_line), node.name()),
+				),
+				None => continue,
+			},
+		};
+		let kind = match issue.kind {
+			RustIssueKind::UnresolvedModule => DiagnosticKind::UnresolvedModule,
+			RustIssueKind::MissingTarget => DiagnosticKind::MissingTarget,
+			RustIssueKind::OrphanSourceFile => DiagnosticKind::OrphanSourceFile,
+		};
+		let line = line_at(line_number);
+		diagnostics.push(match issue.kind {
+			RustIssueKind::OrphanSourceFile => {
+				Diagnostic::warning(kind, issue.message, line, line_number, column)
+			}
+			_ => Diagnostic::error(kind, issue.message, line, line_number, column),
+		});
+	}
+
+	diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+	diagnostics
+}
+
+// Returns how serious a name that cannot be used is, and why
+fn check_name(name: &str) -> Option<(Severity, String)> {
+	// These would escape the directory or cannot be created anywhere
+	if name == "." || name == ".." {
+		return Some((
+			Severity::Error,
+			"refers to a directory rather than naming one".to_string(),
+		));
+	}
+	if name.contains('\0') {
+		return Some((Severity::Error, "contains a NUL character".to_string()));
+	}
+
+	// These work on Unix but not on Windows
+	if let Some(c) = name
+		.chars()
+		.find(|c| RESERVED_CHARS.contains(c) || c.is_control())
+	{
+		return Some((
+			Severity::Warning,
+			format!("contains {:?}, which Windows does not allow", c),
+		));
+	}
+	if name.ends_with('.') || name.ends_with(' ') {
+		return Some((
+			Severity::Warning,
+			"ends with a dot or space, which Windows drops".to_string(),
+		));
+	}
+	let stem = name.split('.').next().unwrap_or_default();
+	if RESERVED_NAMES
+		.iter()
+		.any(|reserved| reserved.eq_ignore_ascii_case(stem))
+	{
+		return Some((
+			Severity::Warning,
+			format!("uses `{}`, a device name Windows reserves", stem),
+		));
+	}
+	if name.len() > 255 {
+		return Some((
+			Severity::Warning,
+			"is longer than the 255 bytes most filesystems allow".to_string(),
+		));
+	}
+	None
+}
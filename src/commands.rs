@@ -0,0 +1,280 @@
+use crate::prose::DroppedText;
+
+// Languages of code blocks whose lines are commands to run
+const SHELL_LANGUAGES: &[&str] = &["bash", "sh", "shell", "zsh", "console", "shell-session"];
+
+// Programs an inline code span has to start with to be taken as a command
+const PROGRAMS: &[&str] = &[
+	"apt",
+	"apt-get",
+	"brew",
+	"bun",
+	"cargo",
+	"cd",
+	"chmod",
+	"cmake",
+	"cp",
+	"curl",
+	"deno",
+	"docker",
+	"docker-compose",
+	"dotnet",
+	"export",
+	"gem",
+	"git",
+	"go",
+	"gradle",
+	"helm",
+	"kubectl",
+	"make",
+	"mkdir",
+	"mv",
+	"mvn",
+	"node",
+	"npm",
+	"npx",
+	"pip",
+	"pip3",
+	"pnpm",
+	"poetry",
+	"python",
+	"python3",
+	"rustup",
+	"source",
+	"sudo",
+	"touch",
+	"uv",
+	"wget",
+	"yarn",
+];
+
+/// A shell command found in the prose between files
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellCommand {
+	/// 1-based line the command starts on in the input
+	pub line: usize,
+	/// The command, with any continuation lines it has
+	pub command: String,
+}
+
+/// Collects the shell commands from text left out of every file
+///
+/// Every line of a `bash`, `sh` or similar code block is a command, apart
+/// from comments and, in `console` blocks, the output between prompts.
+/// Inline code like "Run `cargo run`" counts when it starts with a
+/// well-known program; the same inline command is only taken once.
+pub fn shell_commands(dropped: &[DroppedText]) -> Vec<ShellCommand> {
+	let mut commands: Vec<ShellCommand> = Vec::new();
+	for text in dropped {
+		// The language of the code block each line is in, if any
+		let mut block: Option<&str> = None;
+		let mut continued = false;
+
+		for (i, line) in text.text.lines().enumerate() {
+			let line_number = text.line + i;
+			let trimmed = line.trim();
+
+			if let Some(language) = trimmed.strip_prefix("```") {
+				// A fence with a language always opens a block, a bare one
+				// closes the open one
+				block = match (block, language.trim()) {
+					(Some(_), "") => None,
+					(_, language) => Some(language),
+				};
+				continued = false;
+				continue;
+			}
+
+			match block {
+				Some(language) if SHELL_LANGUAGES.contains(&language) => {
+					let command = if continued {
+						Some(line)
+					}
+					else if language == "console" || language == "shell-session" {
+						trimmed.strip_prefix("$ ")
+					}
+					else {
+						Some(trimmed.strip_prefix("$ ").unwrap_or(trimmed))
+					};
+					let Some(command) = command.filter(|command| {
+						continued || !(command.is_empty() || command.starts_with('#'))
+					})
+					else {
+						continue;
+					};
+
+					match commands.last_mut() {
+						Some(last) if continued => {
+							last.command.push('\n');
+							last.command.push_str(command);
+						}
+						_ => commands.push(ShellCommand {
+							line: line_number,
+							command: command.to_string(),
+						}),
+					}
+					continued = command.ends_with('\\');
+				}
+				Some(_) => {}
+				None => {
+					for span in line.split('`').skip(1).step_by(2) {
+						let span = span.trim();
+						let span = span.strip_prefix("$ ").unwrap_or(span);
+						if is_inline_command(span)
+							&& !commands.iter().any(|command| command.command == span)
+						{
+							commands.push(ShellCommand {
+								line: line_number,
+								command: span.to_string(),
+							});
+						}
+					}
+				}
+			}
+		}
+	}
+	commands
+}
+
+// Returns true for inline code that reads as a command, like `cargo run`
+// rather than `Config` or `cargo`
+fn is_inline_command(span: &str) -> bool {
+	let mut words = span.split_whitespace();
+	let Some(program) = words.next()
+	else {
+		return false;
+	};
+	(PROGRAMS.contains(&program) && words.next().is_some()) || program.starts_with("./")
+}
+
+/// Returns a script running the commands in order, for review before use
+pub fn setup_script(commands: &[ShellCommand]) -> String {
+	let mut script = String::from(concat!(
+		"#!/bin/sh\n",
+		"# Commands collected from the prose of a bundle by claudeai-bundle.\n",
+		"# Review them before running this script; it is never run for you.\n",
+		"set -e\n",
+	));
+	for command in commands {
+		script.push_str(&format!("\n# line {}\n{}\n", command.line, command.command));
+	}
+	script
+}
+
+/// A `chmod` among the shell commands, like `chmod +x run.sh`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chmod {
+	/// 1-based line the command starts on in the input
+	pub line: usize,
+	/// The mode as given, octal like `755` or symbolic like `u+x,go-w`
+	pub mode: String,
+	/// The paths the mode applies to, as given
+	pub paths: Vec<String>,
+}
+
+impl Chmod {
+	/// Returns the mode the command leaves a node with, starting from its
+	/// current bits, or `None` if the mode cannot be read
+	pub fn apply(&self, mode: u32, is_dir: bool) -> Option<u32> {
+		if (1..=4).contains(&self.mode.len()) && self.mode.chars().all(|c| ('0'..='7').contains(&c))
+		{
+			return u32::from_str_radix(&self.mode, 8).ok();
+		}
+
+		let mut mode = mode;
+		for clause in self.mode.split(',') {
+			let operators = clause.find(['+', '-', '='])?;
+			let (who, mut rest) = clause.split_at(operators);
+			let mut mask = 0;
+			for c in who.chars() {
+				mask |= match c {
+					'u' => 0o4700,
+					'g' => 0o2070,
+					'o' => 0o0007,
+					'a' => 0o7777,
+					_ => return None,
+				};
+			}
+			// No one named means everyone
+			if mask == 0 {
+				mask = 0o7777;
+			}
+
+			while let Some(operator) = rest.chars().next() {
+				let permissions = &rest[1..];
+				let end = permissions
+					.find(['+', '-', '='])
+					.unwrap_or(permissions.len());
+				let mut bits = 0;
+				for c in permissions[..end].chars() {
+					bits |= match c {
+						'r' => 0o444,
+						'w' => 0o222,
+						'x' => 0o111,
+						// Execute only where something already has it, or for
+						// directories
+						'X' if is_dir || mode & 0o111 != 0 => 0o111,
+						'X' => 0,
+						's' => 0o6000,
+						't' => 0o1000,
+						_ => return None,
+					};
+				}
+				let bits = bits & mask;
+				match operator {
+					'+' => mode |= bits,
+					'-' => mode &= !bits,
+					'=' => mode = (mode & !(mask & 0o777)) | bits,
+					_ => return None,
+				}
+				rest = &permissions[end..];
+			}
+		}
+		Some(mode & 0o7777)
+	}
+}
+
+/// Picks the `chmod` commands out of the shell commands, in order
+///
+/// Commands chained with `&&` or `;` are looked at one by one, and options
+/// like `-R` are skipped.
+pub fn chmods(commands: &[ShellCommand]) -> Vec<Chmod> {
+	let mut chmods = Vec::new();
+	for command in commands {
+		for part in command
+			.command
+			.split(['\n', ';'])
+			.flat_map(|part| part.split("&&"))
+		{
+			let mut words = part
+				.split_whitespace()
+				.filter(|word| *word != "\\")
+				.skip_while(|word| *word == "sudo");
+			if words.next() != Some("chmod") {
+				continue;
+			}
+			let mut words = words.skip_while(|word| is_chmod_option(word));
+			let Some(mode) = words.next()
+			else {
+				continue;
+			};
+			let paths: Vec<String> = words.map(str::to_string).collect();
+			if !paths.is_empty() {
+				chmods.push(Chmod {
+					line: command.line,
+					mode: mode.to_string(),
+					paths,
+				});
+			}
+		}
+	}
+	chmods
+}
+
+// Returns true for an option of chmod, as opposed to a mode like `-x`
+fn is_chmod_option(word: &str) -> bool {
+	word.starts_with("--")
+		|| word
+			.strip_prefix('-')
+			.is_some_and(|flags| !flags.is_empty() && flags.chars().all(|c| "Rvfc".contains(c)))
+}
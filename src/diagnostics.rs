@@ -0,0 +1,207 @@
+use std::fmt;
+
+use crate::error::{self, ParseError};
+
+/// How serious a problem found while parsing is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+	/// Worth knowing about, but not a problem
+	Note,
+	/// The tree was built, but may not be what the author meant
+	Warning,
+	/// Part of the input had to be dropped to build the tree
+	Error,
+}
+
+impl Severity {
+	/// Returns the name of the severity, as shown in output
+	pub fn name(&self) -> &'static str {
+		match self {
+			Severity::Note => "note",
+			Severity::Warning => "warning",
+			Severity::Error => "error",
+		}
+	}
+}
+
+/// The kind of problem a diagnostic reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+	/// A path declared twice in the header, or given two body sections
+	DuplicatePath,
+	/// A body section for a path the header does not declare
+	OrphanSection,
+	/// A file declared in the header that no body section fills in
+	MissingContents,
+	/// A header entry indented under a file rather than a directory
+	NestedUnderFile,
+	/// Indentation that does not map cleanly onto a depth
+	AmbiguousIndentation,
+	/// A body section for a path that is a directory
+	DirectoryContents,
+	/// A body section that cannot be set on its path, such as a symlink
+	InvalidSection,
+	/// A file whose body section is blank
+	EmptyFile,
+	/// A file whose contents look cut off part way through
+	TruncatedFile,
+	/// A name that cannot be used on common filesystems
+	InvalidName,
+	/// A Rust `mod` declaration with no file for the module
+	UnresolvedModule,
+	/// A `Cargo.toml` target path that is not in the bundle
+	MissingTarget,
+	/// A Rust source file no crate root reaches
+	OrphanSourceFile,
+	/// A base64 or hex body section that does not decode
+	InvalidEncoding,
+	/// A symlink whose target is outside the output directory
+	UnsafeLink,
+	/// A file whose lines had a gutter, quote markers or indentation removed
+	NormalizedContents,
+	/// A file whose last line has no trailing newline
+	NoFinalNewline,
+}
+
+impl DiagnosticKind {
+	/// Returns a stable name for the kind, for machine-readable output
+	pub fn name(&self) -> &'static str {
+		match self {
+			DiagnosticKind::DuplicatePath => "duplicate-path",
+			DiagnosticKind::OrphanSection => "orphan-section",
+			DiagnosticKind::MissingContents => "missing-contents",
+			DiagnosticKind::NestedUnderFile => "nested-under-file",
+			DiagnosticKind::AmbiguousIndentation => "ambiguous-indentation",
+			DiagnosticKind::DirectoryContents => "directory-contents",
+			DiagnosticKind::InvalidSection => "invalid-section",
+			DiagnosticKind::EmptyFile => "empty-file",
+			DiagnosticKind::TruncatedFile => "truncated-file",
+			DiagnosticKind::InvalidName => "invalid-name",
+			DiagnosticKind::UnresolvedModule => "unresolved-module",
+			DiagnosticKind::MissingTarget => "missing-target",
+			DiagnosticKind::OrphanSourceFile => "orphan-source-file",
+			DiagnosticKind::InvalidEncoding => "invalid-encoding",
+			DiagnosticKind::UnsafeLink => "unsafe-link",
+			DiagnosticKind::NormalizedContents => "normalized-contents",
+			DiagnosticKind::NoFinalNewline => "no-final-newline",
+		}
+	}
+}
+
+/// A problem found while parsing, pointing at the line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	pub severity: Severity,
+	pub kind: DiagnosticKind,
+	pub message: String,
+	/// 1-based line number in the input
+	pub line: usize,
+	/// 1-based column, counted in characters
+	pub column: usize,
+	/// The text of the offending line
+	pub snippet: String,
+}
+
+impl Diagnostic {
+	/// Creates a warning pointing at a column of the given source line
+	pub fn warning(
+		kind: DiagnosticKind,
+		message: impl Into<String>,
+		snippet: &str,
+		line: usize,
+		column: usize,
+	) -> Self {
+		Diagnostic {
+			severity: Severity::Warning,
+			kind,
+			message: message.into(),
+			line,
+			column,
+			snippet: snippet.to_string(),
+		}
+	}
+
+	/// Creates an error pointing at a column of the given source line
+	pub fn error(
+		kind: DiagnosticKind,
+		message: impl Into<String>,
+		snippet: &str,
+		line: usize,
+		column: usize,
+	) -> Self {
+		Diagnostic {
+			severity: Severity::Error,
+			..Diagnostic::warning(kind, message, snippet, line, column)
+		}
+	}
+
+	/// Returns true if this diagnostic is an error rather than a warning
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+
+	/// Returns the diagnostic as a single line of JSON
+	pub fn to_json(&self) -> String {
+		format!(
+			concat!(
+				"{{\"severity\":\"{}\",\"kind\":\"{}\",\"line\":{},\"column\":{},",
+				"\"message\":{},\"snippet\":{}}}"
+			),
+			self.severity.name(),
+			self.kind.name(),
+			self.line,
+			self.column,
+			json_string(&self.message),
+			json_string(&self.snippet)
+		)
+	}
+}
+
+impl From<Diagnostic> for ParseError {
+	fn from(diagnostic: Diagnostic) -> Self {
+		ParseError::new(
+			diagnostic.message,
+			&diagnostic.snippet,
+			diagnostic.line,
+			diagnostic.column,
+		)
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "{}: {}", self.severity.name(), self.message)?;
+		error::write_snippet(f, self.line, self.column, &self.snippet)
+	}
+}
+
+// Quotes and escapes a string for JSON output
+fn json_string(text: &str) -> String {
+	let mut quoted = String::from("\"");
+	for c in text.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			'\r' => quoted.push_str("\\r"),
+			'\t' => quoted.push_str("\\t"),
+			c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+			c => quoted.push(c),
+		}
+	}
+	quoted.push('"');
+	quoted
+}
+
+// Returns the 1-based column of the first character of part within line,
+// which part must be a slice of
+pub(crate) fn column_of(line: &str, part: &str) -> usize {
+	let offset = (part.as_ptr() as usize).saturating_sub(line.as_ptr() as usize);
+	line[..offset.min(line.len())].chars().count() + 1
+}
+
+// Returns the 1-based column a node's name starts at on its header line
+pub(crate) fn column_of_name(line: &str, name: &str) -> usize {
+	line.rfind(name)
+		.map_or(1, |offset| column_of(line, &line[offset..]))
+}
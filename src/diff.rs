@@ -0,0 +1,419 @@
+use std::{
+	borrow::Cow,
+	collections::{BTreeMap, BTreeSet},
+	fmt, fs,
+	path::{Path, PathBuf},
+};
+
+use similar::{ChangeTag, TextDiff};
+
+use crate::{error::Result, filesystem::FileSystem, fsnode::FsNode, journal::JOURNAL_DIR};
+
+// Directories on disk that never take part in a comparison
+pub(crate) const IGNORED_DIRS: &[&str] = &[".git", JOURNAL_DIR];
+
+/// How a file differs between the on-disk directory and the bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+	/// The file is in the bundle but not on disk
+	Added,
+	/// The file is on disk but not in the bundle
+	Removed,
+	/// The file is in both with different contents
+	Modified,
+}
+
+/// A single file that differs between the on-disk directory and the bundle
+#[derive(Debug, Clone)]
+pub struct FileChange {
+	pub path: PathBuf,
+	pub kind: ChangeKind,
+	/// Contents on disk, if the file exists there
+	pub old: Option<String>,
+	/// Contents in the bundle, if the file exists there
+	pub new: Option<String>,
+}
+
+impl FileChange {
+	/// Returns the number of inserted and deleted lines
+	pub fn line_counts(&self) -> (usize, usize) {
+		let old = self.old.as_deref().unwrap_or_default();
+		let new = self.new.as_deref().unwrap_or_default();
+
+		let mut insertions = 0;
+		let mut deletions = 0;
+		for change in TextDiff::from_lines(old, new).iter_all_changes() {
+			match change.tag() {
+				ChangeTag::Insert => insertions += 1,
+				ChangeTag::Delete => deletions += 1,
+				ChangeTag::Equal => {}
+			}
+		}
+		(insertions, deletions)
+	}
+
+	/// Returns the change as a unified diff with `a/` and `b/` headers
+	pub fn unified_diff(&self) -> String {
+		let path = self.path.display();
+		let old_header = match self.kind {
+			ChangeKind::Added => "/dev/null".to_string(),
+			_ => format!("a/{}", path),
+		};
+		let new_header = match self.kind {
+			ChangeKind::Removed => "/dev/null".to_string(),
+			_ => format!("b/{}", path),
+		};
+
+		let old = self.old.as_deref().unwrap_or_default();
+		let new = self.new.as_deref().unwrap_or_default();
+		unified_diff(old, new, &old_header, &new_header)
+	}
+}
+
+// Renders a unified diff between two texts with three lines of context
+fn unified_diff(old: &str, new: &str, old_header: &str, new_header: &str) -> String {
+	TextDiff::from_lines(old, new)
+		.unified_diff()
+		.context_radius(3)
+		.header(old_header, new_header)
+		.to_string()
+}
+
+// Collects every file below dir, keyed by its path relative to root
+fn collect_disk_files(
+	root: &Path,
+	dir: &Path,
+	files: &mut BTreeMap<PathBuf, String>,
+) -> Result<()> {
+	for entry in fs::read_dir(dir)? {
+		let entry = entry?;
+		let path = entry.path();
+		let file_type = entry.file_type()?;
+
+		if file_type.is_dir() {
+			if IGNORED_DIRS
+				.iter()
+				.any(|ignored| entry.file_name() == *ignored)
+			{
+				continue;
+			}
+			collect_disk_files(root, &path, files)?;
+		}
+		else if file_type.is_file() {
+			let bytes = fs::read(&path)?;
+			let contents = String::from_utf8_lossy(&bytes).into_owned();
+			let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+			files.insert(relative, contents);
+		}
+	}
+	Ok(())
+}
+
+/// Compares the files of a bundle against a directory on disk
+///
+/// Files are matched by their path relative to the directory, the same path
+/// `extract` would write them to. The `.git` directory and the extraction
+/// journal are ignored. Changes are returned sorted by path.
+pub fn diff_directory(fs: &FileSystem<'_>, dir: impl AsRef<Path>) -> Result<Vec<FileChange>> {
+	let dir = dir.as_ref();
+
+	let mut disk_files = BTreeMap::new();
+	collect_disk_files(dir, dir, &mut disk_files)?;
+
+	let mut bundle_files = BTreeMap::new();
+	for node in fs.nodes() {
+		if let FsNode::File { .. } = *node {
+			bundle_files.insert(node.relative_location(), file_text(node.fs_node()));
+		}
+	}
+
+	let mut changes = Vec::new();
+	for (path, new) in &bundle_files {
+		match disk_files.get(path) {
+			None => changes.push(FileChange {
+				path: path.clone(),
+				kind: ChangeKind::Added,
+				old: None,
+				new: Some(new.to_string()),
+			}),
+			Some(old) if old != new => changes.push(FileChange {
+				path: path.clone(),
+				kind: ChangeKind::Modified,
+				old: Some(old.clone()),
+				new: Some(new.to_string()),
+			}),
+			Some(_) => {}
+		}
+	}
+	for (path, old) in disk_files {
+		if !bundle_files.contains_key(&path) {
+			changes.push(FileChange {
+				path,
+				kind: ChangeKind::Removed,
+				old: Some(old),
+				new: None,
+			});
+		}
+	}
+
+	changes.sort_by(|a, b| a.path.cmp(&b.path));
+	Ok(changes)
+}
+
+/// Minimum content similarity for a removed and an added file to be reported
+/// as a rename
+pub const RENAME_THRESHOLD: f32 = 0.5;
+
+/// What kind of node a path is, for reporting type changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+	File,
+	Directory,
+	Symlink,
+}
+
+impl NodeKind {
+	/// Returns the name the kind is reported by
+	pub fn name(&self) -> &'static str {
+		match self {
+			NodeKind::File => "file",
+			NodeKind::Directory => "directory",
+			NodeKind::Symlink => "symlink",
+		}
+	}
+}
+
+impl fmt::Display for NodeKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.name())
+	}
+}
+
+/// A single structural difference between two filesystems
+#[derive(Debug, Clone, PartialEq)]
+pub enum NodeChange {
+	/// The node only exists in the new filesystem
+	Added { path: PathBuf, is_directory: bool },
+	/// The node only exists in the old filesystem
+	Removed { path: PathBuf, is_directory: bool },
+	/// The file exists in both with different contents
+	ContentsChanged {
+		path: PathBuf,
+		old: String,
+		new: String,
+	},
+	/// The node is of a different kind in each filesystem, such as a file
+	/// that became a directory or a symlink
+	TypeChanged {
+		path: PathBuf,
+		old_kind: NodeKind,
+		new_kind: NodeKind,
+	},
+	/// A removed file whose contents closely match an added file
+	Renamed {
+		from: PathBuf,
+		to: PathBuf,
+		similarity: f32,
+		old: String,
+		new: String,
+	},
+}
+
+impl NodeChange {
+	/// Returns the path this change is reported under
+	pub fn path(&self) -> &Path {
+		match self {
+			NodeChange::Added { path, .. } => path,
+			NodeChange::Removed { path, .. } => path,
+			NodeChange::ContentsChanged { path, .. } => path,
+			NodeChange::TypeChanged { path, .. } => path,
+			NodeChange::Renamed { to, .. } => to,
+		}
+	}
+
+	/// Returns a unified diff of the contents for changed and renamed files
+	pub fn unified_diff(&self) -> Option<String> {
+		match self {
+			NodeChange::ContentsChanged { path, old, new } => Some(unified_diff(
+				old,
+				new,
+				&format!("a/{}", path.display()),
+				&format!("b/{}", path.display()),
+			)),
+			NodeChange::Renamed {
+				from, to, old, new, ..
+			} => Some(unified_diff(
+				old,
+				new,
+				&format!("a/{}", from.display()),
+				&format!("b/{}", to.display()),
+			)),
+			_ => None,
+		}
+	}
+}
+
+// A node reduced to what the structural diff compares
+enum DiffEntry<'f> {
+	Directory,
+	File(Cow<'f, str>),
+	Symlink(&'f str),
+}
+
+impl DiffEntry<'_> {
+	fn kind(&self) -> NodeKind {
+		match self {
+			DiffEntry::Directory => NodeKind::Directory,
+			DiffEntry::File(_) => NodeKind::File,
+			DiffEntry::Symlink(_) => NodeKind::Symlink,
+		}
+	}
+}
+
+// Returns a file's contents as text, with bytes that are not valid UTF-8
+// replaced as they are for files on disk
+fn file_text<'f>(node: &'f FsNode<'_>) -> Cow<'f, str> {
+	String::from_utf8_lossy(node.bytes().unwrap_or_default())
+}
+
+fn diff_entries<'f>(fs: &'f FileSystem<'_>) -> BTreeMap<PathBuf, DiffEntry<'f>> {
+	let mut entries = BTreeMap::new();
+	for node in fs.nodes() {
+		let path = node.relative_location();
+
+		// The root has no path of its own
+		if path.as_os_str().is_empty() {
+			continue;
+		}
+
+		let entry = match node.fs_node() {
+			file @ FsNode::File { .. } => DiffEntry::File(file_text(file)),
+			FsNode::Directory { .. } => DiffEntry::Directory,
+			FsNode::Symlink { target, .. } => DiffEntry::Symlink(target),
+		};
+		entries.insert(path, entry);
+	}
+	entries
+}
+
+/// Computes the structural differences between two filesystems
+///
+/// Nodes are matched by path. Files removed from `old` and added to `new` are
+/// paired up as renames when their contents are at least `RENAME_THRESHOLD`
+/// similar, best matches first. Changes are returned sorted by path.
+pub fn diff_filesystems(old: &FileSystem<'_>, new: &FileSystem<'_>) -> Vec<NodeChange> {
+	let old_entries = diff_entries(old);
+	let new_entries = diff_entries(new);
+
+	let mut changes = Vec::new();
+	let mut removed_files = Vec::new();
+	let mut added_files = Vec::new();
+
+	for (path, old_entry) in &old_entries {
+		match (old_entry, new_entries.get(path)) {
+			(DiffEntry::File(old), Some(DiffEntry::File(new))) => {
+				if old != new {
+					changes.push(NodeChange::ContentsChanged {
+						path: path.clone(),
+						old: old.to_string(),
+						new: new.to_string(),
+					});
+				}
+			}
+			// A symlink's target stands in for its contents
+			(DiffEntry::Symlink(old), Some(DiffEntry::Symlink(new))) => {
+				if old != new {
+					changes.push(NodeChange::ContentsChanged {
+						path: path.clone(),
+						old: old.to_string(),
+						new: new.to_string(),
+					});
+				}
+			}
+			(DiffEntry::Directory, Some(DiffEntry::Directory)) => {}
+			(_, Some(new_entry)) => changes.push(NodeChange::TypeChanged {
+				path: path.clone(),
+				old_kind: old_entry.kind(),
+				new_kind: new_entry.kind(),
+			}),
+			(DiffEntry::File(contents), None) => removed_files.push((path, contents.as_ref())),
+			(DiffEntry::Directory, None) => changes.push(NodeChange::Removed {
+				path: path.clone(),
+				is_directory: true,
+			}),
+			(DiffEntry::Symlink(_), None) => changes.push(NodeChange::Removed {
+				path: path.clone(),
+				is_directory: false,
+			}),
+		}
+	}
+
+	for (path, new_entry) in &new_entries {
+		if old_entries.contains_key(path) {
+			continue;
+		}
+		match new_entry {
+			DiffEntry::File(contents) => added_files.push((path, contents.as_ref())),
+			DiffEntry::Directory => changes.push(NodeChange::Added {
+				path: path.clone(),
+				is_directory: true,
+			}),
+			DiffEntry::Symlink(_) => changes.push(NodeChange::Added {
+				path: path.clone(),
+				is_directory: false,
+			}),
+		}
+	}
+
+	// Score every removed/added pair and take the most similar ones first
+	let mut candidates = Vec::new();
+	for (i, (_, old)) in removed_files.iter().enumerate() {
+		for (j, (_, new)) in added_files.iter().enumerate() {
+			let similarity = TextDiff::from_lines(*old, *new).ratio();
+			if similarity >= RENAME_THRESHOLD {
+				candidates.push((similarity, i, j));
+			}
+		}
+	}
+	candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+	let mut renamed_from = BTreeSet::new();
+	let mut renamed_to = BTreeSet::new();
+	for (similarity, i, j) in candidates {
+		if renamed_from.contains(&i) || renamed_to.contains(&j) {
+			continue;
+		}
+		renamed_from.insert(i);
+		renamed_to.insert(j);
+
+		let (from, old) = removed_files[i];
+		let (to, new) = added_files[j];
+		changes.push(NodeChange::Renamed {
+			from: from.clone(),
+			to: to.clone(),
+			similarity,
+			old: old.to_string(),
+			new: new.to_string(),
+		});
+	}
+
+	for (i, (path, _)) in removed_files.iter().enumerate() {
+		if !renamed_from.contains(&i) {
+			changes.push(NodeChange::Removed {
+				path: (*path).clone(),
+				is_directory: false,
+			});
+		}
+	}
+	for (j, (path, _)) in added_files.iter().enumerate() {
+		if !renamed_to.contains(&j) {
+			changes.push(NodeChange::Added {
+				path: (*path).clone(),
+				is_directory: false,
+			});
+		}
+	}
+
+	changes.sort_by(|a, b| a.path().cmp(b.path()));
+	changes
+}
@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use crate::error::{Error, Result};
+
+// Comment openers an elision marker can start with
+const COMMENT_TOKENS: &[&str] = &["//", "#", "/*", "<!--", "--", ";"];
+
+// Phrases that mark a comment with an ellipsis as standing in for real code.
+// Generic words like "more" or "other" are left out, as ordinary comments
+// such as `# wait for more...` end in an ellipsis too.
+const ELISION_PHRASES: &[&str] = &["existing", "unchanged", "rest of", "remains the same"];
+
+/// Returns true if the line is a placeholder such as
+/// `// ... rest of the code unchanged ...` or `# ... existing imports ...`
+pub fn is_elision_marker(line: &str) -> bool {
+	let trimmed = line.trim();
+	let Some(text) = COMMENT_TOKENS
+		.iter()
+		.find_map(|token| trimmed.strip_prefix(token))
+	else {
+		return false;
+	};
+
+	let text = text
+		.trim_end_matches("*/")
+		.trim_end_matches("-->")
+		.trim()
+		.to_lowercase();
+	// The ellipsis has to open or close the comment
+	let starts_with_ellipsis = text.starts_with("...") || text.starts_with('…');
+	let ends_with_ellipsis = text.ends_with("...") || text.ends_with('…');
+	if !starts_with_ellipsis && !ends_with_ellipsis {
+		return false;
+	}
+
+	// A comment that is only an ellipsis, or one next to an elision phrase
+	let words = text.trim_matches(|c: char| c == '.' || c == '…' || c.is_whitespace());
+	words.is_empty() || ELISION_PHRASES.iter().any(|phrase| words.contains(phrase))
+}
+
+/// Returns true if any line of the contents is an elision marker
+pub fn has_elisions(contents: &str) -> bool {
+	contents.lines().any(is_elision_marker)
+}
+
+// Returns the index of the first line at or after start matching the line
+fn find_line(lines: &[&str], start: usize, line: &str) -> Option<usize> {
+	let line = line.trim();
+	(start..lines.len()).find(|&i| lines[i].trim() == line)
+}
+
+/// Splices a fragment containing elision markers into the existing file
+///
+/// Each marker stands for the run of existing lines between the last line
+/// before it and the first line after it, both of which must appear in the
+/// existing file in order. Returns `None` when a marker cannot be anchored.
+pub fn splice(fragment: &str, existing: &str) -> Option<String> {
+	let existing_lines: Vec<&str> = existing.lines().collect();
+
+	// Split the fragment into the segments between markers
+	let mut segments: Vec<Vec<&str>> = vec![Vec::new()];
+	for line in fragment.lines() {
+		if is_elision_marker(line) {
+			segments.push(Vec::new());
+		}
+		else {
+			segments.last_mut().unwrap().push(line);
+		}
+	}
+
+	// Blank lines next to a marker belong to the elided run
+	for segment in &mut segments {
+		while segment.last().is_some_and(|line| line.trim().is_empty()) {
+			segment.pop();
+		}
+		while segment.first().is_some_and(|line| line.trim().is_empty()) {
+			segment.remove(0);
+		}
+	}
+
+	let mut result: Vec<&str> = Vec::new();
+	let mut cursor = 0;
+
+	for (i, segment) in segments.iter().enumerate() {
+		if i > 0 {
+			// The elided run ends right before this segment's first line
+			let end = match segment.first() {
+				Some(first) => find_line(&existing_lines, cursor, first)?,
+				None => existing_lines.len(),
+			};
+			result.extend_from_slice(&existing_lines[cursor..end]);
+			cursor = end;
+		}
+
+		result.extend_from_slice(segment);
+
+		if i + 1 < segments.len() {
+			// The next elided run starts right after this segment's last line
+			cursor = match segment.last() {
+				Some(last) => find_line(&existing_lines, cursor, last)? + 1,
+				None => cursor,
+			};
+		}
+	}
+
+	let mut spliced = result.join("\n");
+	if existing.ends_with('\n') {
+		spliced.push('\n');
+	}
+	Some(spliced)
+}
+
+// Splices a fragment into the existing file a target read back, failing with
+// `Error::PartialFile` for the path when there is none or it does not anchor
+pub(crate) fn merge(fragment: &str, existing: Option<&[u8]>, path: &Path) -> Result<String> {
+	existing
+		.and_then(|existing| std::str::from_utf8(existing).ok())
+		.and_then(|existing| splice(fragment, existing))
+		.ok_or_else(|| Error::PartialFile(path.display().to_string()))
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+// Width encoded sections are wrapped at, as in MIME
+const LINE_WIDTH: usize = 76;
+
+/// How a body section marked like `// assets/logo.png (base64)` encodes
+/// the bytes of its file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+	/// Standard base64, padded
+	Base64,
+	/// Two hexadecimal digits per byte
+	Hex,
+}
+
+impl Encoding {
+	/// Returns the name the encoding is marked with
+	pub fn name(&self) -> &'static str {
+		match self {
+			Encoding::Base64 => "base64",
+			Encoding::Hex => "hex",
+		}
+	}
+
+	/// Returns the bytes encoded and wrapped into lines, each ending with a
+	/// newline
+	pub fn encode(&self, bytes: &[u8]) -> String {
+		let encoded = match self {
+			Encoding::Base64 => STANDARD.encode(bytes),
+			Encoding::Hex => bytes.iter().map(|byte| format!("{:02x}", byte)).collect(),
+		};
+		let mut wrapped = String::with_capacity(encoded.len() + encoded.len() / LINE_WIDTH + 1);
+		for line in encoded.as_bytes().chunks(LINE_WIDTH) {
+			// Both encodings are ASCII, so every chunk is whole characters
+			wrapped.push_str(std::str::from_utf8(line).expect("encoded text is ASCII"));
+			wrapped.push('\n');
+		}
+		wrapped
+	}
+
+	/// Decodes the text of a section, ignoring the whitespace it is wrapped
+	/// with
+	pub fn decode(&self, text: &str) -> Result<Vec<u8>, String> {
+		let text: String = text.split_whitespace().collect();
+		match self {
+			Encoding::Base64 => STANDARD
+				.decode(&text)
+				.map_err(|err| format!("invalid base64: {}", err)),
+			Encoding::Hex => {
+				if !text.len().is_multiple_of(2) {
+					return Err("invalid hex: odd number of digits".to_string());
+				}
+				(0..text.len())
+					.step_by(2)
+					.map(|i| {
+						text.get(i..i + 2)
+							.and_then(|digits| u8::from_str_radix(digits, 16).ok())
+							.ok_or_else(|| format!("invalid hex digits at offset {}", i))
+					})
+					.collect()
+			}
+		}
+	}
+}
+
+impl fmt::Display for Encoding {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+// Splits the encoding a section marker names off its path, like
+// `assets/logo.png (base64)`
+pub(crate) fn strip_encoding(path: &str) -> (&str, Option<Encoding>) {
+	for encoding in [Encoding::Base64, Encoding::Hex] {
+		if let Some(path) = path
+			.strip_suffix(')')
+			.and_then(|path| path.strip_suffix(encoding.name()))
+			.and_then(|path| path.strip_suffix('('))
+		{
+			return (path.trim_end(), Some(encoding));
+		}
+	}
+	(path, None)
+}
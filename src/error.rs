@@ -1,4 +1,4 @@
-use std::io;
+use std::{fmt, io};
 use thiserror::Error;
 
 /// Error types for the claudeai-extract library
@@ -8,9 +8,9 @@ pub enum Error {
 	#[error("I/O error: {0}")]
 	Io(#[from] io::Error),
 
-	/// Failed to parse tree structure
-	#[error("Parse error: {0}")]
-	Parse(String),
+	/// Failed to parse the input at a specific line
+	#[error("{0}")]
+	Syntax(ParseError),
 
 	/// Error during node path resolution
 	#[error("Path resolution error: {0}")]
@@ -20,11 +20,100 @@ pub enum Error {
 	#[error("Node not found: {0}")]
 	NodeNotFound(String),
 
-	/// Error when attempting to set contents on a non-file node
-	#[error("Cannot set contents on a non-file node")]
+	/// A node already exists at the path being created
+	#[error("Node already exists: {0}")]
+	AlreadyExists(String),
+
+	/// The directory a node is being created in does not exist
+	#[error("Parent directory not found: {0}")]
+	MissingParent(String),
+
+	/// Error when attempting a file operation, like setting contents or
+	/// removing a deleted file, on a node that is not a file
+	#[error("Not a file node")]
 	InvalidNodeType,
+
+	/// The extraction journal is missing or malformed
+	#[error("Journal error: {0}")]
+	Journal(String),
+
+	/// Files changed after extraction, so undo would lose those changes
+	#[error("Files modified since extraction: {0}")]
+	ModifiedSinceExtraction(String),
+
+	/// File contents elide code that could not be merged with the file on disk
+	#[error("Partial file with elided code could not be merged: {0}")]
+	PartialFile(String),
+
+	/// A content filter name or argument was not recognized
+	#[error("Filter error: {0}")]
+	Filter(String),
+
+	/// A path or symlink target would reach outside the output directory
+	#[error("Path escapes the output directory: {0}")]
+	UnsafePath(String),
+
+	/// An archive could not be written, or its format or timestamp was not
+	/// recognized
+	#[error("Archive error: {0}")]
+	Archive(String),
+}
+
+/// A problem at a specific place in the input
+///
+/// Displays like a compiler diagnostic, with the offending line and a caret
+/// under the column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+	pub message: String,
+	/// 1-based line number in the input
+	pub line: usize,
+	/// 1-based column, counted in characters
+	pub column: usize,
+	/// The text of the offending line
+	pub snippet: String,
+}
+
+impl ParseError {
+	/// Creates an error pointing at a column of the given source line
+	pub fn new(message: impl Into<String>, snippet: &str, line: usize, column: usize) -> Self {
+		ParseError {
+			message: message.into(),
+			line,
+			column,
+			snippet: snippet.to_string(),
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		writeln!(f, "Parse error: {}", self.message)?;
+		write_snippet(f, self.line, self.column, &self.snippet)
+	}
+}
+
+// Writes the location, the source line and a caret under the column
+pub(crate) fn write_snippet(
+	f: &mut fmt::Formatter<'_>,
+	line: usize,
+	column: usize,
+	snippet: &str,
+) -> fmt::Result {
+	let gutter = " ".repeat(line.to_string().len());
+
+	// Keep tabs before the caret so it lines up under the same character
+	let padding: String = snippet
+		.chars()
+		.take(column.saturating_sub(1))
+		.map(|c| if c == '\t' { '\t' } else { ' ' })
+		.collect();
+
+	writeln!(f, "{}--> line {}, column {}", gutter, line, column)?;
+	writeln!(f, "{} |", gutter)?;
+	writeln!(f, "{} | {}", line, snippet)?;
+	write!(f, "{} | {}^", gutter, padding)
 }
 
 /// Result type shorthand for Error
 pub type Result<T> = std::result::Result<T, Error>;
-
@@ -1,21 +1,355 @@
 use std::{
-	cell::RefCell,
+	borrow::Cow,
 	collections::HashMap,
 	fmt,
-	rc::{Rc, Weak},
+	path::{Path, PathBuf},
 };
 
 use crate::{
+	commands::{self, ShellCommand},
+	diagnostics::{Diagnostic, DiagnosticKind, column_of, column_of_name},
+	elision,
+	encoding::{self, Encoding},
 	error::{Error, Result},
-	fsnode::{FsNode, NodeRef},
+	filter::FilterChain,
+	fsnode::{self, FsNode, Node, NodeId, Span},
+	line_ending::{self, BOM, LineEnding},
+	normalize,
+	parallel::{self, ParallelOptions, WriteFailure},
+	prose::{self, DroppedText},
+	safety,
+	target::{DiskTarget, ExtractTarget},
 };
 
 /// Represents a filesystem
-#[derive(Debug)]
-pub struct FileSystem {
-	root: NodeRef,
-	nodes: Vec<NodeRef>,
-	path_map: HashMap<String, NodeRef>,
+///
+/// Nodes live in an arena and refer to each other by `NodeId`, so the tree
+/// is `Send + Sync` and can be shared between threads once parsed.
+#[derive(Debug, Clone)]
+pub struct FileSystem<'a> {
+	root: NodeId,
+	nodes: Vec<Option<FsNode<'a>>>,
+	// Where each node came from in the parsed input, indexed like nodes
+	spans: Vec<Span>,
+	path_map: HashMap<String, NodeId>,
+	deletions: Vec<String>,
+	// Prose and fences in the body that belong to no file
+	dropped: Vec<DroppedText>,
+	// Why files looked cut off before filters changed them, such as a final
+	// newline added to a section that ended mid-line
+	truncations: HashMap<NodeId, String>,
+}
+
+/// How a bundle is parsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+	/// Remove line-number gutters, blockquote markers and shared indentation
+	/// from every line of a file, reporting each removal as a warning
+	pub normalize: bool,
+}
+
+impl Default for ParseOptions {
+	fn default() -> Self {
+		ParseOptions { normalize: true }
+	}
+}
+
+// What a path is already taken by when overlaying
+#[derive(Clone, Copy)]
+enum ExistingKind {
+	File,
+	Directory,
+	Symlink,
+}
+
+// Returns the line with the comment prefix stripped, if it has one
+fn strip_prefix<'l>(line: &'l str, comment_prefix: Option<&str>) -> &'l str {
+	comment_prefix
+		.and_then(|prefix| line.strip_prefix(prefix))
+		.unwrap_or(line)
+}
+
+// Suffix of a section marker whose file does not end with a newline, like
+// `// notes.txt (no final newline)`
+pub(crate) const NO_FINAL_NEWLINE: &str = "(no final newline)";
+
+// Splits a `(no final newline)` suffix off a section marker's path
+fn strip_unterminated(path: &str) -> (&str, bool) {
+	match path.strip_suffix(NO_FINAL_NEWLINE) {
+		Some(path) => (path.trim_end(), true),
+		None => (path, false),
+	}
+}
+
+// Where a body line falls relative to the section being read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LinePosition {
+	// No file's section is open
+	Outside,
+	// In a file's section, just after a blank line
+	AfterBlank,
+	// In a file's section, right after the marker or a line of contents
+	Within,
+}
+
+// What a header entry declares once its metadata is taken off
+struct HeaderEntry<'e> {
+	name: &'e str,
+	kind: EntryKind<'e>,
+	mode: Option<u32>,
+}
+
+enum EntryKind<'e> {
+	File,
+	Directory,
+	Symlink(&'e str),
+}
+
+// Splits `tree -p` permissions like `[-rwxr-xr-x]` and a symlink's
+// `-> target` off an entry. Directories end with '/' or have a `d`
+// permission type. Setuid, setgid and sticky bits are dropped.
+fn parse_entry(entry: &str) -> HeaderEntry<'_> {
+	let mut name = entry;
+	let mut kind = None;
+	let mut mode = None;
+
+	if let Some((metadata, rest)) = entry
+		.strip_prefix('[')
+		.and_then(|entry| entry.split_once(']'))
+		&& let Some(permissions) = metadata.split_whitespace().next()
+		&& let Some((file_type, bits)) = parse_permissions(permissions)
+	{
+		name = rest.trim_start();
+		mode = Some(bits & fsnode::UNTRUSTED_MODE_MASK);
+		kind = match file_type {
+			'd' => Some(EntryKind::Directory),
+			_ => None,
+		};
+	}
+
+	if let Some((link, target)) = name.split_once(" -> ") {
+		return HeaderEntry {
+			name: link.trim_end(),
+			kind: EntryKind::Symlink(target.trim()),
+			mode: None,
+		};
+	}
+	let kind = kind.unwrap_or(if name.ends_with('/') {
+		EntryKind::Directory
+	}
+	else {
+		EntryKind::File
+	});
+	HeaderEntry {
+		name: name.trim_end_matches('/'),
+		kind,
+		mode,
+	}
+}
+
+// Reads an `ls -l` style permission string like `-rwxr-xr-x` into its type
+// character and mode bits
+fn parse_permissions(permissions: &str) -> Option<(char, u32)> {
+	let chars: Vec<char> = permissions.chars().collect();
+	if chars.len() != 10 || !"-dl".contains(chars[0]) {
+		return None;
+	}
+
+	let mut mode = 0;
+	for (i, &c) in chars[1..].iter().enumerate() {
+		let bit = 1 << (8 - i);
+		let (allowed, set) = match i % 3 {
+			0 => ("r-", c == 'r'),
+			1 => ("w-", c == 'w'),
+			// The execute column also carries setuid, setgid and sticky
+			_ => {
+				if "sStT".contains(c) {
+					mode |= [0o4000, 0o2000, 0o1000][i / 3];
+				}
+				("xsStT-", "xst".contains(c))
+			}
+		};
+		if !allowed.contains(c) {
+			return None;
+		}
+		if set {
+			mode |= bit;
+		}
+	}
+	Some((chars[0], mode))
+}
+
+// Writes mode bits as an `ls -l` style permission string after the type
+// character, the reverse of parse_permissions
+fn permission_string(file_type: char, mode: u32) -> String {
+	let mut permissions = String::from(file_type);
+	for (i, c) in "rwxrwxrwx".chars().enumerate() {
+		let set = mode & (1 << (8 - i)) != 0;
+		if i % 3 == 2 && mode & [0o4000, 0o2000, 0o1000][i / 3] != 0 {
+			let special = if i == 8 { 't' } else { 's' };
+			permissions.push(if set {
+				special
+			}
+			else {
+				special.to_ascii_uppercase()
+			});
+		}
+		else {
+			permissions.push(if set { c } else { '-' });
+		}
+	}
+	permissions
+}
+
+// Returns the mode given for a file or directory when it differs from the
+// one it would get without it
+fn unexpected_mode(node: &FsNode<'_>) -> Option<u32> {
+	let (mode, expected) = match node {
+		FsNode::File {
+			mode: Some(mode),
+			contents,
+			..
+		} => {
+			let executable = contents
+				.as_deref()
+				.is_some_and(|contents| contents.starts_with(b"#!"));
+			(
+				*mode,
+				if executable {
+					fsnode::DEFAULT_EXECUTABLE_MODE
+				}
+				else {
+					fsnode::DEFAULT_FILE_MODE
+				},
+			)
+		}
+		FsNode::Directory {
+			mode: Some(mode), ..
+		} => (*mode, fsnode::DEFAULT_EXECUTABLE_MODE),
+		_ => return None,
+	};
+	(mode != expected).then_some(mode)
+}
+
+// Adds an entry declared on a header line, with a trailing '/' for a
+// directory, and returns the directory to nest later entries in if it is
+// one. An entry repeating an existing path is reported and the existing node
+// kept.
+fn add_header_entry(
+	fs: &mut FileSystem<'_>,
+	parent: NodeId,
+	entry: &str,
+	line: &str,
+	line_number: usize,
+	diagnostics: &mut Vec<Diagnostic>,
+) -> Option<NodeId> {
+	let HeaderEntry { name, kind, mode } = parse_entry(entry);
+	let is_dir = matches!(kind, EntryKind::Directory);
+
+	if let Some(existing) = fs.child(parent, name) {
+		diagnostics.push(Diagnostic::warning(
+			DiagnosticKind::DuplicatePath,
+			format!(
+				"`{}` is declared more than once",
+				fs.relative_location(existing).display()
+			),
+			line,
+			line_number,
+			column_of(line, entry),
+		));
+		// Later entries nest in the directory that is already there
+		return (is_dir && fs.entry(existing).is_directory()).then_some(existing);
+	}
+
+	let name = name.to_string();
+	let id = match kind {
+		EntryKind::Directory => fs.insert(FsNode::Directory {
+			name,
+			parent: Some(parent),
+			children: Vec::new(),
+			mode,
+		}),
+		EntryKind::File => fs.insert(FsNode::File {
+			name,
+			parent,
+			contents: None,
+			mode,
+			encoding: None,
+			unterminated: false,
+		}),
+		EntryKind::Symlink(target) => fs.insert(FsNode::Symlink {
+			name,
+			parent,
+			target: target.to_string(),
+		}),
+	};
+	fs.spans[id.0].header_line = Some(line_number);
+	is_dir.then_some(id)
+}
+
+// Returns true for a header entry that declares a directory
+fn is_directory_entry(entry: &str) -> bool {
+	matches!(parse_entry(entry).kind, EntryKind::Directory)
+}
+
+// Reports a body section that could not be set on its node as an error on
+// the section's marker line, so the rest of the body is still parsed
+fn section_result(
+	result: Result<bool>,
+	marker: &str,
+	line_number: usize,
+	diagnostics: &mut Vec<Diagnostic>,
+) {
+	if let Err(err) = result {
+		diagnostics.push(Diagnostic::error(
+			DiagnosticKind::InvalidSection,
+			err.to_string(),
+			marker,
+			line_number,
+			1,
+		));
+	}
+}
+
+// Checks the depth of a header entry against the entry before it, which is
+// given with its depth and whether it is a file
+fn check_depth(
+	entry: &str,
+	depth: usize,
+	previous: Option<(&str, usize, bool)>,
+	first_depth: usize,
+	line: &str,
+	line_number: usize,
+	diagnostics: &mut Vec<Diagnostic>,
+) {
+	let column = column_of(line, entry);
+	match previous {
+		Some((file, previous_depth, true)) if depth > previous_depth => {
+			diagnostics.push(Diagnostic::warning(
+				DiagnosticKind::NestedUnderFile,
+				format!("`{}` is indented under the file `{}`", entry, file),
+				line,
+				line_number,
+				column,
+			));
+		}
+		_ => {
+			let max_depth = previous.map_or(first_depth, |(_, depth, _)| depth + 1);
+			if depth > max_depth {
+				diagnostics.push(Diagnostic::warning(
+					DiagnosticKind::AmbiguousIndentation,
+					format!(
+						"`{}` is indented {} levels deeper than the entry before it",
+						entry,
+						depth + 1 - max_depth
+					),
+					line,
+					line_number,
+					column,
+				));
+			}
+		}
+	}
 }
 
 // Helper function to parse tree command format like:
@@ -24,11 +358,16 @@ pub struct FileSystem {
 // └── dir2/
 //     ├── file2
 //     └── file3
-fn parse_tree_format<F>(fs: &mut FileSystem, header: &str, strip_prefix: F, debug: bool)
-where
-	F: Fn(&str) -> String,
-{
-	let mut dir_stack = Vec::new();
+fn parse_tree_format(
+	fs: &mut FileSystem<'_>,
+	header: &str,
+	comment_prefix: Option<&str>,
+	diagnostics: &mut Vec<Diagnostic>,
+	debug: bool,
+) {
+	let mut dir_stack: Vec<NodeId> = Vec::new();
+	// The previous entry, its depth and whether it is a file
+	let mut previous: Option<(&str, usize, bool)> = None;
 
 	if debug {
 		println!(
@@ -38,14 +377,16 @@ where
 	}
 
 	// Parse the header structure line by line for tree command format
-	for line in header.lines() {
+	for (i, raw_line) in header.lines().enumerate() {
+		let line_number = i + 1;
+
 		// Skip empty lines in the header
-		if line.is_empty() {
+		if raw_line.is_empty() {
 			continue;
 		}
 
 		// Strip the prefix if needed
-		let line = strip_prefix(line);
+		let line = strip_prefix(raw_line, comment_prefix);
 
 		// Skip empty lines (which could happen after stripping the prefix)
 		if line.trim().is_empty() {
@@ -63,14 +404,15 @@ where
 		// Skip the first line if it's just a directory name with no indentation
 		if !line.contains('├') && !line.contains('└') && !line.contains('│') && dir_stack.is_empty()
 		{
-			// Create root directory with this name
+			// Name the root directory after this line
 			let name = line.trim_end_matches('/').to_string();
-
-			*fs.root.borrow_mut() = FsNode::Directory {
-				name: name.clone(),
-				parent: Weak::new(),
-				children: Vec::new(),
-			};
+			if let FsNode::Directory {
+				name: root_name, ..
+			} = fs.entry_mut(fs.root)
+			{
+				*root_name = name;
+			}
+			fs.spans[fs.root.0].header_line = Some(line_number);
 
 			continue;
 		}
@@ -83,92 +425,51 @@ where
 
 		let depth = indent_count / 4 + (if indent_count % 4 > 0 { 1 } else { 0 });
 
-		// Adjust the directory stack based on the depth
-		while dir_stack.len() >= depth {
-			dir_stack.pop();
-		}
-		let current_dir = dir_stack.last().unwrap_or(&fs.root).clone();
-
 		// Extract the name by removing indentation characters
-		let name = line.trim_start_matches([' ', '│', '├', '└', '─']).to_string();
-
-		// Determine if it's a directory or file
-		let is_dir = name.ends_with('/');
-		let name = name.trim_end_matches('/').to_string();
-
-		if is_dir {
-			// Create a new directory
-			let new_dir = Rc::new(RefCell::new(FsNode::Directory {
-				name: name.clone(),
-				parent: Rc::downgrade(&current_dir),
-				children: Vec::new(),
-			}));
-
-			// Add to the current directory's children - do it in a separate scope to avoid
-			// multiple borrows
-			{
-				let mut current_borrowed = current_dir.borrow_mut();
-				match &mut *current_borrowed {
-					FsNode::Directory { children, .. } => {
-						children.push(new_dir.clone());
-					}
-					FsNode::Root => {
-						// Convert root to a directory if needed
-						*current_borrowed = FsNode::Directory {
-							name: String::new(),
-							parent: Weak::new(),
-							children: vec![new_dir.clone()],
-						};
-					}
-					_ => {}
-				}
-			}
-
-			// Update directory stack
-			dir_stack.push(new_dir.clone());
-
-			// Add to nodes list
-			fs.nodes.push(new_dir.clone());
+		let entry = line.trim_start_matches([' ', '│', '├', '└', '─']);
 
-			// Add to path map
-			let path = new_dir.borrow().relative_location().to_string_lossy().to_string();
-			fs.path_map.insert(path, new_dir.clone());
-		}
-		else {
-			// Create a new file
-			let new_file = Rc::new(RefCell::new(FsNode::File {
-				name,
-				parent: Rc::downgrade(&current_dir),
-				contents: None,
-			}));
-
-			// Add to the current directory's children - do it in a separate scope to avoid
-			// multiple borrows
-			{
-				let mut current_borrowed = current_dir.borrow_mut();
-				match &mut *current_borrowed {
-					FsNode::Directory { children, .. } => {
-						children.push(new_file.clone());
-					}
-					FsNode::Root => {
-						// Convert root to a directory if needed
-						*current_borrowed = FsNode::Directory {
-							name: String::new(),
-							parent: Weak::new(),
-							children: vec![new_file.clone()],
-						};
-					}
-					_ => {}
-				}
+		// Tree lines are drawn four characters to a level
+		if depth == 0 || indent_count % 4 != 0 {
+			let message = if depth == 0 {
+				format!("`{}` has no tree indentation", entry)
 			}
+			else {
+				format!(
+					"`{}` is indented {} characters, which is not a whole level",
+					entry, indent_count
+				)
+			};
+			diagnostics.push(Diagnostic::warning(
+				DiagnosticKind::AmbiguousIndentation,
+				message,
+				raw_line,
+				line_number,
+				column_of(raw_line, entry),
+			));
+		}
+		check_depth(
+			entry,
+			depth,
+			previous,
+			1,
+			raw_line,
+			line_number,
+			diagnostics,
+		);
 
-			// Add to nodes list
-			fs.nodes.push(new_file.clone());
+		// Adjust the directory stack based on the depth
+		while !dir_stack.is_empty() && dir_stack.len() >= depth {
+			dir_stack.pop();
+		}
+		let current_dir = dir_stack.last().copied().unwrap_or(fs.root);
 
-			// Add to path map
-			let path = new_file.borrow().relative_location().to_string_lossy().to_string();
-			fs.path_map.insert(path, new_file.clone());
+		// Create the entry, and make it the current directory if it is one
+		if let Some(dir) =
+			add_header_entry(fs, current_dir, entry, raw_line, line_number, diagnostics)
+		{
+			dir_stack.push(dir);
 		}
+		previous = Some((entry, depth, !is_directory_entry(entry)));
 	}
 }
 
@@ -176,11 +477,16 @@ where
 // - File1
 // - Dir/
 //   - SubFile1
-fn parse_indented_list<F>(fs: &mut FileSystem, header: &str, strip_prefix: F, debug: bool)
-where
-	F: Fn(&str) -> String,
-{
-	let mut dir_stack: Vec<(NodeRef, usize)> = Vec::new(); // (node, depth)
+fn parse_indented_list(
+	fs: &mut FileSystem<'_>,
+	header: &str,
+	comment_prefix: Option<&str>,
+	diagnostics: &mut Vec<Diagnostic>,
+	debug: bool,
+) {
+	let mut dir_stack: Vec<(NodeId, usize)> = Vec::new(); // (node, depth)
+	// The previous entry, its depth and whether it is a file
+	let mut previous: Option<(&str, usize, bool)> = None;
 
 	if debug {
 		println!(
@@ -190,9 +496,11 @@ where
 	}
 
 	// Process each line in the header
-	for line in header.lines() {
+	for (i, raw_line) in header.lines().enumerate() {
+		let line_number = i + 1;
+
 		// Strip the prefix if needed
-		let line = strip_prefix(line);
+		let line = strip_prefix(raw_line, comment_prefix);
 
 		// Skip empty lines (which could happen after stripping the prefix)
 		if line.trim().is_empty() {
@@ -217,7 +525,30 @@ where
 		let depth = indent_count / 2;
 
 		// Extract the file/directory name, removing the "- " prefix
-		let item_name = line.trim_start().trim_start_matches("- ").to_string();
+		let entry = line.trim_start().trim_start_matches("- ");
+
+		// List items are indented two spaces to a level
+		if indent_count % 2 != 0 {
+			diagnostics.push(Diagnostic::warning(
+				DiagnosticKind::AmbiguousIndentation,
+				format!(
+					"`{}` is indented {} spaces, which is not a whole level",
+					entry, indent_count
+				),
+				raw_line,
+				line_number,
+				column_of(raw_line, entry),
+			));
+		}
+		check_depth(
+			entry,
+			depth,
+			previous,
+			0,
+			raw_line,
+			line_number,
+			diagnostics,
+		);
 
 		// Adjust the directory stack if we're moving back up
 		while !dir_stack.is_empty() && dir_stack.last().unwrap().1 >= depth {
@@ -225,175 +556,77 @@ where
 		}
 
 		// Get the current directory from the stack or root
-		let current_dir = if let Some((parent, _)) = dir_stack.last() {
-			parent.clone()
-		}
-		else {
-			fs.root.clone()
-		};
-
-		// Determine if it's a directory or file
-		let is_dir = item_name.ends_with('/');
-		let name = item_name.trim_end_matches('/').to_string();
-
-		if is_dir {
-			// Create a new directory and add it to the parent
-			{
-				// First, check if we need to borrow the parent to get its children
-				let mut needs_new_dir = false;
-
-				match &*current_dir.borrow() {
-					FsNode::Directory { .. } => {
-						// We'll add the child later
-						needs_new_dir = true;
-					}
-					FsNode::Root => {
-						needs_new_dir = true;
-					}
-					_ => {}
-				}
+		let current_dir = dir_stack.last().map(|&(id, _)| id).unwrap_or(fs.root);
 
-				if needs_new_dir {
-					// Create the new directory
-					let new_dir = Rc::new(RefCell::new(FsNode::Directory {
-						name: name.clone(),
-						parent: Rc::downgrade(&current_dir),
-						children: Vec::new(),
-					}));
-
-					// Add to current directory children
-					{
-						let mut current_dir_mut = current_dir.borrow_mut();
-
-						match &mut *current_dir_mut {
-							FsNode::Directory { children, .. } => {
-								children.push(new_dir.clone());
-							}
-							FsNode::Root => {
-								*current_dir_mut = FsNode::Directory {
-									name: String::new(),
-									parent: Weak::new(),
-									children: vec![new_dir.clone()],
-								};
-							}
-							_ => {}
-						}
-					}
-
-					// Update the directory stack
-					dir_stack.push((new_dir.clone(), depth));
-
-					// Add to nodes list
-					fs.nodes.push(new_dir.clone());
-
-					// Add to path map
-					// We need to be careful about borrowing. First calculate the path:
-					let mut path_parts = vec![name.clone()];
-					let mut current = current_dir.clone();
-
-					loop {
-						let borrowed = current.borrow();
-						match &*borrowed {
-							FsNode::Root => break,
-							FsNode::Directory { name, parent, .. } => {
-								if !name.is_empty() {
-									path_parts.push(name.clone());
-								}
-
-								if let Some(p) = parent.upgrade() {
-									drop(borrowed); // Release the borrow before getting another
-									current = p;
-								}
-								else {
-									break;
-								}
-							}
-							_ => break,
-						}
-					}
-
-					path_parts.reverse();
-					let path = path_parts.join("/");
-
-					fs.path_map.insert(path, new_dir.clone());
-				}
-			}
-		}
-		else {
-			// Create a new file
-			let new_file = Rc::new(RefCell::new(FsNode::File {
-				name: name.clone(),
-				parent: Rc::downgrade(&current_dir),
-				contents: None,
-			}));
-
-			// Add to parent's children
-			{
-				let mut current_dir_mut = current_dir.borrow_mut();
-
-				match &mut *current_dir_mut {
-					FsNode::Directory { children, .. } => {
-						children.push(new_file.clone());
-					}
-					FsNode::Root => {
-						*current_dir_mut = FsNode::Directory {
-							name: String::new(),
-							parent: Weak::new(),
-							children: vec![new_file.clone()],
-						};
-					}
-					_ => {}
-				}
-			}
-
-			// Add to nodes list
-			fs.nodes.push(new_file.clone());
-
-			// Add to path map - calculate path safely
-			let mut path_parts = vec![name.clone()];
-			let mut current = current_dir.clone();
-
-			loop {
-				let borrowed = current.borrow();
-				match &*borrowed {
-					FsNode::Root => break,
-					FsNode::Directory { name, parent, .. } => {
-						if !name.is_empty() {
-							path_parts.push(name.clone());
-						}
-
-						if let Some(p) = parent.upgrade() {
-							drop(borrowed); // Release the borrow before getting another
-							current = p;
-						}
-						else {
-							break;
-						}
-					}
-					_ => break,
-				}
-			}
-
-			path_parts.reverse();
-			let path = path_parts.join("/");
-
-			fs.path_map.insert(path, new_file.clone());
+		// Create the entry, and add it to the directory stack if it is one
+		if let Some(dir) =
+			add_header_entry(fs, current_dir, entry, raw_line, line_number, diagnostics)
+		{
+			dir_stack.push((dir, depth));
 		}
+		previous = Some((entry, depth, !is_directory_entry(entry)));
 	}
 }
 
-impl FileSystem {
+impl<'a> FileSystem<'a> {
 	/// Creates a new filesystem with a root node
 	pub fn new() -> Self {
-		let root = Rc::new(RefCell::new(FsNode::Root));
+		let root = FsNode::Directory {
+			name: String::new(),
+			parent: None,
+			children: Vec::new(),
+			mode: None,
+		};
 		FileSystem {
-			root: root.clone(),
-			nodes: vec![root],
+			root: NodeId(0),
+			nodes: vec![Some(root)],
+			spans: vec![Span::default()],
 			path_map: HashMap::new(),
+			deletions: Vec::new(),
+			dropped: Vec::new(),
+			truncations: HashMap::new(),
+		}
+	}
+
+	// Returns a live node; ids held internally always refer to one
+	fn entry(&self, id: NodeId) -> &FsNode<'a> {
+		self.nodes[id.0]
+			.as_ref()
+			.expect("node id refers to a removed node")
+	}
+
+	fn entry_mut(&mut self, id: NodeId) -> &mut FsNode<'a> {
+		self.nodes[id.0]
+			.as_mut()
+			.expect("node id refers to a removed node")
+	}
+
+	// Adds a node to the arena and to its parent's children, and registers
+	// its path
+	fn insert(&mut self, node: FsNode<'a>) -> NodeId {
+		let id = NodeId(self.nodes.len());
+		let parent = node.parent();
+		self.nodes.push(Some(node));
+		self.spans.push(Span::default());
+
+		if let Some(parent) = parent
+			&& let FsNode::Directory { children, .. } = self.entry_mut(parent)
+		{
+			children.push(id);
 		}
+
+		let path = self.relative_location(id).to_string_lossy().to_string();
+		self.path_map.insert(path, id);
+		id
 	}
 
-	fn parse_header(&mut self, header: &str, strip_prefix: impl Fn(&str) -> String, debug: bool) {
+	pub(crate) fn parse_header(
+		&mut self,
+		header: &str,
+		comment_prefix: Option<&str>,
+		diagnostics: &mut Vec<Diagnostic>,
+		debug: bool,
+	) {
 		// Check if we're parsing a tree format or an indented list format
 		let is_list_format = header.contains(" - ");
 
@@ -402,85 +635,258 @@ impl FileSystem {
 			if debug {
 				println!("Parsing with indented list format parser");
 			}
-			parse_indented_list(self, header, strip_prefix, debug);
+			parse_indented_list(self, header, comment_prefix, diagnostics, debug);
 		}
 		else {
 			// For tree command format with indentation characters
 			if debug {
 				println!("Parsing with tree command format parser");
 			}
-			parse_tree_format(self, header, strip_prefix, debug);
+			parse_tree_format(self, header, comment_prefix, diagnostics, debug);
 		}
 	}
 
-	fn parse_body(&mut self, parts: &[&str], comment_prefix: Option<&str>, debug: bool) -> Result<()> {
-		// Skip if there's no body
-		if parts.len() <= 1 {
-			return Ok(());
+	// Returns true if a body line starts a new section rather than being part
+	// of the one it is in. A comment naming a path in the header, or marked
+	// `(deleted)`, with an encoding or `(no final newline)`, always does. Any
+	// other comment only does outside a file's section, or after a blank line
+	// inside one when it reads like a path, so code comments stay contents.
+	pub(crate) fn is_section_marker(
+		&self,
+		line: &str,
+		comment_prefix: Option<&str>,
+		position: LinePosition,
+	) -> bool {
+		let Some(stripped) = comment_prefix.and_then(|prefix| line.strip_prefix(prefix))
+		else {
+			return false;
+		};
+		let path = stripped.trim();
+		if path.is_empty() || elision::is_elision_marker(line) {
+			return false;
 		}
+		if path.ends_with("(deleted)") {
+			return true;
+		}
+		let (path, unterminated) = strip_unterminated(path);
+		let (path, encoding) = encoding::strip_encoding(path);
+		if unterminated || encoding.is_some() || self.get_node(path).is_ok() {
+			return true;
+		}
+		match position {
+			LinePosition::Outside => true,
+			LinePosition::AfterBlank => {
+				!path.contains(char::is_whitespace) && path.contains(['.', '/'])
+			}
+			LinePosition::Within => false,
+		}
+	}
 
-		let mut current_file: Option<NodeRef> = None;
-		let mut current_contents = String::new();
-
-		// Process each line of the body
-		for (i, body_part) in parts.iter().skip(1).enumerate() {
-			let lines = body_part.lines();
+	// Checks whether a body line starts a new file's section. Returns `None`
+	// for ordinary lines, and for markers the file whose contents follow, if
+	// it is a file in the header. Deletion markers are recorded here, and
+	// markers for anything else are reported.
+	pub(crate) fn section_marker(
+		&mut self,
+		line: &str,
+		line_number: usize,
+		comment_prefix: Option<&str>,
+		position: LinePosition,
+		diagnostics: &mut Vec<Diagnostic>,
+		debug: bool,
+	) -> Option<Option<NodeId>> {
+		// Elision markers like "// ... existing code ..." and comments in
+		// the code are contents
+		if !self.is_section_marker(line, comment_prefix, position) {
+			return None;
+		}
+		let path = line.strip_prefix(comment_prefix?)?.trim();
 
-			for line in lines {
-				// If there's a comment_prefix and the line starts with it,
-				// it might be a file path (start of a new file's contents)
-				if let Some(prefix) = comment_prefix {
-					if let Some(stripped) = line.strip_prefix(prefix) {
-						// Save the previous file's contents if there was one
-						if let Some(file) = current_file {
-							if !current_contents.is_empty() {
-								let mut file_ref = file.borrow_mut();
-								file_ref.set_contents(current_contents.clone())?;
-								current_contents.clear();
-							}
-						}
+		// A "(deleted)" marker removes the file when overlaid
+		if let Some(deleted) = path.strip_suffix("(deleted)") {
+			if debug {
+				println!("Deletion marker for path: {}", deleted.trim());
+			}
+			self.deletions.push(deleted.trim().to_string());
+			return Some(None);
+		}
 
-						// Get the new file path
-						let path = stripped.trim();
-
-						// Look up the file in our filesystem
-						current_file = match self.get_node(path) {
-							Ok(node) => Some(node),
-							Err(_) => {
-								if debug {
-									println!("Warning: File not found at path: {}", path);
-								}
-								None
-							}
-						};
-						continue;
-					}
+		// Look up the file in our filesystem, without any encoding or missing
+		// newline its contents are marked with
+		let (path, unterminated) = strip_unterminated(path);
+		let (path, encoding) = encoding::strip_encoding(path);
+		let column = column_of(line, path);
+		match self
+			.get_node(path)
+			.map(|node| (node.id(), node.is_directory(), node.link_target().is_some()))
+		{
+			Ok((_, true, _)) => {
+				diagnostics.push(Diagnostic::error(
+					DiagnosticKind::DirectoryContents,
+					format!("`{}` is a directory and cannot have contents", path),
+					line,
+					line_number,
+					column,
+				));
+				Some(None)
+			}
+			Ok((_, _, true)) => {
+				diagnostics.push(Diagnostic::error(
+					DiagnosticKind::InvalidSection,
+					format!("`{}` is a symlink and cannot have contents", path),
+					line,
+					line_number,
+					column,
+				));
+				Some(None)
+			}
+			Ok((id, false, false)) => {
+				// The section's lines are filled in once it ends
+				let span = &mut self.spans[id.0];
+				if span.body_lines.is_some() {
+					diagnostics.push(Diagnostic::warning(
+						DiagnosticKind::DuplicatePath,
+						format!(
+							"`{}` has more than one body section, the last one is used",
+							path
+						),
+						line,
+						line_number,
+						column,
+					));
 				}
+				span.body_lines = Some(line_number + 1..line_number + 1);
+				self.set_encoding(id, encoding);
+				self.set_unterminated(id, unterminated);
+				Some(Some(id))
+			}
+			Err(_) => {
+				diagnostics.push(Diagnostic::warning(
+					DiagnosticKind::OrphanSection,
+					format!("`{}` has a body section but is not in the header", path),
+					line,
+					line_number,
+					column,
+				));
+				Some(None)
+			}
+		}
+	}
 
-				// If we have a current file, add this line to its contents
-				if current_file.is_some() {
-					if !current_contents.is_empty() {
-						current_contents.push('\n');
-					}
-					current_contents.push_str(line);
-				}
+	// Sets a file's contents to its section of the body, whose first line is
+	// first_line of the input
+	pub(crate) fn set_section(
+		&mut self,
+		file: NodeId,
+		contents: impl Into<Cow<'a, str>>,
+		first_line: usize,
+	) -> Result<()> {
+		let contents = contents.into();
+		let line_count = contents.lines().count();
+		self.set_contents(file, contents)?;
+		self.spans[file.0].body_lines = Some(first_line..first_line + line_count);
+		Ok(())
+	}
+
+	// Sets a file's contents from its body section, keeping any prose and
+	// fences around them apart. Returns false for a blank section, which
+	// leaves the file as it was.
+	pub(crate) fn fill_section(
+		&mut self,
+		file: NodeId,
+		section: Cow<'a, str>,
+		first_line: usize,
+		debug: bool,
+	) -> Result<bool> {
+		let split = prose::split_section(&section, self.entry(file).name());
+		for (line, range) in &split.dropped {
+			self.drop_text(&section[range.clone()], first_line + line, debug);
+		}
+
+		let Some(mut range) = split.contents
+		else {
+			return Ok(false);
+		};
+		// The newline ending the last line is only there to end the section
+		if self.entry(file).is_unterminated() {
+			let text = &section[range.clone()];
+			let trimmed = text
+				.strip_suffix('\n')
+				.map(|text| text.strip_suffix('\r').unwrap_or(text));
+			range.end -= text.len() - trimmed.unwrap_or(text).len();
+		}
+		let contents = match section {
+			Cow::Borrowed(section) => Cow::Borrowed(&section[range]),
+			Cow::Owned(mut section) => {
+				section.truncate(range.end);
+				section.drain(..range.start);
+				Cow::Owned(section)
 			}
+		};
+		self.set_section(file, contents, first_line + split.first_line)?;
+		Ok(true)
+	}
+
+	fn parse_body(
+		&mut self,
+		body: &'a str,
+		first_line: usize,
+		comment_prefix: Option<&str>,
+		diagnostics: &mut Vec<Diagnostic>,
+		debug: bool,
+	) {
+		// The current file, its marker line, and the offset and line its
+		// contents start at
+		let mut current_file: Option<(NodeId, &str, usize, usize)> = None;
+		let mut offset = 0;
+		let mut seen_marker = false;
+		let mut previous_blank = false;
+
+		// Process each line of the body, keeping track of where it starts
+		for (i, line) in body.split_inclusive('\n').enumerate() {
+			let line_number = first_line + i;
+			let line_start = offset;
+			offset += line.len();
+			let line = line.trim_end_matches(['\n', '\r']);
+
+			let position = match current_file {
+				None => LinePosition::Outside,
+				Some(_) if previous_blank => LinePosition::AfterBlank,
+				Some(_) => LinePosition::Within,
+			};
+			previous_blank = line.trim().is_empty();
+			let marker = self.section_marker(
+				line,
+				line_number,
+				comment_prefix,
+				position,
+				diagnostics,
+				debug,
+			);
+			if let Some(file) = marker {
+				// Anything before the first section belongs to no file
+				if !seen_marker {
+					self.drop_text(&body[..line_start], first_line, debug);
+					seen_marker = true;
+				}
 
-			// If this isn't the last body part, add a blank line between parts
-			if i < parts.len() - 2 && !current_contents.is_empty() {
-				current_contents.push_str("\n\n");
+				// Save the previous file's contents if there was one
+				if let Some((file, marker, start, start_line)) = current_file.take() {
+					let section = Cow::Borrowed(&body[start..line_start]);
+					let result = self.fill_section(file, section, start_line, debug);
+					section_result(result, marker, start_line - 1, diagnostics);
+				}
+				let marker = &body[line_start..offset];
+				let marker = marker.trim_end_matches(['\n', '\r']);
+				current_file = file.map(|file| (file, marker, offset, line_number + 1));
 			}
 		}
 
 		// Save the last file's contents if there is one
-		if let Some(file) = current_file {
-			if !current_contents.is_empty() {
-				let mut file_ref = file.borrow_mut();
-				file_ref.set_contents(current_contents)?;
-			}
+		if let Some((file, marker, start, start_line)) = current_file {
+			let result = self.fill_section(file, Cow::Borrowed(&body[start..]), start_line, debug);
+			section_result(result, marker, start_line - 1, diagnostics);
 		}
-
-		Ok(())
 	}
 
 	/// Parse the output of the tree command including file contents
@@ -489,7 +895,9 @@ impl FileSystem {
 	/// everything up to that point as the header. After the header, it parses
 	/// file contents where lines starting with the comment prefix (if provided)
 	/// and followed by a path indicate the file path, and subsequent lines are
-	/// the file contents.
+	/// the file contents. Markdown fences, explanatory paragraphs and lead-ins
+	/// like "Here's the updated..." around a file's contents are left out of
+	/// it and kept in `dropped_text`.
 	///
 	/// If comment_prefix is provided, it will strip that prefix from all lines
 	/// in the header.
@@ -499,8 +907,43 @@ impl FileSystem {
 	///
 	/// If debug is true, additional information about the parsing process will
 	/// be printed.
-	pub fn parse(input: &str, comment_prefix: Option<&str>, debug: bool) -> Result<Self> {
+	///
+	/// File contents borrow from the input rather than copying it; use
+	/// `into_owned` for a filesystem that outlives the input. Returns the
+	/// first error `parse_with_diagnostics` finds; warnings are ignored.
+	pub fn parse(input: &'a str, comment_prefix: Option<&str>, debug: bool) -> Result<Self> {
+		let (fs, diagnostics) = Self::parse_with_diagnostics(input, comment_prefix, debug);
+		match diagnostics.into_iter().find(Diagnostic::is_error) {
+			Some(error) => Err(Error::Syntax(error.into())),
+			None => Ok(fs),
+		}
+	}
+
+	/// Parses like `parse`, but always returns the best tree it could build
+	/// along with every problem found, sorted by line
+	///
+	/// Errors mark input that had to be dropped, such as a body section for a
+	/// directory. Warnings mark input that was used but may not mean what its
+	/// author intended: duplicate paths, body sections for paths missing from
+	/// the header, files with no body section, entries nested under files,
+	/// ambiguous indentation and contents that were normalized.
+	pub fn parse_with_diagnostics(
+		input: &'a str,
+		comment_prefix: Option<&str>,
+		debug: bool,
+	) -> (Self, Vec<Diagnostic>) {
+		Self::parse_with_options(input, comment_prefix, debug, ParseOptions::default())
+	}
+
+	/// Parses like `parse_with_diagnostics` with the given options
+	pub fn parse_with_options(
+		input: &'a str,
+		comment_prefix: Option<&str>,
+		debug: bool,
+		options: ParseOptions,
+	) -> (Self, Vec<Diagnostic>) {
 		let mut fs = FileSystem::new();
+		let mut diagnostics = Vec::new();
 
 		if debug {
 			println!("Debug mode enabled");
@@ -513,194 +956,909 @@ impl FileSystem {
 			}
 		}
 
-		// Split the input into header and body parts
-		let parts: Vec<&str> = input.split("\n\n").collect();
-		let header = parts.first().ok_or_else(|| Error::Parse("Input is empty".to_string()))?;
+		// Split the input into the header and the body at the first blank line,
+		// which leaves the body starting two lines after the header's last
+		let input = input.strip_prefix(BOM).unwrap_or(input);
+		let (header, body) = line_ending::split_header(input);
+		let body_line = header.matches('\n').count() + 3;
 
-		// Helper function to strip comment prefix if provided
-		let strip_prefix = |line: &str| -> String {
-			if let Some(prefix) = comment_prefix {
-				if let Some(string) = line.strip_prefix(prefix) {
-					string.to_string()
+		// Parse the header to build the file structure
+		fs.parse_header(header, comment_prefix, &mut diagnostics, debug);
+
+		// Parse the body to extract file contents
+		fs.parse_body(body, body_line, comment_prefix, &mut diagnostics, debug);
+
+		// Decode encoded sections, and clean up what pasting from a code view
+		// or a quote adds to each line of the rest
+		let sections: Vec<NodeId> = fs
+			.nodes()
+			.filter(|node| node.span().body_lines.is_some())
+			.map(|node| node.id())
+			.collect();
+		for id in sections {
+			diagnostics.extend(fs.decode_file(id));
+			if options.normalize {
+				diagnostics.extend(fs.normalize_file(id, debug));
+			}
+		}
+
+		// Modes set by `chmod` commands between the files
+		fs.apply_chmod_commands(debug);
+
+		// Once a bundle has any body sections, every file is expected to have one
+		fs.check_missing_contents(header, &mut diagnostics);
+		diagnostics.sort_by_key(|diagnostic| diagnostic.line);
+
+		if debug {
+			println!("Parsing complete: {} nodes created", fs.len());
+			for node in fs.nodes() {
+				let span = node.span();
+				if let Some(line) = span.header_line {
+					println!(
+						"Node {} declared on line {}",
+						node.relative_location().display(),
+						line
+					);
 				}
-				else {
-					line.to_string()
+				if let Some(lines) = &span.body_lines
+					&& !lines.is_empty()
+				{
+					println!(
+						"Node {} contents from lines {}-{}",
+						node.relative_location().display(),
+						lines.start,
+						lines.end - 1
+					);
+				}
+				if node.line_ending() == Some(LineEnding::Crlf) {
+					println!(
+						"Node {} has CRLF line endings",
+						node.relative_location().display()
+					);
 				}
 			}
-			else {
-				line.to_string()
+			for diagnostic in &diagnostics {
+				println!("{}", diagnostic);
 			}
-		};
-
-		// Parse the header to build the file structure
-		fs.parse_header(header, strip_prefix, debug);
+		}
 
-		// Parse the body to extract file contents
-		fs.parse_body(&parts, comment_prefix, debug)?;
+		(fs, diagnostics)
+	}
 
-		if debug {
-			println!("Parsing complete: {} nodes created", fs.nodes().len());
+	// Reports files declared in the header that no body section filled in,
+	// provided the bundle has body sections at all
+	pub(crate) fn check_missing_contents(&self, header: &str, diagnostics: &mut Vec<Diagnostic>) {
+		if self.spans.iter().all(|span| span.body_lines.is_none()) {
+			return;
 		}
 
-		Ok(fs)
+		let lines: Vec<&str> = header.lines().collect();
+		for node in self.nodes() {
+			// A blank section is an empty file rather than a missing one
+			if node.is_directory() || node.span().body_lines.is_some() {
+				continue;
+			}
+			let Some(line_number) = node.span().header_line
+			else {
+				continue;
+			};
+
+			let line = lines[line_number - 1];
+			let column = column_of_name(line, node.name());
+			diagnostics.push(Diagnostic::warning(
+				DiagnosticKind::MissingContents,
+				format!(
+					"`{}` is in the header but has no body section",
+					node.relative_location().display()
+				),
+				line,
+				line_number,
+				column,
+			));
+		}
 	}
 
 	/// Returns the tree output representation of this filesystem
+	///
+	/// Symlinks are shown as `name -> target`.
 	pub fn tree_output(&self) -> String {
+		self.header_output(false)
+	}
+
+	// Returns the tree output, with `tree -p` permissions like `[-rwxr-xr-x]`
+	// in front of nodes whose mode is not the one they would get anyway when
+	// permissions is set
+	pub(crate) fn header_output(&self, permissions: bool) -> String {
 		let mut result = String::new();
 
 		// Get the name of the root directory
-		let root_name = self.root.borrow().name();
+		let root_name = self.entry(self.root).name();
 		if !root_name.is_empty() {
 			result.push_str(&format!("{}/\n", root_name));
 		}
 
 		// Recursively print all children
-		Self::tree_output_recursive(&self.root, &mut result, 0, &[]);
+		self.tree_output_recursive(self.root, &mut result, 0, &[], permissions);
 
 		result
 	}
 
 	fn tree_output_recursive(
-		node: &NodeRef,
+		&self,
+		id: NodeId,
 		result: &mut String,
 		depth: usize,
 		is_last: &[bool],
+		permissions: bool,
 	) {
-		let node_ref = node.borrow();
-
-		match &*node_ref {
-			FsNode::Root => {
-				// Root node only prints its children
-				if let FsNode::Directory { children, .. } = &*node_ref {
-					for (i, child) in children.iter().enumerate() {
-						let is_last_child = i == children.len() - 1;
-						let mut new_is_last = is_last.to_vec();
-						new_is_last.push(is_last_child);
-						Self::tree_output_recursive(child, result, depth, &new_is_last);
-					}
+		let node = self.entry(id);
+
+		if depth > 0 {
+			// Print the prefix
+			for &is_last_item in is_last.iter().take(depth - 1) {
+				if is_last_item {
+					result.push_str("    ");
+				}
+				else {
+					result.push_str("│   ");
 				}
 			}
-			FsNode::Directory { name, children, .. } => {
-				if depth > 0 {
-					// Print directory prefix
-					for &is_last_item in is_last.iter().take(depth - 1) {
-						if is_last_item {
-							result.push_str("    ");
-						}
-						else {
-							result.push_str("│   ");
-						}
-					}
 
-					// Print the branch character
-					if is_last[depth - 1] {
-						result.push_str("└── ");
-					}
-					else {
-						result.push_str("├── ");
-					}
-
-					result.push_str(&format!("{}/\n", name));
-				}
+			// Print the branch character
+			if is_last[depth - 1] {
+				result.push_str("└── ");
+			}
+			else {
+				result.push_str("├── ");
+			}
 
-				// Print children
-				for (i, child) in children.iter().enumerate() {
-					let is_last_child = i == children.len() - 1;
-					let mut new_is_last = is_last.to_vec();
-					new_is_last.push(is_last_child);
-					Self::tree_output_recursive(child, result, depth + 1, &new_is_last);
-				}
+			if permissions && let Some(mode) = unexpected_mode(node) {
+				let file_type = if node.is_directory() { 'd' } else { '-' };
+				result.push_str(&format!("[{}]  ", permission_string(file_type, mode)));
 			}
-			FsNode::File { name, .. } => {
-				// Print file prefix
-				for &is_last_item in is_last.iter().take(depth - 1) {
-					if is_last_item {
-						result.push_str("    ");
-					}
-					else {
-						result.push_str("│   ");
-					}
+			match node {
+				FsNode::Directory { .. } => result.push_str(&format!("{}/\n", node.name())),
+				FsNode::Symlink { target, .. } => {
+					result.push_str(&format!("{} -> {}\n", node.name(), target))
 				}
+				FsNode::File { .. } => result.push_str(&format!("{}\n", node.name())),
+			}
+		}
 
-				// Print the branch character
-				if is_last[depth - 1] {
-					result.push_str("└── ");
-				}
-				else {
-					result.push_str("├── ");
-				}
+		// Print children
+		let children = node.children();
+		for (i, &child) in children.iter().enumerate() {
+			let is_last_child = i == children.len() - 1;
+			let mut new_is_last = is_last.to_vec();
+			new_is_last.push(is_last_child);
+			self.tree_output_recursive(child, result, depth + 1, &new_is_last, permissions);
+		}
+	}
+
+	/// Returns the root directory
+	pub fn root(&self) -> Node<'_> {
+		Node::new(self, self.root, self.entry(self.root))
+	}
+
+	/// Returns the node with the id, or `None` if it was removed
+	pub fn node(&self, id: NodeId) -> Option<Node<'_>> {
+		let node = self.nodes.get(id.0)?.as_ref()?;
+		Some(Node::new(self, id, node))
+	}
+
+	/// Returns all nodes in the filesystem, root first, in the order they were
+	/// added
+	pub fn nodes(&self) -> impl Iterator<Item = Node<'_>> {
+		self.nodes
+			.iter()
+			.enumerate()
+			.filter_map(|(i, node)| Some(Node::new(self, NodeId(i), node.as_ref()?)))
+	}
 
-				result.push_str(&format!("{}\n", name));
+	/// Returns the number of nodes, including the root
+	pub fn len(&self) -> usize {
+		self.nodes.iter().filter(|node| node.is_some()).count()
+	}
+
+	/// Returns true if the filesystem holds nothing but its root
+	pub fn is_empty(&self) -> bool {
+		self.len() == 1
+	}
+
+	/// Returns the relative location of a node as a PathBuf
+	///
+	/// The root directory's name, if it has one, is the first component.
+	pub fn relative_location(&self, id: NodeId) -> PathBuf {
+		let mut parts = Vec::new();
+
+		// Walk up the parent chain, collecting names
+		let mut current = Some(id);
+		while let Some(id) = current {
+			let node = self.entry(id);
+			if !node.name().is_empty() {
+				parts.push(node.name());
 			}
+			current = node.parent();
 		}
-	}
 
-	/// Returns all nodes in the filesystem
-	pub fn nodes(&self) -> &[NodeRef] {
-		&self.nodes
+		// Reverse the parts and create a PathBuf
+		parts.iter().rev().collect::<PathBuf>()
 	}
 
 	/// Returns a node by its path or an error if not found
-	pub fn get_node(&self, path: &str) -> Result<NodeRef> {
+	pub fn get_node(&self, path: &str) -> Result<Node<'_>> {
 		// Directly look up in path map (fast path)
-		if let Some(node) = self.path_map.get(path) {
-			return Ok(node.clone());
+		if let Some(&id) = self.path_map.get(path) {
+			return Ok(Node::new(self, id, self.entry(id)));
 		}
 
 		// Split the path into components
-		let components: Vec<&str> = path.split('/').collect();
-		if components.is_empty() {
-			return Ok(self.root.clone());
-		}
+		let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
 
 		// Start search from the root
-		let mut current = self.root.clone();
+		let mut current = self.root;
 
 		// Special case: the first component might be the root directory name
 		let mut start_idx = 0;
-		let root_name = current.borrow().name();
-		if !root_name.is_empty() && components[0] == root_name {
+		let root_name = self.entry(current).name();
+		if !root_name.is_empty() && components.first() == Some(&root_name) {
 			start_idx = 1;
 		}
 
 		// Navigate through each path component
 		for &component in &components[start_idx..] {
-			let found = match &*current.borrow() {
-				FsNode::Directory { children, .. } => {
-					children.iter().find(|node| node.borrow().name() == component).cloned()
+			match self.child(current, component) {
+				Some(id) => current = id,
+				None => return Err(Error::NodeNotFound(path.to_string())), // Component not found
+			}
+		}
+
+		Ok(Node::new(self, current, self.entry(current)))
+	}
+
+	/// Sets the contents of the file with the id, either borrowed or owned
+	pub fn set_contents(&mut self, id: NodeId, contents: impl Into<Cow<'a, str>>) -> Result<()> {
+		self.truncations.remove(&id);
+		match self.nodes.get_mut(id.0).and_then(Option::as_mut) {
+			Some(node) => node.set_contents(contents),
+			None => Err(Error::NodeNotFound(format!("{:?}", id))),
+		}
+	}
+
+	/// Sets the permission bits of the file or directory with the id, or
+	/// clears them with `None`
+	pub fn set_mode(&mut self, id: NodeId, mode: Option<u32>) -> Result<()> {
+		match self.nodes.get_mut(id.0).and_then(Option::as_mut) {
+			Some(node) => node.set_mode(mode),
+			None => Err(Error::NodeNotFound(format!("{:?}", id))),
+		}
+	}
+
+	/// Sets the contents of the file with the id to bytes, either borrowed or
+	/// owned
+	pub fn set_bytes(&mut self, id: NodeId, contents: impl Into<Cow<'a, [u8]>>) -> Result<()> {
+		self.truncations.remove(&id);
+		match self.nodes.get_mut(id.0).and_then(Option::as_mut) {
+			Some(node) => node.set_bytes(contents),
+			None => Err(Error::NodeNotFound(format!("{:?}", id))),
+		}
+	}
+
+	// Replaces the text of an encoded section with the bytes it encodes.
+	// Returns an error diagnostic, and leaves the file without contents, when
+	// the text does not decode.
+	pub(crate) fn decode_file(&mut self, id: NodeId) -> Option<Diagnostic> {
+		let encoding = self.entry(id).encoding()?;
+		let line = self.spans[id.0].body_lines.as_ref()?.start;
+		let text = self.entry(id).contents().unwrap_or_default();
+		match encoding.decode(text) {
+			Ok(bytes) => {
+				self.set_bytes(id, bytes)
+					.expect("only files have encoded sections");
+				None
+			}
+			Err(reason) => {
+				let diagnostic = Diagnostic::error(
+					DiagnosticKind::InvalidEncoding,
+					format!(
+						"`{}` is marked {} but {}",
+						self.relative_location(id).display(),
+						encoding,
+						reason
+					),
+					text.lines().next().unwrap_or_default(),
+					line,
+					1,
+				);
+				self.clear_contents(id);
+				Some(diagnostic)
+			}
+		}
+	}
+
+	// Records how a file's contents were encoded in the bundle
+	pub(crate) fn set_encoding(&mut self, id: NodeId, new_encoding: Option<Encoding>) {
+		if let Some(Some(FsNode::File { encoding, .. })) = self.nodes.get_mut(id.0) {
+			*encoding = new_encoding;
+		}
+	}
+
+	// Records whether a file's section was marked `(no final newline)`
+	pub(crate) fn set_unterminated(&mut self, id: NodeId, marked: bool) {
+		if let Some(Some(FsNode::File { unterminated, .. })) = self.nodes.get_mut(id.0) {
+			*unterminated = marked;
+		}
+	}
+
+	// Drops the contents of a file, leaving it as if it had none
+	pub(crate) fn clear_contents(&mut self, id: NodeId) {
+		if let Some(Some(FsNode::File { contents, .. })) = self.nodes.get_mut(id.0) {
+			*contents = None;
+		}
+	}
+
+	// Removes line-number gutters, blockquote markers and shared indentation
+	// from a file's contents, returning a warning for each removal. Encoded
+	// sections are left exactly as decoded.
+	pub(crate) fn normalize_file(&mut self, id: NodeId, debug: bool) -> Vec<Diagnostic> {
+		if self.entry(id).encoding().is_some() {
+			return Vec::new();
+		}
+		let Some(original) = self.entry(id).contents()
+		else {
+			return Vec::new();
+		};
+		let Some((contents, applied)) = normalize::normalize(original)
+		else {
+			return Vec::new();
+		};
+		let path = self.relative_location(id);
+		let line = self.spans[id.0]
+			.body_lines
+			.as_ref()
+			.map_or(0, |lines| lines.start);
+		let snippet = original.lines().next().unwrap_or_default();
+		let diagnostics = applied
+			.iter()
+			.map(|normalization| {
+				if debug {
+					println!("Removed {} from {}", normalization, path.display());
 				}
-				_ => None,
-			};
+				Diagnostic::warning(
+					DiagnosticKind::NormalizedContents,
+					format!("removed {} from `{}`", normalization, path.display()),
+					snippet,
+					line,
+					1,
+				)
+			})
+			.collect();
+		self.set_contents(id, contents)
+			.expect("only files have contents to normalize");
+		diagnostics
+	}
 
-			match found {
-				Some(node) => current = node,
-				None => return Err(Error::NodeNotFound(path.to_string())), // Component not found
+	/// Runs the contents of every file through the filters, in order
+	pub fn apply_filters(&mut self, filters: &FilterChain) {
+		let ids: Vec<NodeId> = self.nodes().map(|node| node.id()).collect();
+		for id in ids {
+			self.filter_file(id, filters);
+		}
+	}
+
+	// Runs one file's text contents through the filters, leaving binary files
+	// and encoded sections alone
+	pub(crate) fn filter_file(&mut self, id: NodeId, filters: &FilterChain) {
+		if self.entry(id).encoding().is_some() {
+			return;
+		}
+		let Some(contents) = self.entry(id).contents()
+		else {
+			return;
+		};
+		let path = self.relative_location(id);
+		if let Cow::Owned(mut filtered) = filters.apply(&path, contents) {
+			// A file marked as having no final newline does not gain one
+			if self.entry(id).is_unterminated() && filtered.ends_with('\n') {
+				filtered.pop();
+				if filtered.ends_with('\r') {
+					filtered.pop();
+				}
+			}
+			// Truncation is judged on the contents as they were in the bundle
+			let truncation = self.node(id).and_then(|node| node.truncation());
+			self.set_contents(id, filtered)
+				.expect("only files have contents to filter");
+			if let Some(reason) = truncation {
+				self.truncations.insert(id, reason);
+			}
+		}
+	}
+
+	// Returns why a file looked cut off before its filters ran, if it did
+	pub(crate) fn unfiltered_truncation(&self, id: NodeId) -> Option<&str> {
+		self.truncations.get(&id).map(String::as_str)
+	}
+
+	// Returns where the node came from in the input
+	pub(crate) fn span(&self, id: NodeId) -> &Span {
+		&self.spans[id.0]
+	}
+
+	/// Returns the paths marked `(deleted)` in the body of the bundle
+	pub fn deletions(&self) -> &[String] {
+		&self.deletions
+	}
+
+	/// Returns the text in the body that was left out of every file, in the
+	/// order it appeared
+	pub fn dropped_text(&self) -> &[DroppedText] {
+		&self.dropped
+	}
+
+	/// Returns the shell commands found in the dropped text, in order
+	pub fn shell_commands(&self) -> Vec<ShellCommand> {
+		commands::shell_commands(&self.dropped)
+	}
+
+	/// Sets the modes that `chmod` commands in the dropped text give files
+	/// and directories in the bundle, like `chmod +x scripts/run.sh`
+	///
+	/// Paths that name no node, or name a symlink, are skipped, as are modes
+	/// that cannot be read.
+	pub fn apply_chmod_commands(&mut self, debug: bool) {
+		for chmod in commands::chmods(&self.shell_commands()) {
+			for path in &chmod.paths {
+				let Ok(node) = self.get_node(path.trim_start_matches("./"))
+				else {
+					continue;
+				};
+				if node.link_target().is_some() {
+					continue;
+				}
+				let (id, is_dir) = (node.id(), node.is_directory());
+				let current = node.mode().unwrap_or(if is_dir {
+					fsnode::DEFAULT_EXECUTABLE_MODE
+				}
+				else {
+					fsnode::DEFAULT_FILE_MODE
+				});
+				let Some(mode) = chmod.apply(current, is_dir)
+				else {
+					continue;
+				};
+				let mode = mode & fsnode::UNTRUSTED_MODE_MASK;
+				if debug {
+					println!(
+						"Mode of {} set to {:o} by the chmod on line {}",
+						path, mode, chmod.line
+					);
+				}
+				self.set_mode(id, Some(mode))
+					.expect("chmod paths name files or directories");
 			}
 		}
+	}
+
+	// Records text from the body that belongs to no file, without the blank
+	// lines around it
+	pub(crate) fn drop_text(&mut self, text: &str, mut line: usize, debug: bool) {
+		let mut text = text.trim_end();
+		if text.trim_start().is_empty() {
+			return;
+		}
+		while let Some((first, rest)) = text.split_once('\n')
+			&& first.trim().is_empty()
+		{
+			text = rest;
+			line += 1;
+		}
+		if debug {
+			let last_line = line + text.lines().count() - 1;
+			println!("Dropped text on lines {}-{}", line, last_line);
+		}
+		self.dropped.push(DroppedText {
+			line,
+			text: text.to_string(),
+		});
+	}
+
+	// Removes a node from its parent's children
+	fn detach(&mut self, id: NodeId) {
+		if let Some(parent) = self.entry(id).parent()
+			&& let FsNode::Directory { children, .. } = self.entry_mut(parent)
+		{
+			children.retain(|&child| child != id);
+		}
+	}
+
+	// Returns the node and all of its descendants
+	fn subtree(&self, id: NodeId) -> Vec<NodeId> {
+		let mut ids = vec![id];
+		let mut i = 0;
+		while i < ids.len() {
+			ids.extend_from_slice(self.entry(ids[i]).children());
+			i += 1;
+		}
+		ids
+	}
+
+	// Registers the subtree under its current paths after it was renamed or
+	// moved
+	fn reindex(&mut self, id: NodeId) {
+		let moved = self.subtree(id);
+		self.path_map.retain(|_, id| !moved.contains(id));
+		for id in moved {
+			let path = self.relative_location(id).to_string_lossy().to_string();
+			self.path_map.insert(path, id);
+		}
+	}
+
+	// Returns the child of a directory with the given name
+	fn child(&self, directory: NodeId, name: &str) -> Option<NodeId> {
+		self.entry(directory)
+			.children()
+			.iter()
+			.copied()
+			.find(|&id| self.entry(id).name() == name)
+	}
+
+	// Returns the existing directory a new node at the path goes into, along
+	// with the new node's name
+	fn parent_for<'p>(&self, path: &'p str) -> Result<(NodeId, &'p str)> {
+		let path = path.trim_end_matches('/');
+		let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+		if name.is_empty() {
+			return Err(Error::PathResolution(format!("Invalid path: {}", path)));
+		}
+
+		let parent = self
+			.get_node(parent_path)
+			.map_err(|_| Error::MissingParent(path.to_string()))?;
+		if !parent.is_directory() {
+			return Err(Error::MissingParent(path.to_string()));
+		}
+		if self.child(parent.id(), name).is_some() {
+			return Err(Error::AlreadyExists(path.to_string()));
+		}
+
+		Ok((parent.id(), name))
+	}
+
+	/// Adds a file at the path, whose parent directory must already exist
+	pub fn add_file(&mut self, path: &str, contents: Option<Cow<'a, str>>) -> Result<NodeId> {
+		let (parent, name) = self.parent_for(path)?;
+		Ok(self.insert(FsNode::File {
+			name: name.to_string(),
+			parent,
+			contents: contents.map(fsnode::text_bytes),
+			mode: None,
+			encoding: None,
+			unterminated: false,
+		}))
+	}
+
+	/// Adds a symlink at the path, whose parent directory must already exist
+	///
+	/// The target is kept as given; `write_to_disk` refuses targets outside
+	/// the output directory.
+	pub fn add_symlink(&mut self, path: &str, target: &str) -> Result<NodeId> {
+		let (parent, name) = self.parent_for(path)?;
+		Ok(self.insert(FsNode::Symlink {
+			name: name.to_string(),
+			parent,
+			target: target.to_string(),
+		}))
+	}
+
+	/// Returns the directory at the path, creating it and any missing parents
+	pub fn create_dir_all(&mut self, path: &str) -> Result<NodeId> {
+		let mut current = self.root;
+		let root_name = self.entry(current).name().to_string();
+
+		for (i, component) in path.split('/').filter(|c| !c.is_empty()).enumerate() {
+			// The first component might be the root directory name
+			if i == 0 && !root_name.is_empty() && component == root_name {
+				continue;
+			}
+
+			current = match self.child(current, component) {
+				Some(id) if self.entry(id).is_directory() => id,
+				Some(id) => {
+					let path = self.relative_location(id);
+					return Err(Error::AlreadyExists(path.display().to_string()));
+				}
+				None => self.insert(FsNode::Directory {
+					name: component.to_string(),
+					parent: Some(current),
+					children: Vec::new(),
+					mode: None,
+				}),
+			};
+		}
 
 		Ok(current)
 	}
 
+	/// Removes the node at the path along with everything below it
+	pub fn remove(&mut self, path: &str) -> Result<()> {
+		let id = self.get_node(path)?.id();
+		if id == self.root {
+			return Err(Error::PathResolution("Cannot remove the root".to_string()));
+		}
+		self.detach(id);
+
+		let removed = self.subtree(id);
+		self.path_map.retain(|_, id| !removed.contains(id));
+		for id in removed {
+			self.nodes[id.0] = None;
+		}
+		Ok(())
+	}
+
+	/// Renames the node at the path within its directory
+	pub fn rename(&mut self, path: &str, new_name: &str) -> Result<()> {
+		let id = self.get_node(path)?.id();
+		if new_name.is_empty() || new_name.contains('/') {
+			return Err(Error::PathResolution(format!("Invalid name: {}", new_name)));
+		}
+		if id == self.root {
+			return Err(Error::PathResolution("Cannot rename the root".to_string()));
+		}
+
+		if let Some(parent) = self.entry(id).parent()
+			&& self
+				.child(parent, new_name)
+				.is_some_and(|other| other != id)
+		{
+			let mut new_path = self.relative_location(id);
+			new_path.set_file_name(new_name);
+			return Err(Error::AlreadyExists(new_path.display().to_string()));
+		}
+
+		match self.entry_mut(id) {
+			FsNode::File { name, .. }
+			| FsNode::Directory { name, .. }
+			| FsNode::Symlink { name, .. } => {
+				*name = new_name.to_string();
+			}
+		}
+
+		self.reindex(id);
+		Ok(())
+	}
+
+	/// Moves the node at the path into another existing directory
+	pub fn move_node(&mut self, path: &str, new_parent: &str) -> Result<()> {
+		let node = self.get_node(path)?;
+		let (id, name) = (node.id(), node.name().to_string());
+		if id == self.root {
+			return Err(Error::PathResolution("Cannot move the root".to_string()));
+		}
+
+		let target = self
+			.get_node(new_parent.trim_end_matches('/'))
+			.map_err(|_| Error::MissingParent(format!("{}/{}", new_parent, name)))?;
+		if !target.is_directory() {
+			return Err(Error::MissingParent(format!("{}/{}", new_parent, name)));
+		}
+		let target = target.id();
+
+		// A directory cannot be moved below itself
+		if self.subtree(id).contains(&target) {
+			return Err(Error::PathResolution(format!(
+				"Cannot move {} into itself",
+				path
+			)));
+		}
+		if let Some(existing) = self.child(target, &name) {
+			if existing == id {
+				return Ok(());
+			}
+			return Err(Error::AlreadyExists(
+				self.relative_location(existing).display().to_string(),
+			));
+		}
+
+		self.detach(id);
+		match self.entry_mut(id) {
+			FsNode::File { parent, .. } | FsNode::Symlink { parent, .. } => *parent = target,
+			FsNode::Directory { parent, .. } => *parent = Some(target),
+		}
+		if let FsNode::Directory { children, .. } = self.entry_mut(target) {
+			children.push(id);
+		}
+
+		self.reindex(id);
+		Ok(())
+	}
+
+	/// Overlays another filesystem on top of this one
+	///
+	/// Files from `other` replace files at the same path and new files and
+	/// directories are added. A file listed in `other` without contents keeps
+	/// the contents it already has, so a follow-up bundle only needs to resend
+	/// the files it changes, and elided code in a resent file is filled in
+	/// from the contents it replaces. Paths `other` marks `(deleted)` are
+	/// removed, and its dropped text follows this filesystem's.
+	pub fn overlay(&mut self, other: &FileSystem<'a>) -> Result<()> {
+		for (i, node) in other.nodes.iter().enumerate() {
+			let Some(node) = node
+			else {
+				continue;
+			};
+			let path = other
+				.relative_location(NodeId(i))
+				.to_string_lossy()
+				.to_string();
+			if path.is_empty() {
+				continue;
+			}
+
+			let existing = self.get_node(&path).ok().map(|existing| {
+				let kind = match *existing {
+					FsNode::File { .. } => ExistingKind::File,
+					FsNode::Directory { .. } => ExistingKind::Directory,
+					FsNode::Symlink { .. } => ExistingKind::Symlink,
+				};
+				(existing.id(), kind)
+			});
+
+			match node {
+				FsNode::File { contents, mode, .. } => {
+					// Keep the existing file unless it is really something else
+					let id = match existing {
+						Some((id, ExistingKind::File)) => id,
+						_ => {
+							if existing.is_some() {
+								self.remove(&path)?;
+							}
+							if let Some((parent_path, _)) = path.rsplit_once('/') {
+								self.create_dir_all(parent_path)?;
+							}
+							self.add_file(&path, None)?
+						}
+					};
+					// Elided code is spliced into the contents the file already
+					// has; a fragment that does not anchor is kept for
+					// extraction to merge with the file on disk
+					let spliced = contents
+						.as_deref()
+						.and_then(|contents| std::str::from_utf8(contents).ok())
+						.filter(|text| node.encoding().is_none() && elision::has_elisions(text))
+						.zip(self.entry(id).bytes())
+						.and_then(|(fragment, existing)| {
+							elision::merge(fragment, Some(existing), Path::new(&path)).ok()
+						});
+					if let Some(merged) = spliced {
+						self.set_contents(id, merged)?;
+					}
+					else if let Some(contents) = contents {
+						self.set_bytes(id, contents.clone())?;
+						self.set_encoding(id, node.encoding());
+						self.set_unterminated(id, node.is_unterminated());
+						if let Some(reason) = other.unfiltered_truncation(NodeId(i)) {
+							self.truncations.insert(id, reason.to_string());
+						}
+					}
+					if mode.is_some() {
+						self.set_mode(id, *mode)?;
+					}
+				}
+				FsNode::Directory { mode, .. } => {
+					// A directory replaces a file or symlink at the same path
+					if let Some((_, ExistingKind::File | ExistingKind::Symlink)) = existing {
+						self.remove(&path)?;
+					}
+					let id = self.create_dir_all(&path)?;
+					if mode.is_some() {
+						self.set_mode(id, *mode)?;
+					}
+				}
+				FsNode::Symlink { target, .. } => {
+					if existing.is_some() {
+						self.remove(&path)?;
+					}
+					if let Some((parent_path, _)) = path.rsplit_once('/') {
+						self.create_dir_all(parent_path)?;
+					}
+					self.add_symlink(&path, target)?;
+				}
+			}
+		}
+
+		for path in other.deletions() {
+			if self.get_node(path).is_ok() {
+				self.remove(path)?;
+			}
+			self.deletions.push(path.clone());
+		}
+		self.dropped.extend_from_slice(other.dropped_text());
+
+		Ok(())
+	}
+
+	/// Returns a filesystem that owns its contents instead of borrowing them
+	/// from the input
+	pub fn into_owned(self) -> FileSystem<'static> {
+		FileSystem {
+			root: self.root,
+			nodes: self
+				.nodes
+				.into_iter()
+				.map(|node| node.map(FsNode::into_owned))
+				.collect(),
+			spans: self.spans,
+			path_map: self.path_map,
+			deletions: self.deletions,
+			dropped: self.dropped,
+			truncations: self.truncations,
+		}
+	}
+
 	/// Write the entire filesystem to disk
 	pub fn write_to_disk(&self, output_dir: impl AsRef<std::path::Path>) -> Result<()> {
-		let output_dir = output_dir.as_ref();
-		for node in &self.nodes {
-			node.borrow().write_to_disk(output_dir)?;
+		self.extract_to(&mut DiskTarget::new(output_dir))
+	}
+
+	/// Write the entire filesystem to an extraction target, in the order
+	/// the nodes were added, then remove the files marked `(deleted)`
+	pub fn extract_to<T: ExtractTarget + ?Sized>(&self, target: &mut T) -> Result<()> {
+		for node in self.nodes() {
+			node.extract_to(target)?;
+		}
+		self.remove_deleted(target)
+	}
+
+	/// Removes the files marked `(deleted)` from an extraction target
+	///
+	/// Paths that are not on the target, or that a later bundle added back,
+	/// are left alone.
+	pub fn remove_deleted<T: ExtractTarget + ?Sized>(&self, target: &mut T) -> Result<()> {
+		for path in &self.deletions {
+			if self.get_node(path).is_ok() {
+				continue;
+			}
+			let path = Path::new(path);
+			safety::check_path(path)?;
+			if target.exists(path) {
+				target.remove_file(path)?;
+			}
 		}
 		Ok(())
 	}
+
+	/// Write the entire filesystem to disk with several workers
+	///
+	/// Directories are created first, and any failure there is returned as an
+	/// error. Files are then written concurrently, and those that fail are
+	/// returned rather than aborting the others unless `fail_fast` is set.
+	pub fn write_to_disk_parallel(
+		&self,
+		output_dir: impl AsRef<std::path::Path>,
+		options: ParallelOptions,
+	) -> Result<Vec<WriteFailure>> {
+		let output_dir = output_dir.as_ref();
+		let (directories, files): (Vec<_>, Vec<_>) =
+			self.nodes().partition(|node| node.is_directory());
+		for node in directories {
+			node.write_to_disk(output_dir)?;
+		}
+
+		Ok(parallel::write_files(&files, options, |_, node| {
+			node.write_to_disk(output_dir)
+		}))
+	}
 }
 
-impl Default for FileSystem {
+impl Default for FileSystem<'_> {
 	fn default() -> Self {
 		Self::new()
 	}
 }
 
-impl fmt::Display for FileSystem {
+impl fmt::Display for FileSystem<'_> {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		write!(f, "{}", self.tree_output())
 	}
 }
-
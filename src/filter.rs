@@ -0,0 +1,214 @@
+use std::{borrow::Cow, fmt, path::Path, str::FromStr};
+
+use crate::{error::Error, line_ending::LineEnding, normalize::shared_indent};
+
+/// Rewrites a file's contents after parsing
+///
+/// Filters run in a `FilterChain`, each seeing the contents the previous one
+/// returned. Returning the contents borrowed leaves the file as it is.
+pub trait ContentFilter: Send + Sync {
+	/// Returns the new contents of the file at path
+	fn filter<'c>(&self, path: &Path, contents: &'c str) -> Cow<'c, str>;
+}
+
+impl<F> ContentFilter for F
+where
+	F: Fn(&Path, &str) -> String + Send + Sync,
+{
+	fn filter<'c>(&self, path: &Path, contents: &'c str) -> Cow<'c, str> {
+		Cow::Owned(self(path, contents))
+	}
+}
+
+/// The filters that come with the crate, selectable by name
+///
+/// The command line takes them from `--filter`, or as a comma-separated list
+/// from the `CLAUDEAI_BUNDLE_FILTERS` environment variable when no `--filter`
+/// is given. Names are parsed with `FromStr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinFilter {
+	/// Converts every line ending to one style (`line-endings` for LF, or
+	/// `line-endings=crlf`)
+	LineEndings(LineEnding),
+	/// Adds a newline in the file's own style to contents that do not end
+	/// with one (`trailing-newline`)
+	TrailingNewline,
+	/// Expands tabs to spaces at the given tab width (`tabs-to-spaces`, or
+	/// `tabs-to-spaces=2`; 4 by default)
+	TabsToSpaces(usize),
+	/// Removes spaces and tabs from the end of every line
+	/// (`strip-trailing-whitespace`)
+	StripTrailingWhitespace,
+	/// Removes the indentation every line shares (`dedent`)
+	Dedent,
+}
+
+impl BuiltinFilter {
+	/// Returns the name the filter is selected by
+	pub fn name(&self) -> &'static str {
+		match self {
+			BuiltinFilter::LineEndings(_) => "line-endings",
+			BuiltinFilter::TrailingNewline => "trailing-newline",
+			BuiltinFilter::TabsToSpaces(_) => "tabs-to-spaces",
+			BuiltinFilter::StripTrailingWhitespace => "strip-trailing-whitespace",
+			BuiltinFilter::Dedent => "dedent",
+		}
+	}
+}
+
+impl fmt::Display for BuiltinFilter {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BuiltinFilter::LineEndings(style) => write!(f, "{}={}", self.name(), style),
+			BuiltinFilter::TabsToSpaces(width) => write!(f, "{}={}", self.name(), width),
+			_ => write!(f, "{}", self.name()),
+		}
+	}
+}
+
+impl FromStr for BuiltinFilter {
+	type Err = Error;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		let (name, argument) = match name.trim().split_once('=') {
+			Some((name, argument)) => (name, Some(argument)),
+			None => (name.trim(), None),
+		};
+		let filter = match name {
+			"line-endings" => {
+				let style = argument.map_or(Ok(LineEnding::Lf), str::parse)?;
+				return Ok(BuiltinFilter::LineEndings(style));
+			}
+			"trailing-newline" => BuiltinFilter::TrailingNewline,
+			"tabs-to-spaces" => {
+				let width = match argument {
+					Some(width) => width
+						.parse()
+						.ok()
+						.filter(|&width| width > 0)
+						.ok_or_else(|| Error::Filter(format!("invalid tab width: {}", width)))?,
+					None => 4,
+				};
+				return Ok(BuiltinFilter::TabsToSpaces(width));
+			}
+			"strip-trailing-whitespace" => BuiltinFilter::StripTrailingWhitespace,
+			"dedent" => BuiltinFilter::Dedent,
+			_ => return Err(Error::Filter(format!("unknown filter: {}", name))),
+		};
+		match argument {
+			Some(_) => Err(Error::Filter(format!("{} takes no argument", name))),
+			None => Ok(filter),
+		}
+	}
+}
+
+impl ContentFilter for BuiltinFilter {
+	fn filter<'c>(&self, _path: &Path, contents: &'c str) -> Cow<'c, str> {
+		match self {
+			BuiltinFilter::LineEndings(style) => style.convert(contents),
+			BuiltinFilter::TrailingNewline if !contents.is_empty() && !contents.ends_with('\n') => {
+				let style = LineEnding::detect(contents).unwrap_or(LineEnding::Lf);
+				Cow::Owned(format!("{}{}", contents, style.as_str()))
+			}
+			BuiltinFilter::TabsToSpaces(width) if contents.contains('\t') => {
+				Cow::Owned(map_lines(contents, |line| expand_tabs(line, *width)))
+			}
+			BuiltinFilter::StripTrailingWhitespace
+				if contents.lines().any(|line| line.ends_with([' ', '\t'])) =>
+			{
+				Cow::Owned(map_lines(contents, |line| {
+					line.trim_end_matches([' ', '\t']).to_string()
+				}))
+			}
+			BuiltinFilter::Dedent => {
+				let indent = shared_indent(contents.lines()).len();
+				if indent == 0 {
+					return Cow::Borrowed(contents);
+				}
+				Cow::Owned(map_lines(contents, |line| {
+					line.get(indent..).unwrap_or_default().to_string()
+				}))
+			}
+			_ => Cow::Borrowed(contents),
+		}
+	}
+}
+
+/// Filters applied one after another to every file
+#[derive(Default)]
+pub struct FilterChain {
+	filters: Vec<Box<dyn ContentFilter>>,
+}
+
+impl FilterChain {
+	/// Creates a chain that leaves contents as they are
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Adds a filter to the end of the chain
+	pub fn push(&mut self, filter: impl ContentFilter + 'static) {
+		self.filters.push(Box::new(filter));
+	}
+
+	/// Returns true if the chain has no filters
+	pub fn is_empty(&self) -> bool {
+		self.filters.is_empty()
+	}
+
+	/// Runs the contents of the file at path through every filter in order
+	pub fn apply<'c>(&self, path: &Path, contents: &'c str) -> Cow<'c, str> {
+		let mut contents = Cow::Borrowed(contents);
+		for filter in &self.filters {
+			if let Cow::Owned(filtered) = filter.filter(path, &contents) {
+				contents = Cow::Owned(filtered);
+			}
+		}
+		contents
+	}
+}
+
+impl fmt::Debug for FilterChain {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "FilterChain({} filters)", self.filters.len())
+	}
+}
+
+impl FromIterator<BuiltinFilter> for FilterChain {
+	fn from_iter<I: IntoIterator<Item = BuiltinFilter>>(filters: I) -> Self {
+		let mut chain = FilterChain::new();
+		for filter in filters {
+			chain.push(filter);
+		}
+		chain
+	}
+}
+
+// Rewrites each line, keeping its line ending
+fn map_lines(contents: &str, map: impl Fn(&str) -> String) -> String {
+	contents
+		.split_inclusive('\n')
+		.map(|line| {
+			let text = line.trim_end_matches(['\n', '\r']);
+			map(text) + &line[text.len()..]
+		})
+		.collect()
+}
+
+// Replaces each tab with the spaces up to the next tab stop
+fn expand_tabs(line: &str, width: usize) -> String {
+	let mut expanded = String::with_capacity(line.len());
+	let mut column = 0;
+	for c in line.chars() {
+		if c == '\t' {
+			let spaces = width - column % width;
+			expanded.extend(std::iter::repeat_n(' ', spaces));
+			column += spaces;
+		}
+		else {
+			expanded.push(c);
+			column += 1;
+		}
+	}
+	expanded
+}
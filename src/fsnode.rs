@@ -1,71 +1,207 @@
 use std::{
-	cell::RefCell,
-	path::PathBuf,
-	rc::{Rc, Weak},
+	borrow::Cow,
+	ops::{Deref, Range},
+	path::{Path, PathBuf},
 };
 
-use crate::error::{Error, Result};
+use crate::{
+	elision,
+	encoding::Encoding,
+	error::{Error, Result},
+	filesystem::FileSystem,
+	line_ending::LineEnding,
+	safety,
+	target::{DiskTarget, ExtractTarget},
+	truncation,
+};
+
+/// Identifies a node within the `FileSystem` that created it
+///
+/// Ids are never reused, so the id of a removed node stays dangling rather
+/// than pointing at some other node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub(crate) usize);
+
+/// Where a node came from in the parsed input, as 1-based line numbers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Span {
+	/// The header line the node was declared on
+	pub header_line: Option<usize>,
+	/// The body lines its contents were taken from, end exclusive
+	pub body_lines: Option<Range<usize>>,
+}
 
-// Type aliases to make the code more readable
-pub type NodeRef = Rc<RefCell<FsNode>>;
-pub type WeakNodeRef = Weak<RefCell<FsNode>>;
+/// Permission bits a file gets when none are given
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Permission bits a directory, or a file starting with a `#!` line, gets
+/// when none are given
+pub const DEFAULT_EXECUTABLE_MODE: u32 = 0o755;
+
+// Bits kept of modes taken from a bundle or an archive, which never set
+// setuid, setgid or sticky on what they extract
+pub(crate) const UNTRUSTED_MODE_MASK: u32 = 0o777;
 
 /// Represents a node in the filesystem
-#[derive(Debug)]
-pub enum FsNode {
-	Root,
+///
+/// File contents are bytes, borrowed from the parsed input where possible;
+/// see `FileSystem::into_owned` for a node that outlives it. Text files are
+/// read through `contents`, any file through `bytes`.
+#[derive(Debug, Clone)]
+pub enum FsNode<'a> {
 	File {
 		name: String,
-		parent: WeakNodeRef,
-		contents: Option<String>,
+		parent: NodeId,
+		contents: Option<Cow<'a, [u8]>>,
+		/// Permission bits given for the file, if any
+		mode: Option<u32>,
+		/// How the contents were encoded in the bundle, if they were; encoded
+		/// contents are exact bytes that filters and clean-up leave alone
+		encoding: Option<Encoding>,
+		/// The section was marked `(no final newline)`, so the contents end
+		/// without one even after filters
+		unterminated: bool,
 	},
 	Directory {
 		name: String,
-		parent: WeakNodeRef,
-		children: Vec<NodeRef>,
+		/// `None` only for the root directory
+		parent: Option<NodeId>,
+		children: Vec<NodeId>,
+		/// Permission bits given for the directory, if any
+		mode: Option<u32>,
+	},
+	Symlink {
+		name: String,
+		parent: NodeId,
+		/// The path the link points to, relative to its directory
+		target: String,
 	},
 }
 
-impl FsNode {
-	/// Get a clone of the node's weak parent reference
-	pub fn parent_ref(&self) -> Option<WeakNodeRef> {
+impl<'a> FsNode<'a> {
+	/// Returns the id of the node's parent, or `None` for the root
+	pub fn parent(&self) -> Option<NodeId> {
 		match self {
-			FsNode::Root => None,
-			FsNode::File { parent, .. } => Some(parent.clone()),
-			FsNode::Directory { parent, .. } => Some(parent.clone()),
+			FsNode::File { parent, .. } | FsNode::Symlink { parent, .. } => Some(*parent),
+			FsNode::Directory { parent, .. } => *parent,
 		}
 	}
 
 	/// Returns the name of this node
-	pub fn name(&self) -> String {
+	pub fn name(&self) -> &str {
 		match self {
-			FsNode::Root => String::new(),
-			FsNode::File { name, .. } => name.clone(),
-			FsNode::Directory { name, .. } => name.clone(),
+			FsNode::File { name, .. }
+			| FsNode::Directory { name, .. }
+			| FsNode::Symlink { name, .. } => name,
 		}
 	}
 
 	/// Returns true if this node is a directory
 	pub fn is_directory(&self) -> bool {
-		matches!(self, FsNode::Directory { .. } | FsNode::Root)
+		matches!(self, FsNode::Directory { .. })
 	}
 
-	/// Returns the file contents if this is a file with contents
-	pub fn contents(&self) -> Option<String> {
+	/// Returns the target of a symlink, or `None` for anything else
+	pub fn link_target(&self) -> Option<&str> {
 		match self {
-			FsNode::File { contents, .. } => contents.clone(),
+			FsNode::Symlink { target, .. } => Some(target),
 			_ => None,
 		}
 	}
 
-	/// Sets the contents of a file
-	pub fn set_contents(&mut self, contents: String) -> Result<()> {
+	/// Returns the ids of the node's children, empty for files
+	pub fn children(&self) -> &[NodeId] {
+		match self {
+			FsNode::Directory { children, .. } => children,
+			_ => &[],
+		}
+	}
+
+	/// Returns the file contents as text, or `None` if this is not a file
+	/// with contents or they are not valid UTF-8
+	pub fn contents(&self) -> Option<&str> {
+		std::str::from_utf8(self.bytes()?).ok()
+	}
+
+	/// Returns the file contents as bytes if this is a file with contents
+	pub fn bytes(&self) -> Option<&[u8]> {
+		match self {
+			FsNode::File { contents, .. } => contents.as_deref(),
+			_ => None,
+		}
+	}
+
+	/// Returns how the file's contents were encoded in the bundle, if they
+	/// were marked with an encoding
+	pub fn encoding(&self) -> Option<Encoding> {
+		match self {
+			FsNode::File { encoding, .. } => *encoding,
+			_ => None,
+		}
+	}
+
+	/// Returns true for a file marked `(no final newline)` in the bundle
+	pub fn is_unterminated(&self) -> bool {
+		matches!(
+			self,
+			FsNode::File {
+				unterminated: true,
+				..
+			}
+		)
+	}
+
+	/// Returns true for a file whose contents are not valid UTF-8
+	pub fn is_binary(&self) -> bool {
+		self.bytes().is_some() && self.contents().is_none()
+	}
+
+	/// Returns the permission bits to write the node with
+	///
+	/// Bits given explicitly win; otherwise a file starting with a `#!` line
+	/// is executable. Returns `None` when the node should get whatever the
+	/// system gives new files and directories, and always for symlinks.
+	pub fn mode(&self) -> Option<u32> {
+		match self {
+			FsNode::File {
+				mode: Some(mode), ..
+			}
+			| FsNode::Directory {
+				mode: Some(mode), ..
+			} => Some(*mode),
+			FsNode::File { contents, .. } => contents
+				.as_deref()
+				.is_some_and(|contents| contents.starts_with(b"#!"))
+				.then_some(DEFAULT_EXECUTABLE_MODE),
+			_ => None,
+		}
+	}
+
+	/// Sets the permission bits of a file or directory, or clears them with
+	/// `None`
+	pub fn set_mode(&mut self, new_mode: Option<u32>) -> Result<()> {
+		match self {
+			FsNode::File { mode, .. } | FsNode::Directory { mode, .. } => {
+				*mode = new_mode.map(|mode| mode & 0o7777);
+				Ok(())
+			}
+			FsNode::Symlink { .. } => Err(Error::InvalidNodeType),
+		}
+	}
+
+	/// Sets the contents of a file to text, either borrowed or owned
+	pub fn set_contents(&mut self, contents: impl Into<Cow<'a, str>>) -> Result<()> {
+		self.set_bytes(text_bytes(contents.into()))
+	}
+
+	/// Sets the contents of a file to bytes, either borrowed or owned
+	pub fn set_bytes(&mut self, contents: impl Into<Cow<'a, [u8]>>) -> Result<()> {
 		if let FsNode::File {
 			contents: file_contents,
 			..
 		} = self
 		{
-			*file_contents = Some(contents);
+			*file_contents = Some(contents.into());
 			Ok(())
 		}
 		else {
@@ -73,79 +209,240 @@ impl FsNode {
 		}
 	}
 
-	/// Writes the node to disk in the specified directory
-	pub fn write_to_disk(&self, output_dir: impl AsRef<std::path::Path>) -> Result<()> {
-		use std::fs;
-
-		// Get the relative path
-		let relative_path = self.relative_location();
-		if relative_path.as_os_str().is_empty() {
-			return Ok(());
+	/// Returns the node with its contents copied out of the input
+	pub fn into_owned(self) -> FsNode<'static> {
+		match self {
+			FsNode::File {
+				name,
+				parent,
+				contents,
+				mode,
+				encoding,
+				unterminated,
+			} => FsNode::File {
+				name,
+				parent,
+				contents: contents.map(|contents| Cow::Owned(contents.into_owned())),
+				mode,
+				encoding,
+				unterminated,
+			},
+			FsNode::Directory {
+				name,
+				parent,
+				children,
+				mode,
+			} => FsNode::Directory {
+				name,
+				parent,
+				children,
+				mode,
+			},
+			FsNode::Symlink {
+				name,
+				parent,
+				target,
+			} => FsNode::Symlink {
+				name,
+				parent,
+				target,
+			},
 		}
+	}
+}
 
-		// Create a PathBuf from the output directory and relative path
-		let output_path = PathBuf::from(output_dir.as_ref()).join(relative_path);
+/// A node together with the filesystem it belongs to
+///
+/// Dereferences to the `FsNode` and adds what needs the rest of the tree,
+/// such as the node's path and its parent and children.
+#[derive(Debug, Clone, Copy)]
+pub struct Node<'a> {
+	fs: &'a FileSystem<'a>,
+	id: NodeId,
+	node: &'a FsNode<'a>,
+}
 
-		match self {
-			FsNode::Root => {
-				// Root node doesn't need writing
-				Ok(())
-			}
-			FsNode::Directory { .. } => {
-				// Create directory
-				fs::create_dir_all(&output_path)?;
-				Ok(())
-			}
-			FsNode::File { .. } => {
-				// Ensure parent directory exists
-				if let Some(parent) = output_path.parent() {
-					fs::create_dir_all(parent)?
-				}
+impl<'a> Node<'a> {
+	pub(crate) fn new(fs: &'a FileSystem<'a>, id: NodeId, node: &'a FsNode<'a>) -> Self {
+		Node { fs, id, node }
+	}
 
-				// Write file contents
-				let content = self.contents().unwrap_or_default();
+	/// Returns the node's id
+	pub fn id(&self) -> NodeId {
+		self.id
+	}
 
-				fs::write(&output_path, content)?;
-				Ok(())
-			}
+	/// Returns the underlying node
+	pub fn fs_node(&self) -> &'a FsNode<'a> {
+		self.node
+	}
+
+	/// Returns the name of this node
+	pub fn name(&self) -> &'a str {
+		self.node.name()
+	}
+
+	/// Returns the file contents as text without copying them, or `None` if
+	/// they are not valid UTF-8
+	pub fn contents(&self) -> Option<&'a str> {
+		self.node.contents()
+	}
+
+	/// Returns the file contents as bytes without copying them
+	pub fn bytes(&self) -> Option<&'a [u8]> {
+		self.node.bytes()
+	}
+
+	/// Returns where the node came from in the input
+	pub fn span(&self) -> &'a Span {
+		self.fs.span(self.id)
+	}
+
+	/// Returns how most lines of the file's contents end, or `None` for a
+	/// directory or contents without line breaks
+	pub fn line_ending(&self) -> Option<LineEnding> {
+		LineEnding::detect(self.contents()?)
+	}
+
+	/// Returns why the file's contents look cut off part way through, if they do
+	///
+	/// Encoded sections hold exact bytes and are never taken as cut off, and
+	/// a section marked `(no final newline)` is not expected to end with one.
+	/// Contents changed by filters are judged as they were before.
+	pub fn truncation(&self) -> Option<String> {
+		if self.encoding().is_some() {
+			return None;
+		}
+		if let Some(reason) = self.fs.unfiltered_truncation(self.id) {
+			return Some(reason.to_string());
 		}
+		let from_section = self.span().body_lines.is_some() && !self.is_unterminated();
+		truncation::truncation(self.contents()?, from_section)
+	}
+
+	/// Returns true if the file's body section ends part way through a line
+	/// without being marked `(no final newline)`
+	pub fn lacks_final_newline(&self) -> bool {
+		self.encoding().is_none()
+			&& self.span().body_lines.is_some()
+			&& !self.is_unterminated()
+			&& self.contents().is_some_and(truncation::lacks_final_newline)
+	}
+
+	/// Returns true if this is the root directory
+	pub fn is_root(&self) -> bool {
+		self.node.parent().is_none()
+	}
+
+	/// Returns the node's parent directory, or `None` for the root
+	pub fn parent(&self) -> Option<Node<'a>> {
+		self.node.parent().and_then(|id| self.fs.node(id))
+	}
+
+	/// Returns the node's children in the order they were added
+	pub fn children(&self) -> impl Iterator<Item = Node<'a>> + use<'a> {
+		let fs = self.fs;
+		self.node
+			.children()
+			.iter()
+			.filter_map(move |&id| fs.node(id))
 	}
 
 	/// Returns the relative location of this node as a PathBuf
 	pub fn relative_location(&self) -> PathBuf {
-		match self {
-			FsNode::Root => PathBuf::new(),
-			_ => {
-				let mut parts = Vec::new();
-
-				// First add this node's name
-				parts.push(self.name());
+		self.fs.relative_location(self.id)
+	}
 
-				// Then traverse up the parent chain
-				let mut parent_ref = self.parent_ref();
+	/// Writes the node to disk in the specified directory
+	///
+	/// Paths that would leave the directory, symlinks pointing out of it and
+	/// files that are symlinks on disk are refused with `Error::UnsafePath`.
+	/// Modes are applied where the platform has them.
+	pub fn write_to_disk(&self, output_dir: impl AsRef<Path>) -> Result<()> {
+		self.extract_to(&mut DiskTarget::new(output_dir))
+	}
 
-				while let Some(parent_weak) = parent_ref {
-					if let Some(parent) = parent_weak.upgrade() {
-						let parent_node = parent.borrow();
-						if let FsNode::Root = &*parent_node {
-							break;
-						}
+	/// Writes the node to an extraction target
+	///
+	/// The directory the node is in is created first and its mode, if it has
+	/// one, set right after it. Elided code is merged with the file the
+	/// target already has at the path. Paths that would leave the target's
+	/// root and symlinks pointing out of it are refused with
+	/// `Error::UnsafePath`.
+	pub fn extract_to<T: ExtractTarget + ?Sized>(&self, target: &mut T) -> Result<()> {
+		let relative_path = self.relative_location();
+		if relative_path.as_os_str().is_empty() {
+			return Ok(());
+		}
+		safety::check_path(&relative_path)?;
 
-						// Add the parent's name
-						parts.push(parent_node.name());
+		match self.node {
+			FsNode::Directory { .. } => target.create_dir_all(&relative_path)?,
+			FsNode::File { .. } => {
+				create_parent(target, &relative_path)?;
 
-						// Move up to the next parent
-						parent_ref = parent_node.parent_ref();
-					}
-					else {
-						break;
+				// Merge elided code with the existing file, unless the contents
+				// were encoded byte for byte
+				let fragment = self
+					.contents()
+					.filter(|text| self.encoding().is_none() && elision::has_elisions(text));
+				match fragment {
+					Some(fragment) => {
+						let existing = target.read_file(&relative_path)?;
+						let merged = elision::merge(fragment, existing.as_deref(), &relative_path)?;
+						target.write_file(&relative_path, merged.as_bytes())?;
 					}
+					None => target.write_file(&relative_path, self.bytes().unwrap_or_default())?,
 				}
-
-				// Reverse the parts and create a PathBuf
-				parts.iter().rev().collect::<PathBuf>()
 			}
+			FsNode::Symlink { target: link, .. } => {
+				safety::check_link(&relative_path, link)?;
+				create_parent(target, &relative_path)?;
+				target.symlink(&relative_path, link)?;
+			}
+		}
+		if let Some(mode) = self.mode() {
+			target.set_mode(&relative_path, mode)?;
 		}
+		Ok(())
+	}
+
+	/// Sets the node's permission bits, if it has any, on what is already
+	/// written at its path in the output directory
+	///
+	/// This is for modes that only became known after the node was written,
+	/// like those from `chmod` commands at the end of a streamed bundle.
+	pub fn write_mode(&self, output_dir: impl AsRef<Path>) -> Result<()> {
+		let Some(mode) = self.mode()
+		else {
+			return Ok(());
+		};
+		let relative_path = self.relative_location();
+		safety::check_path(&relative_path)?;
+		DiskTarget::new(output_dir).set_mode(&relative_path, mode)
+	}
+}
+
+impl<'a> Deref for Node<'a> {
+	type Target = FsNode<'a>;
+
+	fn deref(&self) -> &FsNode<'a> {
+		self.node
 	}
 }
 
+// Views text contents as the bytes they are stored as, without copying
+pub(crate) fn text_bytes(contents: Cow<'_, str>) -> Cow<'_, [u8]> {
+	match contents {
+		Cow::Borrowed(contents) => Cow::Borrowed(contents.as_bytes()),
+		Cow::Owned(contents) => Cow::Owned(contents.into_bytes()),
+	}
+}
+
+// Creates the directory a node at the path is in, unless it is the root
+fn create_parent<T: ExtractTarget + ?Sized>(target: &mut T, path: &Path) -> Result<()> {
+	match path.parent() {
+		Some(parent) if !parent.as_os_str().is_empty() => target.create_dir_all(parent),
+		_ => Ok(()),
+	}
+}
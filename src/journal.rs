@@ -0,0 +1,486 @@
+use std::{
+	fs,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	sync::Mutex,
+};
+
+use crate::{
+	error::{Error, Result},
+	filesystem::FileSystem,
+	fsnode::Node,
+	parallel::{self, ParallelOptions, WriteFailure},
+	safety,
+	target::{DiskTarget, ExtractTarget},
+};
+
+/// Name of the hidden directory in the output root that holds the journal
+pub const JOURNAL_DIR: &str = ".claudeai-bundle";
+
+const JOURNAL_FILE: &str = "journal";
+const STASH_DIR: &str = "stash";
+
+/// A single change made to the output directory during an extraction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalEntry {
+	/// A directory that did not exist before the extraction
+	CreatedDir { path: PathBuf },
+	/// A file that did not exist before the extraction
+	CreatedFile { path: PathBuf, hash: u64 },
+	/// A file that existed and was replaced, with its old contents stashed
+	OverwrittenFile {
+		path: PathBuf,
+		hash: u64,
+		stash: String,
+	},
+	/// A symlink that did not exist before the extraction
+	CreatedLink { path: PathBuf },
+	/// A file marked `(deleted)` that was removed, with its contents stashed
+	RemovedFile { path: PathBuf, stash: String },
+}
+
+impl JournalEntry {
+	/// Returns the path relative to the output root this entry refers to
+	pub fn path(&self) -> &Path {
+		match self {
+			JournalEntry::CreatedDir { path } => path,
+			JournalEntry::CreatedFile { path, .. } => path,
+			JournalEntry::OverwrittenFile { path, .. } => path,
+			JournalEntry::CreatedLink { path } => path,
+			JournalEntry::RemovedFile { path, .. } => path,
+		}
+	}
+}
+
+/// Record of every change an extraction made to an output directory
+///
+/// The journal lives in `JOURNAL_DIR` inside the output root, next to a
+/// `stash` directory holding the previous contents of overwritten and
+/// removed files.
+/// Replaying the journal backward with `undo` restores the output root to the
+/// state it was in before the extraction.
+///
+/// A journal is an `ExtractTarget` wrapping a `DiskTarget` for the output
+/// root: everything written through it goes to disk and is recorded.
+#[derive(Debug)]
+pub struct Journal {
+	disk: DiskTarget,
+	entries: Vec<JournalEntry>,
+	// Added to the number of entries to name each stashed file, so workers
+	// writing at once never share a name
+	first_stash: usize,
+}
+
+// FNV-1a, used to detect files modified after the extraction
+fn content_hash(contents: &[u8]) -> u64 {
+	let mut hash: u64 = 0xcbf29ce484222325;
+	for &byte in contents {
+		hash ^= byte as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+impl Journal {
+	/// Starts a new journal for the output root, discarding any previous one
+	pub fn new(output_dir: impl AsRef<Path>) -> Result<Self> {
+		let root = output_dir.as_ref().to_path_buf();
+		let journal_dir = root.join(JOURNAL_DIR);
+
+		// Only the most recent extraction can be undone
+		if journal_dir.exists() {
+			fs::remove_dir_all(&journal_dir)?;
+		}
+		fs::create_dir_all(journal_dir.join(STASH_DIR))?;
+
+		Ok(Journal {
+			disk: DiskTarget::new(root),
+			entries: Vec::new(),
+			first_stash: 0,
+		})
+	}
+
+	/// Loads the journal of the last extraction into the output root
+	pub fn load(output_dir: impl AsRef<Path>) -> Result<Self> {
+		let root = output_dir.as_ref().to_path_buf();
+		let journal_path = root.join(JOURNAL_DIR).join(JOURNAL_FILE);
+
+		let text = match fs::read_to_string(&journal_path) {
+			Ok(text) => text,
+			Err(err) if err.kind() == ErrorKind::NotFound => {
+				return Err(Error::Journal(format!(
+					"No journal found at {}",
+					journal_path.display()
+				)));
+			}
+			Err(err) => return Err(err.into()),
+		};
+
+		let mut entries = Vec::new();
+		for (i, line) in text.lines().enumerate() {
+			let malformed =
+				|| Error::Journal(format!("Malformed journal line {}: {}", i + 1, line));
+			let fields: Vec<&str> = line.splitn(4, '\t').collect();
+			let path = |field: &str| unescape_path(field).ok_or_else(malformed);
+
+			let entry = match fields.as_slice() {
+				["dir", field] => JournalEntry::CreatedDir { path: path(field)? },
+				["link", field] => JournalEntry::CreatedLink { path: path(field)? },
+				["remove", stash, field] => JournalEntry::RemovedFile {
+					path: path(field)?,
+					stash: stash.to_string(),
+				},
+				["file", hash, field] => JournalEntry::CreatedFile {
+					path: path(field)?,
+					hash: u64::from_str_radix(hash, 16).map_err(|_| malformed())?,
+				},
+				["overwrite", hash, stash, field] => JournalEntry::OverwrittenFile {
+					path: path(field)?,
+					hash: u64::from_str_radix(hash, 16).map_err(|_| malformed())?,
+					stash: stash.to_string(),
+				},
+				_ => return Err(malformed()),
+			};
+			entries.push(entry);
+		}
+
+		Ok(Journal {
+			disk: DiskTarget::new(root),
+			entries,
+			first_stash: 0,
+		})
+	}
+
+	/// Returns the recorded changes in the order they were made
+	pub fn entries(&self) -> &[JournalEntry] {
+		&self.entries
+	}
+
+	/// Writes the journal to the output root
+	pub fn save(&self) -> Result<()> {
+		let mut text = String::new();
+		for entry in &self.entries {
+			match entry {
+				JournalEntry::CreatedDir { path } => {
+					text.push_str(&format!("dir\t{}\n", escape_path(path)));
+				}
+				JournalEntry::CreatedFile { path, hash } => {
+					text.push_str(&format!("file\t{:016x}\t{}\n", hash, escape_path(path)));
+				}
+				JournalEntry::OverwrittenFile { path, hash, stash } => {
+					text.push_str(&format!(
+						"overwrite\t{:016x}\t{}\t{}\n",
+						hash,
+						stash,
+						escape_path(path)
+					));
+				}
+				JournalEntry::CreatedLink { path } => {
+					text.push_str(&format!("link\t{}\n", escape_path(path)));
+				}
+				JournalEntry::RemovedFile { path, stash } => {
+					text.push_str(&format!("remove\t{}\t{}\n", stash, escape_path(path)));
+				}
+			}
+		}
+
+		fs::write(self.root().join(JOURNAL_DIR).join(JOURNAL_FILE), text)?;
+		Ok(())
+	}
+
+	// Returns the output root
+	fn root(&self) -> &Path {
+		self.disk.root()
+	}
+
+	// Copies the file at the path, with its mode, into the stash and returns
+	// the name it was stashed under
+	fn stash(&self, path: &Path) -> Result<String> {
+		let stash = (self.first_stash + self.entries.len()).to_string();
+		fs::copy(
+			self.root().join(path),
+			self.root().join(JOURNAL_DIR).join(STASH_DIR).join(&stash),
+		)?;
+		Ok(stash)
+	}
+
+	/// Writes the node to the output root, journaling what changed
+	pub fn write_node(&mut self, node: Node<'_>) -> Result<()> {
+		node.extract_to(self)
+	}
+
+	/// Writes every node of the filesystem to the output root with several
+	/// workers, journaling what changed
+	///
+	/// Directories are created first, then files are written concurrently as
+	/// `FileSystem::write_to_disk_parallel` does. Files that fail are returned
+	/// and left out of the journal.
+	pub fn write_parallel(
+		&mut self,
+		fs: &FileSystem<'_>,
+		options: ParallelOptions,
+	) -> Result<Vec<WriteFailure>> {
+		let (directories, files): (Vec<_>, Vec<_>) =
+			fs.nodes().partition(|node| node.is_directory());
+		for node in directories {
+			self.write_node(node)?;
+		}
+
+		// Each file is journaled on its own and stashes under its own name so
+		// the workers never collide
+		let first_stash = self.entries.len();
+		let written = Mutex::new(Vec::new());
+		let failures = parallel::write_files(&files, options, |i, node| {
+			let mut journal = Journal {
+				disk: self.disk.clone(),
+				entries: Vec::new(),
+				first_stash: first_stash + i,
+			};
+			node.extract_to(&mut journal)?;
+			written.lock().unwrap().push((i, journal.entries));
+			Ok(())
+		});
+
+		// Journal the files in the order the filesystem lists them
+		let mut written = written.into_inner().unwrap();
+		written.sort_by_key(|(i, _)| *i);
+		self.entries
+			.extend(written.into_iter().flat_map(|(_, entries)| entries));
+		Ok(failures)
+	}
+
+	/// Writes every node of the filesystem to the output root and removes
+	/// the files it marks `(deleted)`
+	///
+	/// The journal is saved even when a write fails part way through, so the
+	/// partial extraction can still be undone.
+	pub fn extract(fs: &FileSystem<'_>, output_dir: impl AsRef<Path>) -> Result<Self> {
+		let mut journal = Journal::new(output_dir)?;
+
+		let result = fs.extract_to(&mut journal);
+
+		journal.save()?;
+		result.map(|_| journal)
+	}
+
+	/// Returns the files whose contents changed since they were extracted
+	pub fn modified_files(&self) -> Result<Vec<PathBuf>> {
+		let mut modified = Vec::new();
+		let mut seen = Vec::new();
+
+		// Only the last write of each path describes what should be on disk
+		for entry in self.entries.iter().rev() {
+			let (path, hash) = match entry {
+				JournalEntry::CreatedDir { .. } | JournalEntry::CreatedLink { .. } => continue,
+				JournalEntry::CreatedFile { path, hash } => (path, *hash),
+				JournalEntry::OverwrittenFile { path, hash, .. } => (path, *hash),
+				// A removed file must still be gone
+				JournalEntry::RemovedFile { path, .. } => {
+					if !seen.contains(&path) && self.disk.exists(path) {
+						modified.push(path.clone());
+					}
+					seen.push(path);
+					continue;
+				}
+			};
+			if seen.contains(&path) {
+				continue;
+			}
+			seen.push(path);
+
+			match fs::read(self.root().join(path)) {
+				Ok(contents) if content_hash(&contents) == hash => {}
+				Ok(_) => modified.push(path.clone()),
+				Err(err) if err.kind() == ErrorKind::NotFound => modified.push(path.clone()),
+				Err(err) => return Err(err.into()),
+			}
+		}
+
+		Ok(modified)
+	}
+
+	/// Reverts the extraction by replaying the journal backward
+	///
+	/// Refuses to touch anything if a file was modified since the extraction.
+	/// Directories that gained files of their own are left in place and
+	/// returned.
+	pub fn undo(self) -> Result<Vec<PathBuf>> {
+		let modified = self.modified_files()?;
+		if !modified.is_empty() {
+			let paths: Vec<String> = modified.iter().map(|p| p.display().to_string()).collect();
+			return Err(Error::ModifiedSinceExtraction(paths.join(", ")));
+		}
+
+		let journal_dir = self.root().join(JOURNAL_DIR);
+		let mut kept = Vec::new();
+
+		for entry in self.entries.iter().rev() {
+			let output_path = self.root().join(entry.path());
+			match entry {
+				JournalEntry::CreatedDir { path } => match fs::remove_dir(&output_path) {
+					Ok(()) => {}
+					Err(err) if err.kind() == ErrorKind::DirectoryNotEmpty => {
+						kept.push(path.clone());
+					}
+					Err(err) => return Err(err.into()),
+				},
+				JournalEntry::CreatedFile { .. } | JournalEntry::CreatedLink { .. } => {
+					fs::remove_file(&output_path)?
+				}
+				JournalEntry::OverwrittenFile { stash, .. }
+				| JournalEntry::RemovedFile { stash, .. } => {
+					fs::copy(journal_dir.join(STASH_DIR).join(stash), &output_path)?;
+				}
+			}
+		}
+
+		fs::remove_dir_all(journal_dir)?;
+		Ok(kept)
+	}
+}
+
+impl ExtractTarget for Journal {
+	// Creates each missing directory on the way, journaling it, and never
+	// goes through a symlink already on disk
+	fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+		let mut current = PathBuf::new();
+		for component in path.components() {
+			current.push(component);
+			if self.disk.exists(&current) {
+				safety::check_not_link(&self.root().join(&current), &current)?;
+			}
+			else {
+				self.disk.create_dir_all(&current)?;
+				self.entries.push(JournalEntry::CreatedDir {
+					path: current.clone(),
+				});
+			}
+		}
+		Ok(())
+	}
+
+	// Writes a file, stashing the one it replaces
+	fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<()> {
+		let output_path = self.root().join(path);
+		safety::check_not_link(&output_path, path)?;
+		let hash = content_hash(contents);
+
+		if self.disk.exists(path) {
+			let stash = self.stash(path)?;
+			self.disk.write_file(path, contents)?;
+			self.entries.push(JournalEntry::OverwrittenFile {
+				path: path.to_path_buf(),
+				hash,
+				stash,
+			});
+		}
+		else {
+			self.disk.write_file(path, contents)?;
+			self.entries.push(JournalEntry::CreatedFile {
+				path: path.to_path_buf(),
+				hash,
+			});
+		}
+		Ok(())
+	}
+
+	fn set_mode(&mut self, path: &Path, mode: u32) -> Result<()> {
+		self.disk.set_mode(path, mode)
+	}
+
+	// Creates a symlink; only a link already pointing at the target is left
+	// in place, as undo could not bring back anything else
+	fn symlink(&mut self, path: &Path, target: &str) -> Result<()> {
+		if self.disk.exists(path) {
+			if fs::read_link(self.root().join(path))
+				.is_ok_and(|existing| existing == Path::new(target))
+			{
+				return Ok(());
+			}
+			return Err(Error::AlreadyExists(path.display().to_string()));
+		}
+		self.disk.symlink(path, target)?;
+		self.entries.push(JournalEntry::CreatedLink {
+			path: path.to_path_buf(),
+		});
+		Ok(())
+	}
+
+	// Removes a file, stashing it so undo can bring it back
+	fn remove_file(&mut self, path: &Path) -> Result<()> {
+		safety::check_not_link(&self.root().join(path), path)?;
+		let stash = self.stash(path)?;
+		self.disk.remove_file(path)?;
+		self.entries.push(JournalEntry::RemovedFile {
+			path: path.to_path_buf(),
+			stash,
+		});
+		Ok(())
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		self.disk.exists(path)
+	}
+
+	fn read_file(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+		self.disk.read_file(path)
+	}
+}
+
+// Writes a path as one journal field: backslashes, tabs and line breaks are
+// escaped, and bytes that are not UTF-8 are written as `\xNN`
+fn escape_path(path: &Path) -> String {
+	let mut field = String::new();
+	for chunk in path.as_os_str().as_encoded_bytes().utf8_chunks() {
+		for c in chunk.valid().chars() {
+			match c {
+				'\\' => field.push_str("\\\\"),
+				'\t' => field.push_str("\\t"),
+				'\n' => field.push_str("\\n"),
+				'\r' => field.push_str("\\r"),
+				c => field.push(c),
+			}
+		}
+		for byte in chunk.invalid() {
+			field.push_str(&format!("\\x{:02x}", byte));
+		}
+	}
+	field
+}
+
+// Reads back a path written by `escape_path`
+fn unescape_path(field: &str) -> Option<PathBuf> {
+	let mut bytes = Vec::new();
+	let mut chars = field.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			let mut buffer = [0; 4];
+			bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+			continue;
+		}
+		match chars.next()? {
+			'\\' => bytes.push(b'\\'),
+			't' => bytes.push(b'\t'),
+			'n' => bytes.push(b'\n'),
+			'r' => bytes.push(b'\r'),
+			'x' => {
+				let hex: String = chars.by_ref().take(2).collect();
+				bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+			}
+			_ => return None,
+		}
+	}
+	path_from_bytes(bytes)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+	use std::os::unix::ffi::OsStringExt;
+
+	Some(std::ffi::OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+	String::from_utf8(bytes).ok().map(PathBuf::from)
+}
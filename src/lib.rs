@@ -1,7 +1,38 @@
+pub mod archive;
+pub mod check;
+pub mod commands;
+pub mod diagnostics;
+pub mod diff;
+pub mod elision;
+pub mod encoding;
 pub mod error;
 pub mod fsnode;
 pub mod filesystem;
+pub mod filter;
+pub mod journal;
+pub mod line_ending;
+pub mod normalize;
+pub mod pack;
+pub mod parallel;
+pub mod prose;
+pub mod rust;
+pub mod safety;
+pub mod stream;
+pub mod target;
+pub mod truncation;
 
-pub use error::{Error, Result};
-pub use fsnode::{FsNode, NodeRef, WeakNodeRef};
-pub use filesystem::FileSystem;
\ No newline at end of file
+pub use archive::{ArchiveFormat, ArchiveOptions, ArchiveWriter};
+pub use commands::{Chmod, ShellCommand};
+pub use diagnostics::{Diagnostic, DiagnosticKind, Severity};
+pub use encoding::Encoding;
+pub use error::{Error, ParseError, Result};
+pub use fsnode::{FsNode, Node, NodeId, Span};
+pub use filesystem::{FileSystem, ParseOptions};
+pub use filter::{BuiltinFilter, ContentFilter, FilterChain};
+pub use journal::{Journal, JournalEntry};
+pub use line_ending::LineEnding;
+pub use parallel::{ParallelOptions, WriteFailure};
+pub use prose::DroppedText;
+pub use rust::{RustIssue, RustIssueKind};
+pub use stream::BundleStream;
+pub use target::{DiskTarget, ExtractTarget, MemoryEntry, MemoryTarget};
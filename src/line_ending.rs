@@ -0,0 +1,95 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use crate::error::Error;
+
+/// The byte order mark some editors put at the start of UTF-8 text
+pub const BOM: char = '\u{feff}';
+
+/// How the lines of a file end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+	/// `\n`, as on Unix
+	Lf,
+	/// `\r\n`, as on Windows
+	Crlf,
+}
+
+impl LineEnding {
+	/// Returns the style most lines of the contents end with, or `None` if
+	/// they have no line breaks
+	pub fn detect(contents: &str) -> Option<Self> {
+		let crlf = contents.matches("\r\n").count();
+		let lf = contents.matches('\n').count() - crlf;
+		match (lf, crlf) {
+			(0, 0) => None,
+			(lf, crlf) if crlf > lf => Some(LineEnding::Crlf),
+			_ => Some(LineEnding::Lf),
+		}
+	}
+
+	/// Returns the characters that end a line
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			LineEnding::Lf => "\n",
+			LineEnding::Crlf => "\r\n",
+		}
+	}
+
+	/// Returns the contents with every line ending, CR alone included,
+	/// converted to this style
+	pub fn convert<'c>(&self, contents: &'c str) -> Cow<'c, str> {
+		let converted = match self {
+			LineEnding::Lf if contents.contains('\r') => {
+				contents.replace("\r\n", "\n").replace('\r', "\n")
+			}
+			LineEnding::Crlf if contents.replace("\r\n", "").contains(['\r', '\n']) => {
+				let lf = LineEnding::Lf.convert(contents);
+				lf.replace('\n', "\r\n")
+			}
+			_ => return Cow::Borrowed(contents),
+		};
+		Cow::Owned(converted)
+	}
+}
+
+impl fmt::Display for LineEnding {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LineEnding::Lf => write!(f, "lf"),
+			LineEnding::Crlf => write!(f, "crlf"),
+		}
+	}
+}
+
+impl FromStr for LineEnding {
+	type Err = Error;
+
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		match name.to_lowercase().as_str() {
+			"lf" => Ok(LineEnding::Lf),
+			"crlf" => Ok(LineEnding::Crlf),
+			_ => Err(Error::Filter(format!("unknown line ending: {}", name))),
+		}
+	}
+}
+
+// Splits input at its first blank line into the header and the body,
+// accepting either line ending
+pub(crate) fn split_header(input: &str) -> (&str, &str) {
+	let lf = input.find("\n\n").map(|i| (i, 2));
+	let crlf = input.find("\n\r\n").map(|i| (i, 3));
+	let split = match (lf, crlf) {
+		(Some(lf), Some(crlf)) => Some(lf.min(crlf)),
+		(lf, crlf) => lf.or(crlf),
+	};
+	match split {
+		Some((i, len)) => {
+			let header = &input[..i];
+			(
+				header.strip_suffix('\r').unwrap_or(header),
+				&input[i + len..],
+			)
+		}
+		None => (input, ""),
+	}
+}
@@ -1,12 +1,26 @@
 use anyhow::Context;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::{
+	collections::HashSet,
 	fs,
-	io::{self, Read},
-	path::PathBuf,
+	io::{self, BufRead, BufReader, BufWriter, Read, Write},
+	num::NonZeroUsize,
+	path::{Path, PathBuf},
 };
 
-use claudeai_bundle::{Error, FileSystem, FsNode};
+use claudeai_bundle::{
+	ArchiveFormat, ArchiveOptions, BuiltinFilter, BundleStream, Diagnostic, DiskTarget, Error,
+	FileSystem, FilterChain, Journal, LineEnding, Node, ParallelOptions, ParseOptions, Severity,
+	archive, check,
+	commands::setup_script,
+	diff::{self, ChangeKind, NodeChange},
+	elision,
+	line_ending::BOM,
+	pack,
+};
+
+// Where `extract --write-setup-script` puts the commands from the prose
+const SETUP_SCRIPT: &str = "setup.sh";
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -15,8 +29,10 @@ struct Cli {
 	command: Option<Commands>,
 
 	/// Path to a file containing tree output
+	///
+	/// May be given multiple times to overlay later bundles on earlier ones.
 	#[arg(short, long)]
-	file: Option<PathBuf>,
+	file: Vec<PathBuf>,
 
 	/// Prefix to strip from each line in the header (e.g. "# ")
 	#[arg(long)]
@@ -25,6 +41,29 @@ struct Cli {
 	/// Enable debug mode with verbose output
 	#[arg(long, default_value_t = false)]
 	debug: bool,
+
+	/// Fail on any problem found while parsing, warnings included
+	#[arg(long)]
+	strict: bool,
+
+	/// Keep line-number gutters, blockquote markers and shared indentation in
+	/// file contents rather than removing them
+	#[arg(long)]
+	no_normalize: bool,
+
+	/// Clean-up filter to run on every file's contents, in the order given
+	///
+	/// One of line-endings, trailing-newline, tabs-to-spaces[=WIDTH],
+	/// strip-trailing-whitespace or dedent. May be given multiple times. To
+	/// configure filters once, set the CLAUDEAI_BUNDLE_FILTERS environment
+	/// variable to a comma-separated list; --filter replaces it.
+	#[arg(
+		long = "filter",
+		value_name = "FILTER",
+		env = "CLAUDEAI_BUNDLE_FILTERS",
+		value_delimiter = ','
+	)]
+	filters: Vec<BuiltinFilter>,
 }
 
 #[derive(Subcommand)]
@@ -45,56 +84,858 @@ enum Commands {
 		/// The path to the file to display
 		path: String,
 	},
+	/// Show the prose and fences between files that were left out of them
+	Dropped,
+	/// Print the shell commands found in the prose between files
+	#[command(name = "commands")]
+	CommandList,
 	/// Extract files and directories to the filesystem
 	Extract {
 		/// Directory to extract files to
 		#[arg(long, alias = "output_dir", default_value = ".")]
 		output_directory: PathBuf,
+
+		/// Do not record a journal of the changes for `undo`
+		#[arg(long)]
+		no_journal: bool,
+
+		/// Write this many files at once after creating the directories
+		#[arg(short, long)]
+		jobs: Option<NonZeroUsize>,
+
+		/// Stop writing files after the first one that fails
+		#[arg(long, requires = "jobs")]
+		fail_fast: bool,
+
+		/// Do not write files that look cut off part way through
+		#[arg(long)]
+		skip_truncated: bool,
+
+		/// Also write the commands found in the prose to setup.sh, without
+		/// running them
+		#[arg(long)]
+		write_setup_script: bool,
+
+		#[command(flatten)]
+		output: OutputOptions,
+
+		/// Write the files to a tar, tar.gz or zip archive instead of a
+		/// directory, or to stdout with `-`
+		#[arg(
+			long,
+			value_name = "FILE",
+			conflicts_with_all = ["output_directory", "no_journal", "jobs", "write_setup_script"]
+		)]
+		archive: Option<PathBuf>,
+
+		/// Format of the archive, one of tar, tar.gz or zip; taken from its
+		/// extension by default, and tar on stdout
+		#[arg(long, value_name = "FORMAT", requires = "archive")]
+		archive_format: Option<ArchiveFormat>,
+	},
+	/// Compare the bundle against a directory on disk
+	///
+	/// Exits with status 1 when there are differences.
+	Diff {
+		/// Directory to compare against
+		#[arg(default_value = ".")]
+		directory: PathBuf,
+
+		/// Show a summary of changed lines per file instead of diffs
+		#[arg(long, conflicts_with = "name_only")]
+		stat: bool,
+
+		/// Show only the paths of changed files
+		#[arg(long)]
+		name_only: bool,
+
+		#[command(flatten)]
+		output: OutputOptions,
+	},
+	/// Compare two bundle files structurally
+	///
+	/// Exits with status 1 when there are differences.
+	Compare {
+		/// The earlier bundle
+		old: PathBuf,
+
+		/// The later bundle
+		new: PathBuf,
+
+		/// Also show unified diffs of changed and renamed files
+		#[arg(short, long)]
+		patch: bool,
+
+		#[command(flatten)]
+		output: OutputOptions,
+	},
+	/// Check the bundle for problems before extracting it
+	///
+	/// Exits with status 1 when there are warnings and 2 when there are
+	/// errors. Notes, such as a file with no trailing newline, leave it at 0.
+	Check {
+		/// Print one JSON object per problem instead of annotated source
+		#[arg(long)]
+		json: bool,
+	},
+	/// Write a directory on disk, or a tar or zip archive, out as a bundle
+	///
+	/// Binary files, and text that would not survive a plain section, are
+	/// written as base64 sections.
+	Pack {
+		/// Directory, or tar, tar.gz or zip archive, to pack
+		#[arg(default_value = ".")]
+		directory: PathBuf,
+
+		/// File to write the bundle to instead of stdout
+		#[arg(short, long)]
+		output: Option<PathBuf>,
+	},
+	/// Revert the last extraction using its journal
+	Undo {
+		/// Directory the bundle was extracted to
+		#[arg(long, alias = "output_dir", default_value = ".")]
+		output_directory: PathBuf,
 	},
 }
 
-fn main() -> anyhow::Result<()> {
-	let cli = Cli::parse();
+// How file contents are written out, which `diff` and `compare` apply too so
+// they compare what `extract` would write
+#[derive(Args)]
+struct OutputOptions {
+	/// Line endings to write files with; each file keeps its own by default
+	#[arg(long, value_enum, default_value_t = LineEndings::Preserve)]
+	line_endings: LineEndings,
 
-	// Get tree output from file or stdin
-	let tree_output = if let Some(file_path) = cli.file {
-		fs::read_to_string(&file_path).with_context(|| {
-			format!(
-				"Failed to read tree output from file: {}",
-				file_path.display()
-			)
-		})?
-	}
-	else {
-		let mut buffer = String::new();
-		io::stdin()
-			.read_to_string(&mut buffer)
-			.context("Failed to read tree output from stdin")?;
-		buffer
-	};
+	/// Leave files that do not end with a newline as they are
+	#[arg(long)]
+	no_final_newline: bool,
+}
 
+// Line endings extracted files are written with
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LineEndings {
+	/// Keep the line endings each file has in the bundle
+	Preserve,
+	/// Convert to `\n`
+	Lf,
+	/// Convert to `\r\n`
+	Crlf,
+}
+
+// Returns the comment prefix given on the command line, or "// " when none is
+// given and the bundle starts with it
+fn detect_comment_prefix<'c>(tree_output: &str, cli: &'c Cli) -> Option<&'c str> {
 	// For the simple_tree.txt example, we need to detect and apply the comment
 	// prefix
-	let comment_prefix = if tree_output.starts_with("// ") && cli.comment_prefix.is_none() {
+	let comment_prefix = cli.comment_prefix.as_deref();
+	if tree_output.trim_start_matches(BOM).starts_with("// ") && comment_prefix.is_none() {
 		if cli.debug {
 			println!("Auto-detected comment prefix: \"// \"");
 		}
 		Some("// ")
 	}
 	else {
-		cli.comment_prefix.as_deref()
+		comment_prefix
+	}
+}
+
+// Returns the chain of filters selected on the command line, followed when
+// extracting or comparing by the line-ending conversion and final newline
+// asked for
+fn filter_chain(cli: &Cli) -> FilterChain {
+	let mut chain: FilterChain = cli.filters.iter().copied().collect();
+	if let Some(
+		Commands::Extract { output, .. }
+		| Commands::Diff { output, .. }
+		| Commands::Compare { output, .. },
+	) = &cli.command
+	{
+		let OutputOptions {
+			line_endings,
+			no_final_newline,
+		} = output;
+		match line_endings {
+			LineEndings::Preserve => {}
+			LineEndings::Lf => chain.push(BuiltinFilter::LineEndings(LineEnding::Lf)),
+			LineEndings::Crlf => chain.push(BuiltinFilter::LineEndings(LineEnding::Crlf)),
+		}
+		if !no_final_newline {
+			chain.push(BuiltinFilter::TrailingNewline);
+		}
+	}
+	chain
+}
+
+// Parses a bundle, auto-detecting the "// " comment prefix when none is given,
+// and runs the selected filters on its contents
+fn parse_bundle<'a>(tree_output: &'a str, cli: &Cli) -> anyhow::Result<FileSystem<'a>> {
+	let mut fs = parse_unfiltered(tree_output, cli)?;
+	let filters = filter_chain(cli);
+	if !filters.is_empty() {
+		fs.apply_filters(&filters);
+	}
+	Ok(fs)
+}
+
+// Options for parsing bundles, from the global flags
+fn parse_options(cli: &Cli) -> ParseOptions {
+	ParseOptions {
+		normalize: !cli.no_normalize,
+	}
+}
+
+// Parses a bundle without running any filters
+fn parse_unfiltered<'a>(tree_output: &'a str, cli: &Cli) -> anyhow::Result<FileSystem<'a>> {
+	let comment_prefix = detect_comment_prefix(tree_output, cli);
+
+	let (fs, diagnostics) =
+		FileSystem::parse_with_options(tree_output, comment_prefix, cli.debug, parse_options(cli));
+	if !cli.strict {
+		return match diagnostics.into_iter().find(Diagnostic::is_error) {
+			Some(error) => Err(Error::Syntax(error.into())).context("Failed to parse tree output"),
+			None => Ok(fs),
+		};
+	}
+
+	// In strict mode every problem is reported and fails the parse
+	if !diagnostics.is_empty() {
+		for diagnostic in &diagnostics {
+			eprintln!("{}\n", diagnostic);
+		}
+		return Err(anyhow::anyhow!(
+			"Failed to parse tree output: {} problems found in strict mode",
+			diagnostics.len()
+		));
+	}
+	Ok(fs)
+}
+
+// A bundle to be parsed, or a tar or zip archive already read into a
+// filesystem
+enum Input {
+	Bundle(String),
+	Archive(FileSystem<'static>),
+}
+
+impl Input {
+	// Reads an archive recognized from its first bytes, or else a bundle
+	fn read(bytes: Vec<u8>, name: &str) -> anyhow::Result<Self> {
+		if let Some(format) = ArchiveFormat::detect(&bytes) {
+			let fs = archive::read_archive(bytes.as_slice(), format)
+				.with_context(|| format!("Failed to read {} archive: {}", format, name))?;
+			return Ok(Input::Archive(fs));
+		}
+		let bundle = String::from_utf8(bytes)
+			.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+			.with_context(|| format!("Failed to read tree output from {}", name))?;
+		Ok(Input::Bundle(bundle))
+	}
+
+	// Parses a bundle with `parse_bundle`; archives are used as they are,
+	// without filters
+	fn parse(&self, cli: &Cli) -> anyhow::Result<FileSystem<'_>> {
+		match self {
+			Input::Bundle(bundle) => parse_bundle(bundle, cli),
+			Input::Archive(fs) => Ok(fs.clone()),
+		}
+	}
+}
+
+// Reads a bundle or an archive from a file
+fn read_bundle(file_path: &Path) -> anyhow::Result<Input> {
+	let bytes = fs::read(file_path).with_context(|| {
+		format!(
+			"Failed to read tree output from file: {}",
+			file_path.display()
+		)
+	})?;
+	Input::read(bytes, &format!("file: {}", file_path.display()))
+}
+
+// Writes the nodes of a bundle to the output directory, journaling them
+// unless asked not to
+struct Extraction {
+	output_directory: PathBuf,
+	journal: Option<Journal>,
+	partial_files: usize,
+	skip_truncated: bool,
+	debug: bool,
+	// Paths written so far, relative to the output directory
+	written: HashSet<PathBuf>,
+}
+
+impl Extraction {
+	fn start(
+		output_directory: &Path,
+		no_journal: bool,
+		skip_truncated: bool,
+		debug: bool,
+	) -> anyhow::Result<Self> {
+		// Create the root output directory
+		if debug {
+			println!("Using output directory: {}", output_directory.display());
+		}
+
+		// Ensure the output directory exists before extracting
+		fs::create_dir_all(output_directory).with_context(|| {
+			format!(
+				"Failed to create output directory: {}",
+				output_directory.display()
+			)
+		})?;
+
+		if debug {
+			println!(
+				"Ensured output directory exists: {}",
+				output_directory.display()
+			);
+		}
+
+		// Record every change so the extraction can be undone
+		let journal = if no_journal {
+			None
+		}
+		else {
+			Some(Journal::new(output_directory).context("Failed to start extraction journal")?)
+		};
+
+		Ok(Extraction {
+			output_directory: output_directory.to_path_buf(),
+			journal,
+			partial_files: 0,
+			skip_truncated,
+			debug,
+			written: HashSet::new(),
+		})
+	}
+
+	fn write(&mut self, node: Node<'_>) -> anyhow::Result<()> {
+		// Skip the root node
+		if node.is_root() || self.skip(node) {
+			return Ok(());
+		}
+
+		if self.debug {
+			let path = node.relative_location();
+			println!("Writing {} to disk", path.display());
+		}
+
+		// Write the node to disk, through the journal when there is one
+		let written = match self.journal.as_mut() {
+			Some(journal) => journal.write_node(node),
+			None => node.write_to_disk(&self.output_directory),
+		};
+
+		// Partial files are reported and left alone rather than overwritten
+		if let Err(Error::PartialFile(path)) = &written {
+			println!("Partial file not written: {}", path);
+			self.partial_files += 1;
+			return Ok(());
+		}
+
+		written.with_context(|| {
+			format!(
+				"Failed to write {} to disk",
+				node.relative_location().display()
+			)
+		})?;
+
+		if self.debug {
+			println!("Successfully wrote: {}", node.relative_location().display());
+		}
+		self.written.insert(node.relative_location());
+		Ok(())
+	}
+
+	// Sets the modes known only once the whole bundle is read on the nodes
+	// already written
+	fn write_modes(&self, fs: &FileSystem<'_>) -> anyhow::Result<()> {
+		for node in fs.nodes() {
+			if node.mode().is_some() && self.written.contains(&node.relative_location()) {
+				node.write_mode(&self.output_directory).with_context(|| {
+					format!(
+						"Failed to set the mode of {}",
+						node.relative_location().display()
+					)
+				})?;
+			}
+		}
+		Ok(())
+	}
+
+	// Removes the files the bundle marks `(deleted)`, through the journal
+	// when there is one so undo brings them back
+	fn remove_deleted(&mut self, fs: &FileSystem<'_>) -> anyhow::Result<()> {
+		match self.journal.as_mut() {
+			Some(journal) => fs.remove_deleted(journal),
+			None => fs.remove_deleted(&mut DiskTarget::new(&self.output_directory)),
+		}
+		.context("Failed to remove deleted files")
+	}
+
+	// Reports a file that looks truncated, returning true if it should not
+	// be written
+	fn skip(&self, node: Node<'_>) -> bool {
+		let Some(reason) = node.truncation()
+		else {
+			return false;
+		};
+
+		let path = node.relative_location();
+		if self.skip_truncated {
+			println!(
+				"Truncated file not written: {} ({})",
+				path.display(),
+				reason
+			);
+			true
+		}
+		else {
+			println!("Warning: {} looks truncated ({})", path.display(), reason);
+			false
+		}
+	}
+
+	// Writes the whole filesystem with several workers, reporting every file
+	// that fails rather than stopping at the first
+	fn write_parallel(
+		&mut self,
+		fs: &mut FileSystem<'_>,
+		options: ParallelOptions,
+	) -> anyhow::Result<()> {
+		// Truncated files are dropped from the tree before any worker starts
+		let skipped: Vec<String> = fs
+			.nodes()
+			.filter(|&node| self.skip(node))
+			.map(|node| node.relative_location().to_string_lossy().into_owned())
+			.collect();
+		for path in skipped {
+			fs.remove(&path)?;
+		}
+
+		let failures = match self.journal.as_mut() {
+			Some(journal) => journal.write_parallel(fs, options),
+			None => fs.write_to_disk_parallel(&self.output_directory, options),
+		}
+		.context("Failed to create directories")?;
+
+		let mut failed_files = 0;
+		for failure in failures {
+			match failure.error {
+				// Partial files are reported and left alone rather than overwritten
+				Error::PartialFile(path) => {
+					println!("Partial file not written: {}", path);
+					self.partial_files += 1;
+				}
+				error => {
+					eprintln!(
+						"Failed to write {} to disk: {}",
+						failure.path.display(),
+						error
+					);
+					failed_files += 1;
+				}
+			}
+		}
+
+		if failed_files > 0 {
+			return Err(anyhow::anyhow!(
+				"{} files could not be written",
+				failed_files
+			));
+		}
+		Ok(())
+	}
+
+	fn finish(self, result: anyhow::Result<()>) -> anyhow::Result<()> {
+		// Save the journal even if a write failed so it can still be undone
+		if let Some(journal) = &self.journal {
+			journal
+				.save()
+				.context("Failed to save extraction journal")?;
+		}
+		result?;
+
+		println!("Extracted to: {}", self.output_directory.display());
+
+		if self.partial_files > 0 {
+			return Err(anyhow::anyhow!(
+				"{} partial files with elided code were not written",
+				self.partial_files
+			));
+		}
+		Ok(())
+	}
+}
+
+// Writes the bundle to an archive file, or to stdout for `-`. Truncated
+// files are left out when asked and partial ones always, as there is nothing
+// on disk to fill in their elided code. Messages go to stderr so they cannot
+// end up in an archive on stdout.
+fn extract_archive(
+	fs: &mut FileSystem<'_>,
+	archive: &Path,
+	format: Option<ArchiveFormat>,
+	skip_truncated: bool,
+) -> anyhow::Result<()> {
+	let to_stdout = archive == Path::new("-");
+	let format = match format.or_else(|| ArchiveFormat::from_path(archive)) {
+		Some(format) => format,
+		None if to_stdout => ArchiveFormat::Tar,
+		None => {
+			return Err(anyhow::anyhow!(
+				"Cannot tell the archive format of {}, pass --archive-format",
+				archive.display()
+			));
+		}
+	};
+
+	let mut skipped = Vec::new();
+	let mut partial_files = 0;
+	for node in fs.nodes() {
+		let path = node.relative_location();
+		if let Some(reason) = node.truncation() {
+			if skip_truncated {
+				eprintln!(
+					"Truncated file not written: {} ({})",
+					path.display(),
+					reason
+				);
+				skipped.push(path);
+				continue;
+			}
+			eprintln!("Warning: {} looks truncated ({})", path.display(), reason);
+		}
+		if node.encoding().is_none() && node.contents().is_some_and(elision::has_elisions) {
+			eprintln!("Partial file not written: {}", path.display());
+			skipped.push(path);
+			partial_files += 1;
+		}
+	}
+	for path in skipped {
+		fs.remove(&path.to_string_lossy())?;
+	}
+
+	let options = ArchiveOptions::from_env()?;
+	if to_stdout {
+		archive::write_archive(fs, io::stdout().lock(), format, options)?.flush()?;
+	}
+	else {
+		let file = fs::File::create(archive)
+			.with_context(|| format!("Failed to create archive: {}", archive.display()))?;
+		archive::write_archive(fs, BufWriter::new(file), format, options)
+			.with_context(|| format!("Failed to write archive: {}", archive.display()))?
+			.flush()?;
+		println!("Archived to: {}", archive.display());
+	}
+
+	if partial_files > 0 {
+		return Err(anyhow::anyhow!(
+			"{} partial files with elided code were not written",
+			partial_files
+		));
+	}
+	Ok(())
+}
+
+// Writes the commands found in a bundle's prose to the setup script, for
+// the user to review and run themselves
+fn write_setup_script(extraction: &mut Extraction, fs: &FileSystem<'_>) -> anyhow::Result<()> {
+	if fs
+		.nodes()
+		.any(|node| node.relative_location() == Path::new(SETUP_SCRIPT))
+	{
+		return Err(anyhow::anyhow!(
+			"The bundle already has a {}, not overwriting it",
+			SETUP_SCRIPT
+		));
+	}
+
+	let commands = fs.shell_commands();
+	let mut script = FileSystem::new();
+	script.add_file(SETUP_SCRIPT, Some(setup_script(&commands).into()))?;
+	extraction.write(script.get_node(SETUP_SCRIPT)?)?;
+	println!(
+		"Wrote {} commands to {} for review",
+		commands.len(),
+		SETUP_SCRIPT
+	);
+	Ok(())
+}
+
+// Extracts a single bundle while it is still being read, writing each file
+// as soon as its section ends
+fn extract_stream(
+	mut reader: impl BufRead,
+	cli: &Cli,
+	output_directory: &Path,
+	no_journal: bool,
+	skip_truncated: bool,
+	write_setup: bool,
+) -> anyhow::Result<()> {
+	// Auto-detect the "// " comment prefix from the start of the input, after
+	// any byte order mark
+	let start = reader.fill_buf()?;
+	let start = start
+		.strip_prefix(BOM.to_string().as_bytes())
+		.unwrap_or(start);
+	// Archives are read whole and written like a parsed bundle
+	if let Some(format) = ArchiveFormat::detect(start) {
+		let fs = archive::read_archive(reader, format)
+			.with_context(|| format!("Failed to read {} archive", format))?;
+		let mut extraction =
+			Extraction::start(output_directory, no_journal, skip_truncated, cli.debug)?;
+		let result = fs.nodes().try_for_each(|node| extraction.write(node));
+		return extraction.finish(result);
+	}
+	let commented = start.starts_with(b"// ");
+	let comment_prefix = match cli.comment_prefix.as_deref() {
+		None if commented => {
+			if cli.debug {
+				println!("Auto-detected comment prefix: \"// \"");
+			}
+			Some("// ")
+		}
+		comment_prefix => comment_prefix,
+	};
+
+	let mut stream = BundleStream::new(reader, comment_prefix, cli.debug)
+		.context("Failed to parse tree output")?
+		.with_filters(filter_chain(cli))
+		.with_options(parse_options(cli));
+	let mut extraction =
+		Extraction::start(output_directory, no_journal, skip_truncated, cli.debug)?;
+
+	let result = (|| {
+		// Directories first, the header lists them all before any contents
+		for node in stream.filesystem().nodes() {
+			if node.is_directory() {
+				extraction.write(node)?;
+			}
+		}
+		while let Some(node) = stream.next_file().context("Failed to read tree output")? {
+			extraction.write(node)?;
+		}
+		extraction.write_modes(stream.filesystem())?;
+		extraction.remove_deleted(stream.filesystem())?;
+		if write_setup {
+			write_setup_script(&mut extraction, stream.filesystem())?;
+		}
+		anyhow::Ok(())
+	})();
+
+	extraction.finish(result)
+}
+
+fn main() -> anyhow::Result<()> {
+	let cli = Cli::parse();
+
+	// Undo works from the journal alone and does not read a bundle
+	if let Some(Commands::Undo { output_directory }) = &cli.command {
+		let kept = Journal::load(output_directory)
+			.and_then(Journal::undo)
+			.with_context(|| {
+				format!(
+					"Failed to undo extraction in: {}",
+					output_directory.display()
+				)
+			})?;
+
+		for path in kept {
+			println!("Kept non-empty directory: {}", path.display());
+		}
+		println!("Reverted extraction in: {}", output_directory.display());
+		return Ok(());
+	}
+
+	// Pack reads a directory rather than a bundle
+	if let Some(Commands::Pack { directory, output }) = &cli.command {
+		let fs = if directory.is_file() {
+			match read_bundle(directory)? {
+				Input::Archive(fs) => fs,
+				Input::Bundle(_) => {
+					return Err(anyhow::anyhow!(
+						"Not a directory or an archive: {}",
+						directory.display()
+					));
+				}
+			}
+		}
+		else {
+			pack::read_directory(directory)
+				.with_context(|| format!("Failed to read directory: {}", directory.display()))?
+		};
+		let bundle = pack::pack(&fs);
+		match output {
+			Some(output) => fs::write(output, bundle)
+				.with_context(|| format!("Failed to write bundle: {}", output.display()))?,
+			None => print!("{}", bundle),
+		}
+		return Ok(());
+	}
+
+	// Compare reads both of its bundles from the files it is given
+	if let Some(Commands::Compare {
+		old, new, patch, ..
+	}) = &cli.command
+	{
+		let (old_input, new_input) = (read_bundle(old)?, read_bundle(new)?);
+		let old_fs = old_input.parse(&cli)?;
+		let new_fs = new_input.parse(&cli)?;
+		let changes = diff::diff_filesystems(&old_fs, &new_fs);
+
+		for change in &changes {
+			match change {
+				NodeChange::Added { path, .. } => println!("A {}", path.display()),
+				NodeChange::Removed { path, .. } => println!("D {}", path.display()),
+				NodeChange::ContentsChanged { path, .. } => println!("M {}", path.display()),
+				NodeChange::TypeChanged {
+					path,
+					old_kind,
+					new_kind,
+				} => println!("T {} ({} -> {})", path.display(), old_kind, new_kind),
+				NodeChange::Renamed {
+					from,
+					to,
+					similarity,
+					..
+				} => println!(
+					"R {} -> {} ({:.0}%)",
+					from.display(),
+					to.display(),
+					similarity * 100.0
+				),
+			}
+		}
+
+		if *patch {
+			for diff in changes.iter().filter_map(NodeChange::unified_diff) {
+				print!("\n{}", diff);
+			}
+		}
+
+		// Let scripts detect differences from the exit status
+		if !changes.is_empty() {
+			std::process::exit(1);
+		}
+		return Ok(());
+	}
+
+	// A single bundle is extracted as it is read rather than parsed up front,
+	// unless its files are to be written in parallel or it must be checked
+	// for problems before anything is written
+	if let Some(Commands::Extract {
+		output_directory,
+		no_journal,
+		jobs: None,
+		skip_truncated,
+		write_setup_script: write_setup,
+		archive: None,
+		..
+	}) = &cli.command
+		&& cli.file.len() <= 1
+		&& !cli.strict
+	{
+		return match cli.file.first() {
+			Some(file_path) => {
+				let file = fs::File::open(file_path).with_context(|| {
+					format!(
+						"Failed to read tree output from file: {}",
+						file_path.display()
+					)
+				})?;
+				extract_stream(
+					BufReader::new(file),
+					&cli,
+					output_directory,
+					*no_journal,
+					*skip_truncated,
+					*write_setup,
+				)
+			}
+			None => extract_stream(
+				io::stdin().lock(),
+				&cli,
+				output_directory,
+				*no_journal,
+				*skip_truncated,
+				*write_setup,
+			),
+		};
+	}
+
+	// Get tree output from the files or from stdin, kept alive for the parsed
+	// filesystem to borrow from
+	let inputs = if cli.file.is_empty() {
+		let mut buffer = Vec::new();
+		io::stdin()
+			.read_to_end(&mut buffer)
+			.context("Failed to read tree output from stdin")?;
+		vec![Input::read(buffer, "stdin")?]
+	}
+	else {
+		cli.file
+			.iter()
+			.map(|file_path| read_bundle(file_path))
+			.collect::<anyhow::Result<Vec<_>>>()?
 	};
 
-	// Parse the tree output
-	let fs = FileSystem::parse(&tree_output, comment_prefix, cli.debug)
-		.context("Failed to parse tree output")?;
+	// Check reports on each bundle as written rather than the overlaid tree
+	if let Some(Commands::Check { json }) = &cli.command {
+		let mut diagnostics = Vec::new();
+		// Archives have no header or sections to check
+		for input in &inputs {
+			if let Input::Bundle(input) = input {
+				diagnostics.extend(check::check_with_options(
+					input,
+					detect_comment_prefix(input, &cli),
+					parse_options(&cli),
+				));
+			}
+		}
+
+		for (i, diagnostic) in diagnostics.iter().enumerate() {
+			if *json {
+				println!("{}", diagnostic.to_json());
+			}
+			else {
+				if i > 0 {
+					println!();
+				}
+				println!("{}", diagnostic);
+			}
+		}
+
+		// Let scripts tell warnings from errors by the exit status
+		if diagnostics.iter().any(Diagnostic::is_error) {
+			std::process::exit(2);
+		}
+		if diagnostics
+			.iter()
+			.any(|diagnostic| diagnostic.severity > Severity::Note)
+		{
+			std::process::exit(1);
+		}
+		return Ok(());
+	}
+
+	// Overlay later bundles on earlier ones in order
+	let mut fs = inputs[0].parse(&cli)?;
+	for (file_path, input) in cli.file.iter().zip(&inputs).skip(1) {
+		if cli.debug {
+			println!("Overlaying bundle: {}", file_path.display());
+		}
+		let overlay = input.parse(&cli)?;
+		fs.overlay(&overlay)
+			.with_context(|| format!("Failed to overlay bundle: {}", file_path.display()))?;
+	}
 
 	// Process command or default to print
 	match cli.command {
-		Some(Commands::Find { path }) => println!(
-			"{}",
-			fs.get_node(&path)?.borrow().relative_location().display()
-		),
+		Some(Commands::Find { path }) => {
+			println!("{}", fs.get_node(&path)?.relative_location().display())
+		}
 		Some(Commands::List { tree }) => {
 			if tree {
 				if cli.debug {
@@ -102,14 +943,30 @@ fn main() -> anyhow::Result<()> {
 				}
 				// Display in hierarchical tree format
 				println!("{}", fs.tree_output());
+				for node in fs.nodes() {
+					if let Some(reason) = node.truncation() {
+						eprintln!(
+							"Warning: {} looks truncated ({})",
+							node.relative_location().display(),
+							reason
+						);
+					}
+				}
 			}
 			else {
 				if cli.debug {
 					println!("Printing list hierarchy");
 				}
-				// Display flat list
+				// Display flat list, flagging files that look cut off
 				for node in fs.nodes() {
-					println!("{}", node.borrow().relative_location().display());
+					match node.truncation() {
+						Some(reason) => println!(
+							"{} (truncated: {})",
+							node.relative_location().display(),
+							reason
+						),
+						None => println!("{}", node.relative_location().display()),
+					}
 				}
 			}
 		}
@@ -125,12 +982,17 @@ fn main() -> anyhow::Result<()> {
 				}
 			};
 
-			let node_ref = node.borrow();
-
-			if let Some(contents) = node_ref.contents() {
+			if let Some(contents) = node.contents() {
 				println!("{}", contents);
 			}
-			else if node_ref.is_directory() {
+			else if let Some(bytes) = node.bytes() {
+				// Binary contents are written out as they are
+				io::stdout().write_all(bytes)?;
+			}
+			else if let Some(target) = node.link_target() {
+				println!("Symlink to: {}", target);
+			}
+			else if node.is_directory() {
 				println!("Cannot display contents of directory: {}", path);
 				return Err(anyhow::anyhow!(Error::InvalidNodeType)
 					.context(format!("Cannot display contents of directory: {}", path)));
@@ -139,58 +1001,106 @@ fn main() -> anyhow::Result<()> {
 				println!("File has no contents: {}", path);
 			}
 		}
-		Some(Commands::Extract { output_directory }) => {
-			// Create the root output directory
-			if cli.debug {
-				println!("Using output directory: {}", output_directory.display());
+		Some(Commands::Dropped) => {
+			for (i, dropped) in fs.dropped_text().iter().enumerate() {
+				if i > 0 {
+					println!();
+				}
+				println!("--> line {}", dropped.line);
+				println!("{}", dropped.text);
 			}
-
-			// Ensure the output directory exists before extracting
-			fs::create_dir_all(&output_directory).with_context(|| {
-				format!(
-					"Failed to create output directory: {}",
-					output_directory.display()
-				)
-			})?;
-
-			if cli.debug {
-				println!(
-					"Ensured output directory exists: {}",
-					output_directory.display()
-				);
+		}
+		Some(Commands::CommandList) => {
+			for command in fs.shell_commands() {
+				println!("{}", command.command);
 			}
-
-			// Process all nodes using the write_to_disk method
-			for node in fs.nodes() {
-				let node_ref = node.borrow();
-
-				// Skip the root node
-				if matches!(*node_ref, FsNode::Root) {
-					continue;
+		}
+		Some(Commands::Extract {
+			output_directory,
+			no_journal,
+			jobs,
+			fail_fast,
+			skip_truncated,
+			write_setup_script: write_setup,
+			archive,
+			archive_format,
+			..
+		}) => {
+			if let Some(archive) = archive {
+				return extract_archive(&mut fs, &archive, archive_format, skip_truncated);
+			}
+			let mut extraction =
+				Extraction::start(&output_directory, no_journal, skip_truncated, cli.debug)?;
+			let result = match jobs {
+				Some(workers) => {
+					extraction.write_parallel(&mut fs, ParallelOptions { workers, fail_fast })
 				}
+				None => fs.nodes().try_for_each(|node| extraction.write(node)),
+			};
+			let result = result.and_then(|()| extraction.remove_deleted(&fs));
+			let result = match result {
+				Ok(()) if write_setup => write_setup_script(&mut extraction, &fs),
+				result => result,
+			};
+			extraction.finish(result)?;
+		}
+		Some(Commands::Diff {
+			directory,
+			stat,
+			name_only,
+			..
+		}) => {
+			let changes = diff::diff_directory(&fs, &directory)
+				.with_context(|| format!("Failed to compare with: {}", directory.display()))?;
 
-				if cli.debug {
-					let path = node_ref.relative_location();
-					println!("Writing {} to disk", path.display());
+			if name_only {
+				for change in &changes {
+					println!("{}", change.path.display());
 				}
-
-				// Write the node to disk
-				node_ref.write_to_disk(&output_directory).with_context(|| {
-					format!(
-						"Failed to write {} to disk",
-						node_ref.relative_location().display()
-					)
-				})?;
-
-				if cli.debug {
+			}
+			else if stat {
+				let mut total_insertions = 0;
+				let mut total_deletions = 0;
+				for change in &changes {
+					let (insertions, deletions) = change.line_counts();
+					total_insertions += insertions;
+					total_deletions += deletions;
+					let marker = match change.kind {
+						ChangeKind::Added => "A",
+						ChangeKind::Removed => "D",
+						ChangeKind::Modified => "M",
+					};
 					println!(
-						"Successfully wrote: {}",
-						node_ref.relative_location().display()
+						"{} {} | +{} -{}",
+						marker,
+						change.path.display(),
+						insertions,
+						deletions
 					);
 				}
+				println!(
+					"{} files changed, {} insertions(+), {} deletions(-)",
+					changes.len(),
+					total_insertions,
+					total_deletions
+				);
+			}
+			else {
+				for change in &changes {
+					print!("{}", change.unified_diff());
+				}
 			}
 
-			println!("Extracted to: {}", output_directory.display());
+			// Let scripts detect differences from the exit status
+			if !changes.is_empty() {
+				std::process::exit(1);
+			}
+		}
+		Some(Commands::Compare { .. })
+		| Some(Commands::Undo { .. })
+		| Some(Commands::Pack { .. })
+		| Some(Commands::Check { .. }) => {
+			unreachable!("handled before reading the bundle")
 		}
 		None => {
 			// Default: just output the tree
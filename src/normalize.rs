@@ -0,0 +1,197 @@
+use std::fmt;
+
+/// A prefix removed from every line of a file's contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+	/// Markdown blockquote markers, one `>` per line
+	Blockquote,
+	/// A gutter of consecutive line numbers, like `12 | `, `12\t` or a
+	/// right-aligned column
+	LineNumbers,
+	/// Whitespace every line was indented by, in bytes
+	Indentation(usize),
+}
+
+impl fmt::Display for Normalization {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Normalization::Blockquote => write!(f, "blockquote markers"),
+			Normalization::LineNumbers => write!(f, "line-number gutter"),
+			Normalization::Indentation(width) => write!(f, "{} columns of indentation", width),
+		}
+	}
+}
+
+/// Removes prefixes pasted contents pick up from every line
+///
+/// Strips blockquote markers from quoted text, line-number gutters copied
+/// from a code view and indentation shared by every line, in that order and
+/// only when every non-blank line has them. Returns `None` when there is
+/// nothing to remove.
+pub fn normalize(contents: &str) -> Option<(String, Vec<Normalization>)> {
+	let mut lines: Vec<(String, &str)> = contents
+		.split_inclusive('\n')
+		.map(|line| {
+			let text = line.trim_end_matches(['\n', '\r']);
+			(text.to_string(), &line[text.len()..])
+		})
+		.collect();
+	let mut applied = Vec::new();
+
+	// Nested quotes lose one level at a time
+	while strip_all(&mut lines, strip_quote) {
+		applied.push(Normalization::Blockquote);
+	}
+
+	// The numbers must count up one line at a time to be a gutter
+	let gutters: Vec<Gutter> = lines.iter().filter_map(|(text, _)| gutter(text)).collect();
+	if gutters.len() >= 2
+		&& gutters
+			.windows(2)
+			.all(|pair| pair[1].number == pair[0].number + 1)
+		&& is_gutter_column(&gutters)
+		&& strip_all(&mut lines, strip_gutter)
+	{
+		applied.push(Normalization::LineNumbers);
+	}
+
+	let indent = shared_indent(lines.iter().map(|(text, _)| text.as_str())).len();
+	if indent > 0 {
+		for (text, _) in &mut lines {
+			text.drain(..indent.min(text.len()));
+		}
+		applied.push(Normalization::Indentation(indent));
+	}
+
+	if applied.is_empty() {
+		return None;
+	}
+	let normalized = lines
+		.into_iter()
+		.map(|(text, ending)| text + ending)
+		.collect();
+	Some((normalized, applied))
+}
+
+// Returns the leading whitespace every non-blank line starts with
+pub(crate) fn shared_indent<'l>(lines: impl Iterator<Item = &'l str>) -> &'l str {
+	lines
+		.filter(|text| !text.trim().is_empty())
+		.map(|text| &text[..text.len() - text.trim_start().len()])
+		.reduce(|common, indent| {
+			let shared = common
+				.bytes()
+				.zip(indent.bytes())
+				.take_while(|(a, b)| a == b)
+				.count();
+			&common[..shared]
+		})
+		.unwrap_or_default()
+}
+
+// Strips a prefix from every line if every non-blank line has it, leaving
+// the lines untouched otherwise
+fn strip_all(lines: &mut [(String, &str)], strip: fn(&str) -> Option<&str>) -> bool {
+	let stripped: Option<Vec<String>> = lines
+		.iter()
+		.map(|(text, _)| match strip(text) {
+			Some(rest) => Some(rest.to_string()),
+			None if text.trim().is_empty() => Some(String::new()),
+			None => None,
+		})
+		.collect();
+
+	match stripped {
+		Some(stripped) if lines.iter().any(|(text, _)| !text.trim().is_empty()) => {
+			for ((text, _), stripped) in lines.iter_mut().zip(stripped) {
+				*text = stripped;
+			}
+			true
+		}
+		_ => false,
+	}
+}
+
+// Returns the line without its `>` marker and the space after it
+fn strip_quote(line: &str) -> Option<&str> {
+	let rest = line.strip_prefix('>')?;
+	Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+// A line number at the start of a line, and what separates it from the text
+struct Gutter {
+	number: usize,
+	// Byte offset just past the number
+	end: usize,
+	// Whether spaces come before the number
+	padded: bool,
+	separator: Separator,
+}
+
+#[derive(PartialEq)]
+enum Separator {
+	// `12 | ` or `12| `
+	Bar,
+	// `12\t`
+	Tab,
+	// `12 `, only a gutter as part of a right-aligned column
+	Space,
+}
+
+// Returns the line number of a gutter like `  12 | `, `12\t` or ` 12 `
+fn gutter(line: &str) -> Option<Gutter> {
+	let trimmed = line.trim_start();
+	let digits = trimmed.len()
+		- trimmed
+			.trim_start_matches(|c: char| c.is_ascii_digit())
+			.len();
+	let rest = &trimmed[digits..];
+	let separator = if rest.starts_with('\t') {
+		Separator::Tab
+	}
+	else if rest.trim_start_matches(' ').starts_with('|') {
+		Separator::Bar
+	}
+	else if rest.starts_with(' ') {
+		Separator::Space
+	}
+	else {
+		return None;
+	};
+	Some(Gutter {
+		number: trimmed[..digits].parse().ok()?,
+		end: line.len() - rest.len(),
+		padded: trimmed.len() < line.len(),
+		separator,
+	})
+}
+
+// Checks that the numbers look like a gutter rather than text that starts
+// with numbers, like a list of `1 apple` or YAML keys `1: one`. Numbers
+// separated only by a space must be right-aligned in one column, padded
+// on at least one line.
+fn is_gutter_column(gutters: &[Gutter]) -> bool {
+	if gutters
+		.iter()
+		.all(|gutter| gutter.separator != Separator::Space)
+	{
+		return true;
+	}
+	gutters
+		.iter()
+		.all(|gutter| gutter.separator == Separator::Space && gutter.end == gutters[0].end)
+		&& gutters.iter().any(|gutter| gutter.padded)
+}
+
+// Returns the line without its line-number gutter
+fn strip_gutter(line: &str) -> Option<&str> {
+	let rest = &line[gutter(line)?.end..];
+	if let Some(rest) = rest.strip_prefix('\t') {
+		return Some(rest);
+	}
+	let rest = rest
+		.trim_start_matches(' ')
+		.strip_prefix('|')
+		.unwrap_or(rest);
+	Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
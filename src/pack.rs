@@ -0,0 +1,176 @@
+use std::{fs, path::Path};
+
+use crate::{
+	diff::IGNORED_DIRS,
+	elision,
+	encoding::Encoding,
+	error::Result,
+	filesystem::{FileSystem, LinePosition, NO_FINAL_NEWLINE},
+	fsnode::Node,
+	normalize, prose,
+};
+
+// Prefix of the header lines and section markers of a packed bundle
+const COMMENT_PREFIX: &str = "// ";
+
+// Text with more than one control character in this many is packed as base64
+const CONTROL_RATIO: usize = 32;
+
+/// Reads a directory on disk into a filesystem, sorted by name
+///
+/// Symlinks are read as links rather than followed, and modes are kept. The
+/// `.git` directory and the extraction journal are left out, as are entries
+/// other than files, directories and symlinks.
+pub fn read_directory(dir: impl AsRef<Path>) -> Result<FileSystem<'static>> {
+	let mut fs = FileSystem::new();
+	read_entries(&mut fs, dir.as_ref(), "")?;
+	Ok(fs)
+}
+
+// Adds the entries of dir to the filesystem below the directory at prefix
+fn read_entries(fs: &mut FileSystem<'static>, dir: &Path, prefix: &str) -> Result<()> {
+	let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+	entries.sort_by_key(|entry| entry.file_name());
+
+	for entry in entries {
+		let name = entry.file_name().to_string_lossy().to_string();
+		let path = format!("{}{}", prefix, name);
+		let file_type = entry.file_type()?;
+
+		if file_type.is_dir() {
+			if IGNORED_DIRS.contains(&name.as_str()) {
+				continue;
+			}
+			let id = fs.create_dir_all(&path)?;
+			fs.set_mode(id, mode_of(&entry.metadata()?))?;
+			read_entries(fs, &entry.path(), &format!("{}/", path))?;
+		}
+		else if file_type.is_file() {
+			let id = fs.add_file(&path, None)?;
+			fs.set_bytes(id, fs::read(entry.path())?)?;
+			fs.set_mode(id, mode_of(&entry.metadata()?))?;
+		}
+		else if file_type.is_symlink() {
+			let target = fs::read_link(entry.path())?;
+			fs.add_symlink(&path, &target.to_string_lossy())?;
+		}
+	}
+	Ok(())
+}
+
+// Returns the permission bits of an entry on platforms that have them
+fn mode_of(metadata: &fs::Metadata) -> Option<u32> {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		Some(metadata.permissions().mode() & 0o7777)
+	}
+	#[cfg(not(unix))]
+	{
+		let _ = metadata;
+		None
+	}
+}
+
+/// Writes a filesystem out as a bundle that parses back to the same files
+///
+/// Files that are not UTF-8 text, are mostly control characters, or whose
+/// text would not come back from a section unchanged, get a base64 section
+/// like `// assets/logo.png (base64)`. Text without a final newline gets a
+/// `// notes.txt (no final newline)` marker, and an empty file gets an empty
+/// base64 section, which parses back as empty rather than missing. Modes
+/// other than the ones files and directories get anyway are listed in the
+/// header like `tree -p`, and symlinks as `name -> target`. The header and
+/// markers use the `// ` comment prefix, which parsing detects on its own.
+pub fn pack(fs: &FileSystem<'_>) -> String {
+	let mut bundle = String::new();
+	for line in fs.header_output(true).lines() {
+		bundle.push_str(&format!("{}{}\n", COMMENT_PREFIX, line));
+	}
+
+	for node in fs.nodes() {
+		let Some(bytes) = node.bytes()
+		else {
+			continue;
+		};
+		let path = node.relative_location();
+		bundle.push('\n');
+		let text = node
+			.contents()
+			.filter(|text| !text.is_empty() && survives_section(fs, &node, text));
+		match text {
+			Some(text) if text.ends_with('\n') => {
+				bundle.push_str(&format!("{}{}\n", COMMENT_PREFIX, path.display()));
+				bundle.push_str(text);
+			}
+			Some(text) => {
+				bundle.push_str(&format!(
+					"{}{} {}\n",
+					COMMENT_PREFIX,
+					path.display(),
+					NO_FINAL_NEWLINE
+				));
+				bundle.push_str(text);
+				bundle.push('\n');
+			}
+			None => {
+				let encoding = Encoding::Base64;
+				bundle.push_str(&format!(
+					"{}{} ({})\n",
+					COMMENT_PREFIX,
+					path.display(),
+					encoding
+				));
+				bundle.push_str(&encoding.encode(bytes));
+			}
+		}
+	}
+	bundle
+}
+
+// Returns true if the text would be read back from a plain section the same,
+// once a missing final newline is marked: no NUL bytes, elisions or runs of
+// control characters, no line that reads as a section marker, and nothing the
+// parser takes out as prose or cleans up
+fn survives_section(fs: &FileSystem<'_>, node: &Node<'_>, text: &str) -> bool {
+	// Elided code would be merged with whatever is on disk when extracted
+	if text.contains('\0') || elision::has_elisions(text) {
+		return false;
+	}
+	let controls = text
+		.chars()
+		.filter(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+		.count();
+	if controls * CONTROL_RATIO > text.chars().count() {
+		return false;
+	}
+	// A lone carriage return at the end would read back as a line ending
+	if text.ends_with('\r') {
+		return false;
+	}
+	let mut previous_blank = false;
+	for line in text.lines() {
+		let position = if previous_blank {
+			LinePosition::AfterBlank
+		}
+		else {
+			LinePosition::Within
+		};
+		if fs.is_section_marker(line, Some(COMMENT_PREFIX), position) {
+			return false;
+		}
+		previous_blank = line.trim().is_empty();
+	}
+
+	// The blank line before the next marker ends the section
+	let text = if text.ends_with('\n') {
+		text.to_string()
+	}
+	else {
+		format!("{}\n", text)
+	};
+	let section = format!("{}\n", text);
+	let split = prose::split_section(&section, node.name());
+	let read_back = split.contents.map_or("", |range| &section[range]);
+	read_back == text && normalize::normalize(&text).is_none()
+}
@@ -0,0 +1,84 @@
+use std::{
+	num::NonZeroUsize,
+	path::PathBuf,
+	sync::{
+		Mutex,
+		atomic::{AtomicBool, AtomicUsize, Ordering},
+	},
+	thread,
+};
+
+use crate::{
+	error::{Error, Result},
+	fsnode::Node,
+};
+
+/// How to spread the writes of an extraction over several threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelOptions {
+	/// Number of files written at the same time
+	pub workers: NonZeroUsize,
+	/// Stop handing out files after the first one that fails
+	pub fail_fast: bool,
+}
+
+impl Default for ParallelOptions {
+	fn default() -> Self {
+		ParallelOptions {
+			workers: thread::available_parallelism().unwrap_or(NonZeroUsize::MIN),
+			fail_fast: false,
+		}
+	}
+}
+
+/// A file that could not be written during a parallel extraction
+#[derive(Debug)]
+pub struct WriteFailure {
+	pub path: PathBuf,
+	pub error: Error,
+}
+
+// Runs write on every file from a pool of workers, passing the file's index
+// in files along, and collects the failures sorted by path. Partial files
+// are reported like any other failure but never stop the extraction, as
+// they do not when writing one file at a time.
+pub(crate) fn write_files<'a, F>(
+	files: &[Node<'a>],
+	options: ParallelOptions,
+	write: F,
+) -> Vec<WriteFailure>
+where
+	F: Fn(usize, Node<'a>) -> Result<()> + Sync,
+{
+	let next = AtomicUsize::new(0);
+	let stop = AtomicBool::new(false);
+	let failures = Mutex::new(Vec::new());
+
+	thread::scope(|scope| {
+		for _ in 0..options.workers.get().min(files.len()) {
+			scope.spawn(|| {
+				while !stop.load(Ordering::Relaxed) {
+					let i = next.fetch_add(1, Ordering::Relaxed);
+					let Some(&node) = files.get(i)
+					else {
+						break;
+					};
+
+					if let Err(error) = write(i, node) {
+						if options.fail_fast && !matches!(error, Error::PartialFile(_)) {
+							stop.store(true, Ordering::Relaxed);
+						}
+						failures.lock().unwrap().push(WriteFailure {
+							path: node.relative_location(),
+							error,
+						});
+					}
+				}
+			});
+		}
+	});
+
+	let mut failures = failures.into_inner().unwrap();
+	failures.sort_by(|a, b| a.path.cmp(&b.path));
+	failures
+}
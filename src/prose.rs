@@ -0,0 +1,248 @@
+use std::ops::Range;
+
+// How sentences introducing the next file usually start, matched
+// case-insensitively
+const LEAD_INS: &[&str] = &[
+	"here's",
+	"here is",
+	"and here's",
+	"now let's",
+	"now, let's",
+	"let's",
+	"next, let's",
+	"next,",
+	"finally,",
+	"now we",
+	"now update",
+	"now create",
+	"now add",
+	"we'll",
+	"i'll",
+	"then let's",
+];
+
+// Extensions of files whose contents are prose themselves, where only
+// lead-ins are dropped
+const TEXT_EXTENSIONS: &[&str] = &["md", "markdown", "txt", "rst", "adoc"];
+
+// Extensions of source files, where an explanatory paragraph after the code
+// cannot be part of it
+const CODE_EXTENSIONS: &[&str] = &[
+	"rs", "py", "js", "jsx", "mjs", "cjs", "ts", "tsx", "go", "java", "kt", "c", "h", "cc", "cpp",
+	"hpp", "cs", "rb", "php", "swift", "scala", "sh", "bash", "zsh", "lua", "sql", "json", "toml",
+	"css", "scss", "html", "vue", "svelte", "dart", "zig",
+];
+
+// Names of extensionless files that are prose
+const TEXT_NAMES: &[&str] = &[
+	"LICENSE",
+	"README",
+	"NOTICE",
+	"AUTHORS",
+	"CHANGELOG",
+	"COPYING",
+];
+
+/// Text in a bundle's body that was left out of every file
+///
+/// Covers Markdown fences wrapping a file, explanatory paragraphs between
+/// files, "Here's the updated..." lead-ins and anything before the first
+/// section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedText {
+	/// 1-based line the text starts on in the input
+	pub line: usize,
+	pub text: String,
+}
+
+// How a body section splits into a file's contents and the text around them
+pub(crate) struct Section {
+	// Byte range of the contents within the section, `None` if blank
+	pub contents: Option<Range<usize>>,
+	// Number of lines in the section before the contents
+	pub first_line: usize,
+	// Line the text starts on within the section, and its byte range
+	pub dropped: Vec<(usize, Range<usize>)>,
+}
+
+// Splits the prose and fences the model put around a file out of its section
+pub(crate) fn split_section(section: &str, name: &str) -> Section {
+	let mut offset = 0;
+	let lines: Vec<(Range<usize>, &str)> = section
+		.split_inclusive('\n')
+		.map(|line| {
+			let range = offset..offset + line.len();
+			offset += line.len();
+			(range, line.trim_end_matches(['\n', '\r']))
+		})
+		.collect();
+	let text = |i: usize| lines[i].1;
+	let blank = |i: usize| text(i).trim().is_empty();
+
+	let mut start = 0;
+	let mut end = lines.len();
+	let mut dropped: Vec<Range<usize>> = Vec::new();
+
+	// A lead-in at the very start of the section
+	let first = (start..end).find(|&i| !blank(i)).unwrap_or(end);
+	let paragraph = (first..end).find(|&i| blank(i)).unwrap_or(end);
+	if first < end && is_lead_in((first..paragraph).map(text)) {
+		dropped.push(first..paragraph);
+		start = paragraph;
+	}
+
+	let extension = name
+		.rsplit_once('.')
+		.map(|(_, extension)| extension.to_lowercase());
+	let is_text = match &extension {
+		Some(extension) => TEXT_EXTENSIONS.contains(&extension.as_str()),
+		None => TEXT_NAMES.contains(&name),
+	};
+	if !is_text {
+		// A fence pair wrapping the contents, with whatever follows the
+		// closing fence left out too
+		let first = (start..end).find(|&i| !blank(i)).unwrap_or(end);
+		let wrapped = first < end && is_fence(text(first));
+		if wrapped {
+			dropped.push(first..first + 1);
+			start = first + 1;
+			if let Some(fence) = unmatched_fences(start..end, text).first() {
+				dropped.push(*fence..end);
+				end = *fence;
+			}
+		}
+		// or a single stray closing fence after them; fences in the code
+		// itself, like a Markdown literal, come in pairs
+		else if let [fence] = unmatched_fences(start..end, text)[..] {
+			dropped.push(fence..end);
+			end = fence;
+		}
+
+		// Explanatory paragraphs after source code
+		let is_code = extension
+			.as_deref()
+			.is_some_and(|extension| CODE_EXTENSIONS.contains(&extension));
+		while is_code
+			&& !wrapped
+			&& let Some(last) = (start..end).rev().find(|&i| !blank(i))
+		{
+			let paragraph = (start..last)
+				.rev()
+				.find(|&i| blank(i))
+				.map_or(start, |i| i + 1);
+			if paragraph == start || !(paragraph..=last).all(|i| is_prose(text(i))) {
+				break;
+			}
+			dropped.push(paragraph..end);
+			end = paragraph;
+		}
+	}
+	else {
+		// Only a lead-in to the next file is dropped from prose files
+		if let Some(last) = (start..end).rev().find(|&i| !blank(i)) {
+			let paragraph = (start..last)
+				.rev()
+				.find(|&i| blank(i))
+				.map_or(start, |i| i + 1);
+			if paragraph > start && is_lead_in((paragraph..=last).map(text)) {
+				dropped.push(paragraph..end);
+				end = paragraph;
+			}
+		}
+	}
+
+	// Blank lines left over around dropped text belong to neither
+	if !dropped.is_empty() {
+		while start < end && blank(start) {
+			start += 1;
+		}
+		if end < lines.len() {
+			while end > start && blank(end - 1) {
+				end -= 1;
+			}
+		}
+	}
+
+	let contents = (start < end).then(|| lines[start].0.start..lines[end - 1].0.end);
+	let contents = contents.and_then(|range| {
+		// Without the blank line separating the section from the next marker
+		let section = &section[range.clone()];
+		let len = if section.ends_with("\r\n\r\n") {
+			section.len() - 2
+		}
+		else if section.ends_with("\n\n") {
+			section.len() - 1
+		}
+		else {
+			section.len()
+		};
+		(!section.trim().is_empty()).then(|| range.start..range.start + len)
+	});
+
+	dropped.sort_by_key(|range| range.start);
+	Section {
+		contents,
+		first_line: start,
+		dropped: dropped
+			.into_iter()
+			.filter(|range| range.clone().any(|i| !blank(i)))
+			.map(|range| {
+				(
+					range.start,
+					lines[range.start].0.start..lines[range.end - 1].0.end,
+				)
+			})
+			.collect(),
+	}
+}
+
+fn is_fence(line: &str) -> bool {
+	line.trim_start().starts_with("```")
+}
+
+// Returns the bare closing fences in the lines that close no fence opened
+// among them. A fence with an info string, like ```bash, opens one.
+fn unmatched_fences<'l>(lines: Range<usize>, text: impl Fn(usize) -> &'l str) -> Vec<usize> {
+	let mut open = 0;
+	let mut unmatched = Vec::new();
+	for i in lines {
+		let line = text(i).trim();
+		let Some(info) = line.strip_prefix("```")
+		else {
+			continue;
+		};
+		if !info.trim_start_matches('`').trim().is_empty() {
+			open += 1;
+		}
+		else if open > 0 {
+			open -= 1;
+		}
+		else {
+			unmatched.push(i);
+		}
+	}
+	unmatched
+}
+
+// Returns true for a paragraph introducing what comes next, like "Now let's
+// create the routes module:"
+fn is_lead_in<'l>(mut lines: impl Iterator<Item = &'l str>) -> bool {
+	let Some(first) = lines.next()
+	else {
+		return false;
+	};
+	let last = lines.last().unwrap_or(first);
+	let lowered = first.trim_start().to_lowercase();
+	LEAD_INS.iter().any(|lead_in| lowered.starts_with(lead_in)) && last.trim_end().ends_with(':')
+}
+
+// Returns true for a line that reads as a sentence rather than code
+fn is_prose(line: &str) -> bool {
+	let mut words = line.split_whitespace();
+	let first_word = words.next().unwrap_or_default();
+	line.starts_with(char::is_uppercase)
+		&& !first_word.ends_with(':')
+		&& words.count() >= 3
+		&& line.trim_end().ends_with(['.', ':', '!'])
+		&& !line.contains([';', '{', '}', '='])
+}
@@ -0,0 +1,401 @@
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	path::{Component, Path, PathBuf},
+};
+
+use crate::{diagnostics::column_of, filesystem::FileSystem, fsnode::Node, truncation};
+
+// Directories cargo discovers targets in, with the kind of target they hold
+const TARGET_DIRS: &[&str] = &["src/bin", "examples", "tests", "benches"];
+
+// Sections of Cargo.toml that declare targets with an optional `path`
+const TARGET_SECTIONS: &[&str] = &["[lib]", "[[bin]]", "[[example]]", "[[test]]", "[[bench]]"];
+
+/// The kind of problem found in a Rust crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RustIssueKind {
+	/// A `mod name;` declaration with no file for the module
+	UnresolvedModule,
+	/// A target path in `Cargo.toml` that is not in the bundle
+	MissingTarget,
+	/// A `.rs` file no crate root reaches through `mod` declarations
+	OrphanSourceFile,
+}
+
+/// A problem found by cross-referencing the Rust files of a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RustIssue {
+	pub kind: RustIssueKind,
+	/// The file the problem is in, or the orphaned file itself
+	pub path: PathBuf,
+	/// 1-based line within the file's contents, if the problem is on one
+	pub line: Option<usize>,
+	/// 1-based column within that line
+	pub column: usize,
+	pub message: String,
+}
+
+// A module declared with `mod name;`, and where it was declared
+struct ModDecl {
+	name: String,
+	// The file given by a `#[path = "..."]` attribute
+	path: Option<String>,
+	// Enclosing inline modules, outermost first
+	inline: Vec<String>,
+	line: usize,
+	column: usize,
+}
+
+/// Cross-references the Rust crates in the filesystem
+///
+/// Crate roots come from each `Cargo.toml`: the library and binaries under
+/// `src`, targets cargo discovers in `src/bin`, `examples`, `tests` and
+/// `benches`, `build.rs`, and any explicit target `path`. Without a
+/// `Cargo.toml`, every `main.rs` and `lib.rs` is taken as a crate root. The
+/// `mod` declarations of each root are then followed through the tree. Works
+/// entirely on the in-memory tree; nothing is read from disk.
+pub fn check_crates(fs: &FileSystem<'_>) -> Vec<RustIssue> {
+	let files: BTreeMap<PathBuf, Node<'_>> = fs
+		.nodes()
+		.filter(|node| !node.is_directory())
+		.map(|node| (node.relative_location(), node))
+		.collect();
+
+	let mut issues = Vec::new();
+	let mut roots = Vec::new();
+	let manifests: Vec<&PathBuf> = files
+		.keys()
+		.filter(|path| path.file_name().is_some_and(|name| name == "Cargo.toml"))
+		.collect();
+
+	for manifest in &manifests {
+		let crate_dir = manifest.parent().unwrap_or(Path::new(""));
+		let contents = files[*manifest].contents().unwrap_or_default();
+		let found = roots.len();
+
+		// Targets cargo finds by convention
+		for default in ["src/lib.rs", "src/main.rs", "build.rs"] {
+			let path = crate_dir.join(default);
+			if files.contains_key(&path) {
+				roots.push(path);
+			}
+		}
+		for dir in TARGET_DIRS {
+			let dir = crate_dir.join(dir);
+			for path in files.keys() {
+				let Ok(rest) = path.strip_prefix(&dir)
+				else {
+					continue;
+				};
+				let components = rest.components().count();
+				let is_target = (components == 1 && is_source(path))
+					|| (components == 2 && path.file_name().is_some_and(|name| name == "main.rs"));
+				if is_target {
+					roots.push(path.clone());
+				}
+			}
+		}
+
+		// Targets the manifest names explicitly
+		for (line, column, target) in target_paths(contents) {
+			let path = normalize(&crate_dir.join(&target));
+			if files.contains_key(&path) {
+				roots.push(path);
+			}
+			else {
+				issues.push(RustIssue {
+					kind: RustIssueKind::MissingTarget,
+					path: (*manifest).clone(),
+					line: Some(line),
+					column,
+					message: format!("target path `{}` is not in the bundle", target),
+				});
+			}
+		}
+
+		// Flat bundles often leave out the src directory
+		if roots.len() == found {
+			roots.extend(fallback_roots(&files, crate_dir));
+		}
+	}
+	if manifests.is_empty() {
+		roots.extend(fallback_roots(&files, Path::new("")));
+	}
+
+	// Follow the mod declarations out from every root
+	let mut reached: BTreeSet<PathBuf> = BTreeSet::new();
+	let mut pending: Vec<(PathBuf, bool)> = roots.into_iter().map(|root| (root, true)).collect();
+	while let Some((path, is_root)) = pending.pop() {
+		if !reached.insert(path.clone()) {
+			continue;
+		}
+		let contents = files[&path].contents().unwrap_or_default();
+		let dir = module_dir(&path, is_root);
+
+		for decl in mod_declarations(contents) {
+			let candidates = match &decl.path {
+				Some(explicit) => {
+					vec![normalize(
+						&path.parent().unwrap_or(Path::new("")).join(explicit),
+					)]
+				}
+				None => {
+					let mut dir = dir.clone();
+					dir.extend(&decl.inline);
+					vec![
+						dir.join(format!("{}.rs", decl.name)),
+						dir.join(&decl.name).join("mod.rs"),
+					]
+				}
+			};
+
+			match candidates
+				.iter()
+				.find(|candidate| files.contains_key(*candidate))
+			{
+				Some(found) => pending.push((found.clone(), false)),
+				None => issues.push(RustIssue {
+					kind: RustIssueKind::UnresolvedModule,
+					path: path.clone(),
+					line: Some(decl.line),
+					column: decl.column,
+					message: format!(
+						"module `{}` has no file, expected `{}`",
+						decl.name,
+						candidates
+							.iter()
+							.map(|candidate| candidate.display().to_string())
+							.collect::<Vec<_>>()
+							.join("` or `")
+					),
+				}),
+			}
+		}
+	}
+
+	// Source files inside a crate that nothing reached. A crate with source
+	// files the bundle only lists cannot be judged, as their mod declarations
+	// are unknown.
+	let crate_dirs: Vec<&Path> = if manifests.is_empty() {
+		vec![Path::new("")]
+	}
+	else {
+		manifests.iter().filter_map(|path| path.parent()).collect()
+	};
+	let complete: Vec<&Path> = crate_dirs
+		.into_iter()
+		.filter(|dir| {
+			files.iter().all(|(path, node)| {
+				!is_source(path) || !path.starts_with(dir) || node.span().body_lines.is_some()
+			})
+		})
+		.collect();
+	for path in files.keys() {
+		if is_source(path)
+			&& !reached.contains(path)
+			&& complete.iter().any(|dir| path.starts_with(dir))
+		{
+			issues.push(RustIssue {
+				kind: RustIssueKind::OrphanSourceFile,
+				path: path.clone(),
+				line: None,
+				column: 1,
+				message: format!("`{}` is not reachable from any crate root", path.display()),
+			});
+		}
+	}
+
+	issues
+}
+
+// Returns the main.rs and lib.rs files anywhere under a directory, for
+// crates that do not follow cargo's layout
+fn fallback_roots(files: &BTreeMap<PathBuf, Node<'_>>, dir: &Path) -> Vec<PathBuf> {
+	files
+		.keys()
+		.filter(|path| {
+			path.starts_with(dir)
+				&& path
+					.file_name()
+					.is_some_and(|name| name == "main.rs" || name == "lib.rs")
+		})
+		.cloned()
+		.collect()
+}
+
+fn is_source(path: &Path) -> bool {
+	path.extension().is_some_and(|ext| ext == "rs")
+}
+
+// Returns the directory a file's child modules live in: next to it for crate
+// roots and mod.rs files, otherwise in a directory named after it
+fn module_dir(path: &Path, is_root: bool) -> PathBuf {
+	let parent = path.parent().unwrap_or(Path::new("")).to_path_buf();
+	if is_root || path.file_name().is_some_and(|name| name == "mod.rs") {
+		parent
+	}
+	else {
+		parent.join(path.file_stem().unwrap_or_default())
+	}
+}
+
+// Resolves `.` and `..` components without touching the disk
+fn normalize(path: &Path) -> PathBuf {
+	let mut normalized = PathBuf::new();
+	for component in path.components() {
+		match component {
+			Component::CurDir => {}
+			Component::ParentDir => {
+				normalized.pop();
+			}
+			component => normalized.push(component),
+		}
+	}
+	normalized
+}
+
+// Finds the `mod name;` declarations in a source file, skipping comments and
+// tracking the inline `mod name { ... }` blocks they sit in. Braces inside
+// literals and comments are not counted.
+fn mod_declarations(contents: &str) -> Vec<ModDecl> {
+	let mut decls = Vec::new();
+	let mut inline: Vec<(String, usize)> = Vec::new();
+	let mut depth: usize = 0;
+	let mut path_attribute = None;
+	let mut in_block_comment = false;
+
+	for (i, line) in contents.lines().enumerate() {
+		let trimmed = line.trim();
+		if !in_block_comment
+			&& let Some(path) = trimmed
+				.strip_prefix("#[path")
+				.and_then(|rest| rest.split('"').nth(1))
+		{
+			path_attribute = Some(path.to_string());
+			continue;
+		}
+
+		// Items and braces are only looked for in code, so a `mod` in a
+		// comment or string is skipped, and a line may hold several
+		let code = code_outside_comments(line, &mut in_block_comment);
+		let mut found = false;
+		let mut name_from = 0;
+		for (offset, c) in code.char_indices() {
+			match c {
+				'{' => depth += 1,
+				'}' => {
+					depth = depth.saturating_sub(1);
+					if inline.last().is_some_and(|&(_, open)| open == depth) {
+						inline.pop();
+					}
+				}
+				_ => {}
+			}
+			let at_word =
+				!code[..offset].ends_with(|c: char| c.is_alphanumeric() || c == '_' || c == '#');
+			let Some((name, rest)) = code[offset..]
+				.strip_prefix("mod ")
+				.filter(|_| at_word)
+				.and_then(mod_item)
+			else {
+				continue;
+			};
+			found = true;
+			let start = line[name_from..]
+				.find(name)
+				.map_or(name_from, |start| name_from + start);
+			name_from = start + name.len();
+			let name = name.trim_start_matches("r#").to_string();
+			if rest.starts_with(';') {
+				decls.push(ModDecl {
+					name,
+					path: path_attribute.take(),
+					inline: inline.iter().map(|(name, _)| name.clone()).collect(),
+					line: i + 1,
+					column: column_of(line, &line[start..]),
+				});
+			}
+			else if rest.starts_with('{') {
+				inline.push((name, depth));
+				path_attribute = None;
+			}
+		}
+		let code = code.trim();
+		if !found && !code.is_empty() && !code.starts_with("#[") {
+			path_attribute = None;
+		}
+	}
+	decls
+}
+
+// Returns the code of a line without its literals and comments, keeping track
+// of a block comment that runs on past the end of the line
+fn code_outside_comments(line: &str, in_block_comment: &mut bool) -> String {
+	let (mut rest, _) = truncation::scan_line(line);
+	let mut code = String::new();
+	loop {
+		if *in_block_comment {
+			match rest.find("*/") {
+				Some(end) => {
+					rest.drain(..end + 2);
+					*in_block_comment = false;
+				}
+				None => return code,
+			}
+		}
+		let comment = rest.find("//");
+		match rest.find("/*") {
+			Some(start) if comment.is_none_or(|comment| start < comment) => {
+				code.push_str(&rest[..start]);
+				rest.drain(..start + 2);
+				*in_block_comment = true;
+			}
+			_ => {
+				code.push_str(&rest[..comment.unwrap_or(rest.len())]);
+				return code;
+			}
+		}
+	}
+}
+
+// Splits what follows `mod ` into the module's name and what comes after it
+fn mod_item(rest: &str) -> Option<(&str, &str)> {
+	let rest = rest.trim_start();
+	let end = rest
+		.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '#'))
+		.unwrap_or(rest.len());
+	let (name, rest) = rest.split_at(end);
+	(!name.is_empty()).then(|| (name, rest.trim_start()))
+}
+
+// Finds the explicit `path = "..."` keys of the target sections of a
+// manifest, with the line and column of each path
+fn target_paths(manifest: &str) -> Vec<(usize, usize, String)> {
+	let mut paths = Vec::new();
+	let mut in_target = false;
+
+	for (i, line) in manifest.lines().enumerate() {
+		let trimmed = line.trim();
+		if trimmed.starts_with('[') {
+			in_target = TARGET_SECTIONS.contains(&trimmed);
+			continue;
+		}
+		if !in_target {
+			continue;
+		}
+
+		if let Some((key, value)) = trimmed.split_once('=')
+			&& key.trim() == "path"
+			&& let Some(path) = value
+				.trim()
+				.strip_prefix('"')
+				.and_then(|v| v.split('"').next())
+		{
+			let column = line
+				.find(path)
+				.map_or(1, |offset| column_of(line, &line[offset..]));
+			paths.push((i + 1, column, path.to_string()));
+		}
+	}
+	paths
+}
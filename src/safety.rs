@@ -0,0 +1,66 @@
+use std::path::{Component, Path};
+
+use crate::{
+	error::{Error, Result},
+	journal::JOURNAL_DIR,
+};
+
+/// Checks that a node's path stays inside the directory it is written to
+///
+/// Only plain names are allowed: no `..`, `.`, root or drive prefix. Paths
+/// inside the journal directory are refused too, since `undo` trusts what it
+/// finds there.
+pub fn check_path(relative_path: &Path) -> Result<()> {
+	if relative_path
+		.components()
+		.all(|component| matches!(component, Component::Normal(_)))
+		&& !relative_path.starts_with(JOURNAL_DIR)
+	{
+		Ok(())
+	}
+	else {
+		Err(Error::UnsafePath(relative_path.display().to_string()))
+	}
+}
+
+/// Checks that a symlink at the path points inside the directory it is
+/// written to
+///
+/// The target must be relative and, resolved from the link's directory
+/// without following other links, must not climb above the output root.
+pub fn check_link(relative_path: &Path, target: &str) -> Result<()> {
+	check_path(relative_path)?;
+	if target.is_empty() {
+		return Err(Error::UnsafePath(relative_path.display().to_string()));
+	}
+	let unsafe_link = || Error::UnsafePath(format!("{} -> {}", relative_path.display(), target));
+
+	let mut resolved = relative_path
+		.parent()
+		.map(Path::to_path_buf)
+		.unwrap_or_default();
+	for component in Path::new(target).components() {
+		match component {
+			Component::Normal(name) => resolved.push(name),
+			Component::CurDir => {}
+			Component::ParentDir => {
+				if !resolved.pop() {
+					return Err(unsafe_link());
+				}
+			}
+			Component::RootDir | Component::Prefix(_) => return Err(unsafe_link()),
+		}
+	}
+	Ok(())
+}
+
+// Refuses to write a file through a symlink already on disk at its path,
+// which could point anywhere
+pub(crate) fn check_not_link(output_path: &Path, relative_path: &Path) -> Result<()> {
+	match std::fs::symlink_metadata(output_path) {
+		Ok(metadata) if metadata.file_type().is_symlink() => {
+			Err(Error::UnsafePath(relative_path.display().to_string()))
+		}
+		_ => Ok(()),
+	}
+}
@@ -0,0 +1,261 @@
+use std::{borrow::Cow, collections::HashSet, io::BufRead};
+
+use crate::{
+	diagnostics::Diagnostic,
+	error::{Error, Result},
+	filesystem::{FileSystem, LinePosition, ParseOptions},
+	filter::FilterChain,
+	fsnode::{Node, NodeId},
+	line_ending::BOM,
+};
+
+/// Parses a bundle from a reader, yielding each file as its section ends
+///
+/// The header is read up front, so the whole structure is known before any
+/// contents arrive. The body is then read a line at a time and only the
+/// current file's contents are kept, so memory stays bounded by the largest
+/// file rather than the whole bundle.
+pub struct BundleStream<R> {
+	reader: R,
+	fs: FileSystem<'static>,
+	comment_prefix: Option<String>,
+	debug: bool,
+	// Number of lines read so far
+	line_number: usize,
+	// The file whose section is being read, the line its section starts on
+	// and its contents so far
+	current: Option<(NodeId, usize, String)>,
+	// The file last handed out, whose contents are dropped on the next call
+	returned: Option<NodeId>,
+	// Files that had a section, the rest are handed out at the end
+	seen: HashSet<NodeId>,
+	// Header files without a section, left to hand out once the input ends
+	remaining: Option<Vec<NodeId>>,
+	// Text read before the first section, until that section starts
+	preamble: Option<(usize, String)>,
+	// Run on each file's contents as its section ends
+	filters: FilterChain,
+	options: ParseOptions,
+	diagnostics: Vec<Diagnostic>,
+}
+
+impl<R: BufRead> BundleStream<R> {
+	/// Reads the header from the reader and builds the file structure
+	pub fn new(mut reader: R, comment_prefix: Option<&str>, debug: bool) -> Result<Self> {
+		// The header ends at the first blank line
+		let mut header = String::new();
+		let mut line = String::new();
+		let mut line_number = 0;
+		while reader.read_line(&mut line)? > 0 {
+			line_number += 1;
+			if line_number == 1 && line.starts_with(BOM) {
+				line.remove(0);
+			}
+			if line == "\n" || line == "\r\n" {
+				break;
+			}
+			header.push_str(&line);
+			line.clear();
+		}
+		if header.ends_with('\n') {
+			header.pop();
+			if header.ends_with('\r') {
+				header.pop();
+			}
+		}
+
+		let mut fs = FileSystem::new();
+		let mut diagnostics = Vec::new();
+		fs.parse_header(&header, comment_prefix, &mut diagnostics, debug);
+		if debug {
+			println!("Header parsed: {} nodes created", fs.len());
+		}
+
+		Ok(BundleStream {
+			reader,
+			fs,
+			comment_prefix: comment_prefix.map(str::to_string),
+			debug,
+			line_number,
+			current: None,
+			returned: None,
+			seen: HashSet::new(),
+			remaining: None,
+			preamble: Some((line_number + 1, String::new())),
+			filters: FilterChain::new(),
+			options: ParseOptions::default(),
+			diagnostics,
+		})
+	}
+
+	/// Runs each file's contents through the filters before returning it
+	pub fn with_filters(mut self, filters: FilterChain) -> Self {
+		self.filters = filters;
+		self
+	}
+
+	/// Reads each file's contents with the given options
+	pub fn with_options(mut self, options: ParseOptions) -> Self {
+		self.options = options;
+		self
+	}
+
+	/// Returns the structure read from the header
+	///
+	/// Files have no contents here apart from the one last returned by
+	/// `next_file`. Modes set by `chmod` commands in the prose are only known
+	/// once `next_file` has returned `None`.
+	pub fn filesystem(&self) -> &FileSystem<'static> {
+		&self.fs
+	}
+
+	/// Returns the problems found in the input read so far
+	///
+	/// Errors are also returned from `next_file` as soon as they are found.
+	pub fn diagnostics(&self) -> &[Diagnostic] {
+		&self.diagnostics
+	}
+
+	/// Reads up to the end of the next file's section and returns the file
+	///
+	/// Once the input is exhausted, files listed in the header that had no
+	/// section are returned without contents. Returns `None` when every file
+	/// has been returned. The contents of the previous file are dropped.
+	pub fn next_file(&mut self) -> Result<Option<Node<'_>>> {
+		if let Some(id) = self.returned.take() {
+			self.fs.clear_contents(id);
+		}
+
+		match self.next_section()? {
+			Some(id) => {
+				self.seen.insert(id);
+				self.returned = Some(id);
+			}
+			None => {
+				// The input is done, so every `chmod` in the prose is known
+				if self.remaining.is_none() {
+					self.fs.apply_chmod_commands(self.debug);
+				}
+
+				// Hand out the files that never had a section
+				let remaining = self.remaining.get_or_insert_with(|| {
+					let mut ids: Vec<NodeId> = self
+						.fs
+						.nodes()
+						.filter(|node| !node.is_directory() && !self.seen.contains(&node.id()))
+						.map(|node| node.id())
+						.collect();
+					ids.reverse();
+					ids
+				});
+				match remaining.pop() {
+					Some(id) => self.returned = Some(id),
+					None => return Ok(None),
+				}
+			}
+		}
+
+		Ok(self.returned.and_then(|id| self.fs.node(id)))
+	}
+
+	// Reads lines until a section ends, returning the file it belongs to with
+	// its contents set, or `None` at the end of the input
+	fn next_section(&mut self) -> Result<Option<NodeId>> {
+		if self.remaining.is_some() {
+			return Ok(None);
+		}
+
+		let mut line = String::new();
+		loop {
+			line.clear();
+			if self.reader.read_line(&mut line)? == 0 {
+				// The last section ends with the input
+				return match self.current.take() {
+					Some((file, first_line, contents)) => {
+						self.finish_section(file, first_line, contents)
+					}
+					None => Ok(None),
+				};
+			}
+			self.line_number += 1;
+
+			let trimmed = line.trim_end_matches(['\n', '\r']);
+			let comment_prefix = self.comment_prefix.as_deref();
+			let position = match &self.current {
+				None => LinePosition::Outside,
+				Some((_, _, contents)) if ends_with_blank_line(contents) => {
+					LinePosition::AfterBlank
+				}
+				Some(_) => LinePosition::Within,
+			};
+			let found = self.diagnostics.len();
+			let marker = self.fs.section_marker(
+				trimmed,
+				self.line_number,
+				comment_prefix,
+				position,
+				&mut self.diagnostics,
+				self.debug,
+			);
+			if let Some(error) = self.diagnostics[found..].iter().find(|d| d.is_error()) {
+				return Err(Error::Syntax(error.clone().into()));
+			}
+			if let Some(file) = marker {
+				// Anything before the first section belongs to no file
+				if let Some((first_line, preamble)) = self.preamble.take() {
+					self.fs.drop_text(&preamble, first_line, self.debug);
+				}
+
+				let previous = self.current.take();
+				self.current = file.map(|file| (file, self.line_number + 1, String::new()));
+
+				if let Some((file, first_line, contents)) = previous
+					&& let Some(file) = self.finish_section(file, first_line, contents)?
+				{
+					return Ok(Some(file));
+				}
+			}
+			else if let Some((_, _, contents)) = &mut self.current {
+				contents.push_str(&line);
+			}
+			else if let Some((_, preamble)) = &mut self.preamble {
+				preamble.push_str(&line);
+			}
+		}
+	}
+
+	// Sets a file's contents from its section, skipping blank sections unless
+	// they are encoded, which makes them empty files
+	fn finish_section(
+		&mut self,
+		file: NodeId,
+		first_line: usize,
+		contents: String,
+	) -> Result<Option<NodeId>> {
+		let filled = self
+			.fs
+			.fill_section(file, Cow::Owned(contents), first_line, self.debug)?
+			|| self
+				.fs
+				.node(file)
+				.is_some_and(|node| node.encoding().is_some());
+		if filled {
+			if let Some(error) = self.fs.decode_file(file) {
+				return Err(Error::Syntax(error.into()));
+			}
+			if self.options.normalize {
+				let applied = self.fs.normalize_file(file, self.debug);
+				self.diagnostics.extend(applied);
+			}
+			self.fs.filter_file(file, &self.filters);
+		}
+		Ok(filled.then_some(file))
+	}
+}
+
+// Returns true if the last line of the text is blank
+fn ends_with_blank_line(text: &str) -> bool {
+	text.strip_suffix('\n')
+		.and_then(|text| text.rsplit('\n').next())
+		.is_some_and(|line| line.trim().is_empty())
+}
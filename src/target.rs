@@ -0,0 +1,296 @@
+use std::{
+	collections::BTreeMap,
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+use crate::{
+	error::{Error, Result},
+	safety,
+};
+
+/// Somewhere an extraction writes directories, files and symlinks to
+///
+/// Paths are relative to the target's root and have already been checked
+/// to stay inside it. `FileSystem::extract_to` creates the directory an
+/// entry is in before the entry, and sets the entry's mode right after it.
+pub trait ExtractTarget {
+	/// Creates the directory and any missing parents
+	fn create_dir_all(&mut self, path: &Path) -> Result<()>;
+
+	/// Writes a file, replacing any file already at the path
+	fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<()>;
+
+	/// Sets the permission bits of a file or directory already written
+	fn set_mode(&mut self, path: &Path, mode: u32) -> Result<()>;
+
+	/// Creates a symlink pointing at the target
+	fn symlink(&mut self, path: &Path, target: &str) -> Result<()>;
+
+	/// Removes the file at the path, for a file the bundle marks `(deleted)`
+	fn remove_file(&mut self, path: &Path) -> Result<()>;
+
+	/// Returns true if anything is at the path
+	fn exists(&self, path: &Path) -> bool;
+
+	/// Returns the contents of the file at the path, if the target can read
+	/// it back
+	///
+	/// Elided code is merged into the file found here; targets that cannot
+	/// read leave files with elisions to fail with `Error::PartialFile`.
+	fn read_file(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+		let _ = path;
+		Ok(None)
+	}
+}
+
+/// Extracts to a directory on disk
+///
+/// Directories, files and modes are never written through a symlink already
+/// on disk, which could point anywhere.
+#[derive(Debug, Clone)]
+pub struct DiskTarget {
+	root: PathBuf,
+}
+
+impl DiskTarget {
+	/// Extracts below the directory, which is created when needed
+	pub fn new(root: impl AsRef<Path>) -> Self {
+		DiskTarget {
+			root: root.as_ref().to_path_buf(),
+		}
+	}
+
+	/// Returns the directory extracted to
+	pub fn root(&self) -> &Path {
+		&self.root
+	}
+}
+
+impl ExtractTarget for DiskTarget {
+	fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+		let mut current = PathBuf::new();
+		for component in path.components() {
+			current.push(component);
+			safety::check_not_link(&self.root.join(&current), &current)?;
+		}
+		Ok(fs::create_dir_all(self.root.join(path))?)
+	}
+
+	fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<()> {
+		let output_path = self.root.join(path);
+		safety::check_not_link(&output_path, path)?;
+		Ok(fs::write(output_path, contents)?)
+	}
+
+	fn set_mode(&mut self, path: &Path, mode: u32) -> Result<()> {
+		let output_path = self.root.join(path);
+		safety::check_not_link(&output_path, path)?;
+		set_permissions(&output_path, mode)
+	}
+
+	fn symlink(&mut self, path: &Path, target: &str) -> Result<()> {
+		create_symlink(target, &self.root.join(path))
+	}
+
+	fn remove_file(&mut self, path: &Path) -> Result<()> {
+		let output_path = self.root.join(path);
+		safety::check_not_link(&output_path, path)?;
+		Ok(fs::remove_file(output_path)?)
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		fs::symlink_metadata(self.root.join(path)).is_ok()
+	}
+
+	fn read_file(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+		match fs::read(self.root.join(path)) {
+			Ok(contents) => Ok(Some(contents)),
+			Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(err) => Err(err.into()),
+		}
+	}
+}
+
+/// An entry written to a `MemoryTarget`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryEntry {
+	Directory {
+		mode: Option<u32>,
+	},
+	File {
+		contents: Vec<u8>,
+		mode: Option<u32>,
+	},
+	Symlink {
+		target: String,
+	},
+}
+
+/// Extracts into memory, for tests and for looking at the result before
+/// anything touches the disk
+///
+/// It behaves like a directory on disk: entries need their parent
+/// directory, files are not written through symlinks and replacing a file
+/// keeps its mode.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTarget {
+	entries: BTreeMap<PathBuf, MemoryEntry>,
+}
+
+impl MemoryTarget {
+	/// Creates an empty target
+	pub fn new() -> Self {
+		MemoryTarget::default()
+	}
+
+	/// Returns the entry at the path, if any
+	pub fn get(&self, path: impl AsRef<Path>) -> Option<&MemoryEntry> {
+		self.entries.get(path.as_ref())
+	}
+
+	/// Returns every entry, sorted by path
+	pub fn entries(&self) -> impl Iterator<Item = (&Path, &MemoryEntry)> {
+		self.entries
+			.iter()
+			.map(|(path, entry)| (path.as_path(), entry))
+	}
+
+	// Fails unless the directory the path is in exists
+	fn check_parent(&self, path: &Path) -> Result<()> {
+		match path.parent() {
+			Some(parent) if !parent.as_os_str().is_empty() => match self.entries.get(parent) {
+				Some(MemoryEntry::Directory { .. }) => Ok(()),
+				_ => Err(Error::MissingParent(parent.display().to_string())),
+			},
+			_ => Ok(()),
+		}
+	}
+}
+
+impl ExtractTarget for MemoryTarget {
+	fn create_dir_all(&mut self, path: &Path) -> Result<()> {
+		let mut current = PathBuf::new();
+		for component in path.components() {
+			current.push(component);
+			match self.entries.get(&current) {
+				Some(MemoryEntry::Directory { .. }) => {}
+				Some(_) => return Err(Error::AlreadyExists(current.display().to_string())),
+				None => {
+					self.entries
+						.insert(current.clone(), MemoryEntry::Directory { mode: None });
+				}
+			}
+		}
+		Ok(())
+	}
+
+	fn write_file(&mut self, path: &Path, contents: &[u8]) -> Result<()> {
+		self.check_parent(path)?;
+		let mode = match self.entries.get(path) {
+			None => None,
+			Some(MemoryEntry::File { mode, .. }) => *mode,
+			Some(MemoryEntry::Symlink { .. }) => {
+				return Err(Error::UnsafePath(path.display().to_string()));
+			}
+			Some(MemoryEntry::Directory { .. }) => {
+				return Err(Error::AlreadyExists(path.display().to_string()));
+			}
+		};
+		self.entries.insert(
+			path.to_path_buf(),
+			MemoryEntry::File {
+				contents: contents.to_vec(),
+				mode,
+			},
+		);
+		Ok(())
+	}
+
+	fn set_mode(&mut self, path: &Path, new_mode: u32) -> Result<()> {
+		match self.entries.get_mut(path) {
+			Some(MemoryEntry::File { mode, .. } | MemoryEntry::Directory { mode }) => {
+				*mode = Some(new_mode & 0o7777);
+				Ok(())
+			}
+			Some(MemoryEntry::Symlink { .. }) => Err(Error::UnsafePath(path.display().to_string())),
+			None => Err(Error::NodeNotFound(path.display().to_string())),
+		}
+	}
+
+	fn symlink(&mut self, path: &Path, target: &str) -> Result<()> {
+		self.check_parent(path)?;
+		match self.entries.get(path) {
+			None | Some(MemoryEntry::Symlink { .. }) => {
+				self.entries.insert(
+					path.to_path_buf(),
+					MemoryEntry::Symlink {
+						target: target.to_string(),
+					},
+				);
+				Ok(())
+			}
+			Some(_) => Err(Error::AlreadyExists(path.display().to_string())),
+		}
+	}
+
+	fn remove_file(&mut self, path: &Path) -> Result<()> {
+		match self.entries.get(path) {
+			Some(MemoryEntry::File { .. }) => {
+				self.entries.remove(path);
+				Ok(())
+			}
+			Some(MemoryEntry::Symlink { .. }) => Err(Error::UnsafePath(path.display().to_string())),
+			Some(MemoryEntry::Directory { .. }) => Err(Error::InvalidNodeType),
+			None => Err(Error::NodeNotFound(path.display().to_string())),
+		}
+	}
+
+	fn exists(&self, path: &Path) -> bool {
+		self.entries.contains_key(path)
+	}
+
+	fn read_file(&self, path: &Path) -> Result<Option<Vec<u8>>> {
+		match self.entries.get(path) {
+			Some(MemoryEntry::File { contents, .. }) => Ok(Some(contents.clone())),
+			_ => Ok(None),
+		}
+	}
+}
+
+// Creates a symlink, replacing a symlink already at the path but nothing else
+pub(crate) fn create_symlink(target: &str, path: &Path) -> Result<()> {
+	match fs::symlink_metadata(path) {
+		Ok(metadata) if metadata.file_type().is_symlink() => {
+			if fs::read_link(path)? == Path::new(target) {
+				return Ok(());
+			}
+			fs::remove_file(path)?;
+		}
+		Ok(_) => return Err(Error::AlreadyExists(path.display().to_string())),
+		Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+		Err(err) => return Err(err.into()),
+	}
+
+	#[cfg(unix)]
+	std::os::unix::fs::symlink(target, path)?;
+	#[cfg(not(unix))]
+	return Err(Error::Io(io::Error::new(
+		io::ErrorKind::Unsupported,
+		format!("cannot create symlink {}", path.display()),
+	)));
+	#[cfg(unix)]
+	Ok(())
+}
+
+// Sets the permission bits of a file or directory on platforms that have them
+pub(crate) fn set_permissions(path: &Path, mode: u32) -> Result<()> {
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+	}
+	#[cfg(not(unix))]
+	let _ = (path, mode);
+	Ok(())
+}
@@ -0,0 +1,138 @@
+// Phrases models leave in place of the rest of a file when an answer is cut
+// off, matched case-insensitively on the last line
+const CONTINUE_MARKERS: &[&str] = &[
+	"(continued",
+	"[continued",
+	"continued in the next",
+	"continued below",
+	"continue in the next",
+	"to be continued",
+	"(truncated",
+	"[truncated",
+	"output truncated",
+	"rest of the file",
+];
+
+// Bracket pairs that must balance, with the name used in reasons
+const BRACKETS: &[(char, char, &str)] = &[
+	('{', '}', "braces"),
+	('[', ']', "brackets"),
+	('(', ')', "parentheses"),
+];
+
+/// Returns why file contents look cut off part way through, if they do
+///
+/// Looks for a "continue" marker on the last line, an unterminated code
+/// fence or string, and brackets opened but never closed. A body section
+/// without a final newline backs these up, as a bundle cut off mid-line
+/// leaves one, but is not a sign on its own: answers often end that way.
+pub fn truncation(contents: &str, from_section: bool) -> Option<String> {
+	let reason = truncation_sign(contents)?;
+	if from_section && lacks_final_newline(contents) {
+		return Some(format!(
+			"{}, and the last line has no trailing newline",
+			reason
+		));
+	}
+	Some(reason)
+}
+
+/// Returns true if the contents end part way through a line
+pub fn lacks_final_newline(contents: &str) -> bool {
+	!contents.is_empty() && !contents.ends_with('\n')
+}
+
+// Returns the first sign in the contents themselves of being cut off
+fn truncation_sign(contents: &str) -> Option<String> {
+	let last_line = contents
+		.lines()
+		.rev()
+		.find(|line| !line.trim().is_empty())
+		.unwrap_or_default();
+
+	let lowered = last_line.to_lowercase();
+	if let Some(marker) = CONTINUE_MARKERS
+		.iter()
+		.find(|marker| lowered.contains(*marker))
+	{
+		return Some(format!(
+			"ends with a \"{}\" marker",
+			marker.trim_start_matches(['(', '['])
+		));
+	}
+
+	let fences = contents
+		.lines()
+		.filter(|line| line.trim_start().starts_with("```"))
+		.count();
+	if fences % 2 != 0 {
+		return Some("unterminated code fence".to_string());
+	}
+
+	if !contents.matches("\"\"\"").count().is_multiple_of(2) || scan_line(last_line).1 {
+		return Some("unterminated string".to_string());
+	}
+
+	let code: String = contents.lines().map(|line| scan_line(line).0).collect();
+	for &(opening, closing, name) in BRACKETS {
+		let open = code.matches(opening).count();
+		let closed = code.matches(closing).count();
+		if open > closed {
+			return Some(format!("{} unclosed {}", open - closed, name));
+		}
+	}
+	None
+}
+
+// Returns the characters of a line outside string and character literals,
+// and whether a string is still open at the end of it
+pub(crate) fn scan_line(line: &str) -> (String, bool) {
+	let chars: Vec<char> = line.replace("\"\"\"", "").chars().collect();
+	let mut code = String::new();
+	let mut in_string = false;
+	let mut i = 0;
+	while i < chars.len() {
+		match chars[i] {
+			'\\' if in_string => i += 1,
+			'"' => in_string = !in_string,
+			_ if in_string => {}
+			// Raw strings like r#"..."#, which may hold quotes and backslashes
+			'r' if let Some(hashes) = raw_string_start(&chars, i) => {
+				let body = i + hashes + 2;
+				let close = (body..chars.len()).find(|&j| {
+					chars[j] == '"' && (1..=hashes).all(|k| chars.get(j + k) == Some(&'#'))
+				});
+				match close {
+					Some(close) => i = close + hashes,
+					None => return (code, true),
+				}
+			}
+			// Character literals like '{' and '\'', but not lifetimes
+			'\'' if chars.get(i + 1) == Some(&'\\') => {
+				if let Some(end) = (i + 3..chars.len().min(i + 6)).find(|&j| chars[j] == '\'') {
+					i = end;
+				}
+			}
+			'\'' if chars.get(i + 2) == Some(&'\'') => i += 2,
+			c => code.push(c),
+		}
+		i += 1;
+	}
+	(code, in_string)
+}
+
+// Returns the number of `#`s if a raw string literal starts at the `r`
+fn raw_string_start(chars: &[char], i: usize) -> Option<usize> {
+	let before = i.checked_sub(1).map(|j| chars[j]);
+	let before_prefix = if before == Some('b') {
+		i.checked_sub(2).map(|j| chars[j])
+	}
+	else {
+		before
+	};
+	if before_prefix.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+		return None;
+	}
+	let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+	(chars.get(i + 1 + hashes) == Some(&'"')).then_some(hashes)
+}
@@ -0,0 +1,128 @@
+use std::io::{Cursor, Read};
+
+use claudeai_bundle::{
+	ArchiveFormat, ArchiveOptions, Error, FileSystem, Result, archive::write_archive,
+};
+
+#[test]
+fn test_archive_output() -> Result<()> {
+	let input = "// project/
+// ├── bin/
+// │   └── run
+// ├── latest -> bin/run
+// └── [-rw-------]  notes.txt
+
+// bin/run
+#!/bin/sh
+echo run
+
+// notes.txt
+private
+";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let options = ArchiveOptions {
+		mtime: 1_700_000_000,
+	};
+
+	// The same files always give the same bytes
+	for format in [ArchiveFormat::Tar, ArchiveFormat::TarGz, ArchiveFormat::Zip] {
+		let first = write_archive(&fs, Vec::new(), format, options)?;
+		let second = write_archive(&fs, Vec::new(), format, options)?;
+		assert_eq!(first, second, "{} output differs", format);
+	}
+
+	// Tar entries keep their modes and links, with a fixed time and owner
+	let tar = write_archive(&fs, Vec::new(), ArchiveFormat::Tar, options)?;
+	let mut entries = Vec::new();
+	for entry in tar::Archive::new(tar.as_slice()).entries()? {
+		let mut entry = entry?;
+		let header = entry.header();
+		assert_eq!(header.mtime()?, 1_700_000_000);
+		assert_eq!((header.uid()?, header.gid()?), (0, 0));
+		let path = entry.path()?.to_string_lossy().into_owned();
+		let mode = header.mode()?;
+		let link = entry
+			.link_name()?
+			.map(|link| link.to_string_lossy().into_owned());
+		let mut contents = String::new();
+		entry.read_to_string(&mut contents)?;
+		entries.push((path, mode, link, contents));
+	}
+	assert_eq!(
+		entries,
+		[
+			("project/".into(), 0o755, None, "".into()),
+			("project/bin/".into(), 0o755, None, "".into()),
+			(
+				"project/bin/run".into(),
+				0o755,
+				None,
+				"#!/bin/sh\necho run\n".into()
+			),
+			(
+				"project/latest".into(),
+				0o777,
+				Some("bin/run".into()),
+				"".into()
+			),
+			("project/notes.txt".into(), 0o600, None, "private\n".into()),
+		]
+	);
+
+	// Gzip wraps the same tar
+	let gz = write_archive(&fs, Vec::new(), ArchiveFormat::TarGz, options)?;
+	let mut unzipped = Vec::new();
+	flate2::read::GzDecoder::new(gz.as_slice()).read_to_end(&mut unzipped)?;
+	assert_eq!(unzipped, tar);
+
+	// Zip stores the modes and the nearest time it can hold
+	let zip = write_archive(&fs, Vec::new(), ArchiveFormat::Zip, options)?;
+	let mut zip =
+		zip::ZipArchive::new(Cursor::new(zip)).map_err(|err| Error::Archive(err.to_string()))?;
+	let mut notes = zip
+		.by_name("project/notes.txt")
+		.map_err(|err| Error::Archive(err.to_string()))?;
+	assert_eq!(notes.unix_mode().map(|mode| mode & 0o777), Some(0o600));
+	let time = notes
+		.last_modified()
+		.ok_or(Error::Archive("no time".into()))?;
+	assert_eq!(
+		(
+			time.year(),
+			time.month(),
+			time.day(),
+			time.hour(),
+			time.minute()
+		),
+		(2023, 11, 14, 22, 13)
+	);
+	let mut contents = String::new();
+	notes.read_to_string(&mut contents)?;
+	assert_eq!(contents, "private\n");
+	drop(notes);
+	assert!(
+		zip.by_name("project/latest")
+			.map_err(|err| Error::Archive(err.to_string()))?
+			.is_symlink()
+	);
+
+	// Formats are named like their extensions
+	assert_eq!(
+		ArchiveFormat::from_path("out.tgz"),
+		Some(ArchiveFormat::TarGz)
+	);
+	assert_eq!(
+		ArchiveFormat::from_path("out.ZIP"),
+		Some(ArchiveFormat::Zip)
+	);
+	assert_eq!(ArchiveFormat::from_path("out.bin"), None);
+	assert_eq!("tar.gz".parse::<ArchiveFormat>()?, ArchiveFormat::TarGz);
+
+	// Links out of the archive are refused
+	let escaping = FileSystem::parse("- passwd -> ../../etc/passwd\n", None, false)?;
+	assert!(matches!(
+		write_archive(&escaping, Vec::new(), ArchiveFormat::Tar, options),
+		Err(Error::UnsafePath(_))
+	));
+	Ok(())
+}
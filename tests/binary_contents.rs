@@ -0,0 +1,115 @@
+use std::fs;
+
+use claudeai_bundle::{
+	BuiltinFilter, BundleStream, DiagnosticKind, Encoding, FileSystem, FilterChain, Result, check,
+	pack::{pack, read_directory},
+};
+
+#[test]
+fn test_binary_contents() -> Result<()> {
+	let input = "// - assets/
+//   - logo.png
+// - latin1.txt
+// - notes.txt
+
+// assets/logo.png (base64)
+iVBORw0KGgoA
+AQL/
+
+// latin1.txt (hex)
+63 61 66 e9 0a
+
+// notes.txt
+plain text
+";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let logo = fs.get_node("assets/logo.png")?;
+	assert_eq!(
+		logo.bytes(),
+		Some(&b"\x89PNG\r\n\x1a\n\x00\x01\x02\xff"[..])
+	);
+	assert_eq!(logo.encoding(), Some(Encoding::Base64));
+	assert!(logo.is_binary());
+	assert_eq!(logo.contents(), None);
+	assert_eq!(fs.get_node("latin1.txt")?.bytes(), Some(&b"caf\xe9\n"[..]));
+	let notes = fs.get_node("notes.txt")?;
+	assert_eq!(notes.contents(), Some("plain text\n"));
+	assert!(!notes.is_binary());
+
+	// Streaming decodes sections the same way
+	let mut stream = BundleStream::new(input.as_bytes(), Some("// "), false)?;
+	while let Some(node) = stream.next_file()? {
+		let parsed = fs.get_node(&node.relative_location().to_string_lossy())?;
+		assert_eq!(node.bytes(), parsed.bytes());
+	}
+
+	// A section that does not decode is an error and leaves the file empty
+	let broken = "// - logo.png\n\n// logo.png (base64)\nnot base64!\n";
+	let (fs_broken, diagnostics) = FileSystem::parse_with_diagnostics(broken, Some("// "), false);
+	assert_eq!(diagnostics[0].kind, DiagnosticKind::InvalidEncoding);
+	assert_eq!(diagnostics[0].line, 4);
+	assert_eq!(fs_broken.get_node("logo.png")?.bytes(), None);
+	assert!(
+		BundleStream::new(broken.as_bytes(), Some("// "), false)?
+			.next_file()
+			.is_err()
+	);
+
+	// Both encodings round-trip, whatever the wrapping
+	let bytes: Vec<u8> = (0..=255).collect();
+	for encoding in [Encoding::Base64, Encoding::Hex] {
+		let encoded = encoding.encode(&bytes);
+		assert!(encoded.lines().all(|line| line.len() <= 76));
+		assert_eq!(encoding.decode(&encoded), Ok(bytes.clone()));
+	}
+
+	// Files are written byte for byte, and pack reads them back into a bundle
+	let dir = std::env::temp_dir().join("claudeai-bundle-test-binary-contents");
+	let _ = fs::remove_dir_all(&dir);
+	fs.write_to_disk(&dir)?;
+	assert_eq!(fs::read(dir.join("latin1.txt"))?, b"caf\xe9\n");
+	fs::write(dir.join("no-newline.txt"), "last line")?;
+	fs::write(dir.join("empty.txt"), "")?;
+	fs::write(dir.join("escapes.txt"), "\x1b\x01\x02\x03")?;
+	fs::write(
+		dir.join("code.js"),
+		"a();\n// helpers\n\n// Helpers for b.js\nb();\n",
+	)?;
+
+	let packed = pack(&read_directory(&dir)?);
+	assert!(packed.contains("// assets/logo.png (base64)\n"));
+	assert!(packed.contains("// escapes.txt (base64)\n"));
+	assert!(packed.contains("// notes.txt\nplain text\n"));
+	// Comments that would not start a section leave code as text
+	assert!(packed.contains("// code.js\na();\n"));
+	// Text without a final newline stays text, marked as such
+	assert!(packed.contains("// no-newline.txt (no final newline)\nlast line\n"));
+	let (unpacked, diagnostics) = FileSystem::parse_with_diagnostics(&packed, Some("// "), false);
+	assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+	assert!(check::check(&packed, Some("// ")).is_empty());
+	for path in [
+		"assets/logo.png",
+		"latin1.txt",
+		"notes.txt",
+		"empty.txt",
+		"escapes.txt",
+		"code.js",
+		"no-newline.txt",
+	] {
+		assert_eq!(
+			unpacked.get_node(path)?.bytes(),
+			Some(fs::read(dir.join(path))?.as_slice())
+		);
+	}
+	// and filters do not add one back
+	let mut unpacked = unpacked.into_owned();
+	unpacked.apply_filters(&FilterChain::from_iter([BuiltinFilter::TrailingNewline]));
+	assert_eq!(
+		unpacked.get_node("no-newline.txt")?.contents(),
+		Some("last line")
+	);
+
+	fs::remove_dir_all(&dir)?;
+	Ok(())
+}
@@ -0,0 +1,60 @@
+use claudeai_bundle::{DiagnosticKind, Result, Severity, check};
+
+#[test]
+fn test_check_bundle() -> Result<()> {
+	let input = "// - src/
+//   - main.rs
+//   - lib.rs
+//   - empty.rs
+// - NUL.txt
+// - README.md
+
+// src/main.rs
+fn main() {
+	println!(\"cut off\");
+
+// src/empty.rs
+
+// README.md
+```sh
+cargo run
+";
+
+	let diagnostics = check::check(input, Some("// "));
+	let found: Vec<_> = diagnostics
+		.iter()
+		.map(|diagnostic| (diagnostic.line, diagnostic.kind))
+		.collect();
+	assert_eq!(
+		found,
+		vec![
+			(3, DiagnosticKind::MissingContents),
+			(5, DiagnosticKind::MissingContents),
+			(5, DiagnosticKind::InvalidName),
+			(10, DiagnosticKind::TruncatedFile),
+			(12, DiagnosticKind::EmptyFile),
+			(16, DiagnosticKind::TruncatedFile),
+		]
+	);
+	assert!(diagnostics.iter().all(|d| d.severity == Severity::Warning));
+	assert!(diagnostics[3].message.contains("unclosed braces"));
+	assert!(diagnostics[5].message.contains("code fence"));
+
+	// Machine-readable output is one JSON object per diagnostic
+	assert_eq!(
+		diagnostics[4].to_json(),
+		concat!(
+			r#"{"severity":"warning","kind":"empty-file","line":12,"column":8,"#,
+			r#""message":"`src/empty.rs` is empty","snippet":"// src/empty.rs"}"#
+		)
+	);
+
+	// A coherent bundle has nothing to report
+	let input = "// - main.rs
+
+// main.rs
+fn main() {}
+";
+	assert!(check::check(input, Some("// ")).is_empty());
+	Ok(())
+}
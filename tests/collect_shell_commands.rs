@@ -0,0 +1,66 @@
+use claudeai_bundle::{FileSystem, Result, commands::setup_script};
+
+#[test]
+fn test_collect_shell_commands() -> Result<()> {
+	let input = "// - src/
+//   - main.rs
+
+Start with `cargo new app`, then add the file below.
+
+// src/main.rs
+fn main() {}
+```
+
+Build it:
+
+```bash
+# compile first
+cargo build --release
+docker run \\
+  --rm app
+```
+
+```console
+$ cargo test
+running 1 test
+```
+
+```toml
+name = \"not a command\"
+```
+
+Then `cargo run`, or `./target/release/app`. `Config` and `cargo` alone are
+not commands, and `cargo run` is only taken once.
+";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	assert_eq!(
+		fs.get_node("src/main.rs")?.contents(),
+		Some("fn main() {}\n")
+	);
+
+	let commands: Vec<_> = fs
+		.shell_commands()
+		.into_iter()
+		.map(|command| (command.line, command.command))
+		.collect();
+	let expected: Vec<_> = [
+		(4, "cargo new app"),
+		(14, "cargo build --release"),
+		(15, "docker run \\\n  --rm app"),
+		(20, "cargo test"),
+		(28, "cargo run"),
+		(28, "./target/release/app"),
+	]
+	.into_iter()
+	.map(|(line, command)| (line, command.to_string()))
+	.collect();
+	assert_eq!(commands, expected);
+
+	// The script lists them in order and is never made to run
+	let script = setup_script(&fs.shell_commands());
+	assert!(script.starts_with("#!/bin/sh\n"));
+	assert!(script.contains("\n# line 14\ncargo build --release\n"));
+	assert!(script.ends_with("# line 28\n./target/release/app\n"));
+	Ok(())
+}
@@ -0,0 +1,67 @@
+use claudeai_bundle::{
+	DiagnosticKind, FileSystem, Result, Severity, check, truncation::truncation,
+};
+
+#[test]
+fn test_detect_truncation() -> Result<()> {
+	// Brackets inside strings and character literals do not count
+	assert_eq!(
+		truncation("fn main() {\n\tlet s = \"{\";\n\tlet c = '{';\n}\n", true),
+		None
+	);
+	assert_eq!(
+		truncation("fn main() {\n\tlet v = vec![1, 2\n", true),
+		Some("1 unclosed braces".to_string())
+	);
+	assert_eq!(
+		truncation("print(\"hello\n", false),
+		Some("unterminated string".to_string())
+	);
+	assert_eq!(
+		truncation("def f():\n\t\"\"\"Docs\n", false),
+		Some("unterminated string".to_string())
+	);
+	assert_eq!(
+		truncation("```sh\ncargo run\n", false),
+		Some("unterminated code fence".to_string())
+	);
+	assert_eq!(
+		truncation("fn a() {}\n// ... (continued in the next message)\n", false),
+		Some("ends with a \"continued\" marker".to_string())
+	);
+
+	// A missing final newline only backs up another sign
+	assert_eq!(truncation("key = 1", true), None);
+	assert_eq!(
+		truncation("key = [1", true),
+		Some("1 unclosed brackets, and the last line has no trailing newline".to_string())
+	);
+	assert_eq!(
+		truncation("key = [1", false),
+		Some("1 unclosed brackets".to_string())
+	);
+
+	// The last section of a bundle cut off mid-line is flagged
+	let input = "// - a.rs
+// - b.toml
+
+// a.rs
+fn a() {}
+
+// b.toml
+name = \"b";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	assert_eq!(fs.get_node("a.rs")?.truncation(), None);
+	assert_eq!(
+		fs.get_node("b.toml")?.truncation(),
+		Some("unterminated string, and the last line has no trailing newline".to_string())
+	);
+
+	// check notes a missing final newline that is not a truncation
+	let input = "// - a.toml\n\n// a.toml\nkey = 1";
+	let diagnostics = check::check(input, Some("// "));
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!(diagnostics[0].kind, DiagnosticKind::NoFinalNewline);
+	assert_eq!(diagnostics[0].severity, Severity::Note);
+	Ok(())
+}
@@ -0,0 +1,51 @@
+use std::fs;
+
+use claudeai_bundle::{
+	FileSystem, Result,
+	diff::{self, ChangeKind},
+};
+
+#[test]
+fn test_diff_directory() -> Result<()> {
+	let input = "// File structure
+// - Cargo.toml
+// - src/
+//   - main.rs
+
+// Cargo.toml
+[package]
+name = \"example\"
+
+// src/main.rs
+fn main() {}";
+
+	let dir = std::env::temp_dir().join("claudeai-bundle-test-diff-directory");
+	let _ = fs::remove_dir_all(&dir);
+	fs::create_dir_all(&dir)?;
+	fs::write(dir.join("Cargo.toml"), "[package]\nname = \"old\"\n")?;
+	fs::write(dir.join("README.md"), "readme\n")?;
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let changes = diff::diff_directory(&fs, &dir)?;
+
+	let summary: Vec<(String, ChangeKind)> = changes
+		.iter()
+		.map(|change| (change.path.display().to_string(), change.kind))
+		.collect();
+	assert_eq!(
+		summary,
+		vec![
+			("Cargo.toml".to_string(), ChangeKind::Modified),
+			("README.md".to_string(), ChangeKind::Removed),
+			("src/main.rs".to_string(), ChangeKind::Added),
+		]
+	);
+
+	let cargo_diff = changes[0].unified_diff();
+	assert!(cargo_diff.contains("--- a/Cargo.toml"));
+	assert!(cargo_diff.contains("-name = \"old\""));
+	assert!(cargo_diff.contains("+name = \"example\""));
+
+	fs::remove_dir_all(&dir)?;
+	Ok(())
+}
@@ -0,0 +1,98 @@
+use std::path::PathBuf;
+
+use claudeai_bundle::{
+	FileSystem, Result,
+	diff::{self, NodeChange, NodeKind},
+};
+
+#[test]
+fn test_diff_filesystems() -> Result<()> {
+	let old = "// File structure
+// - Cargo.toml
+// - README.md
+// - docs
+// - src/
+//   - routes.rs
+
+// Cargo.toml
+[package]
+name = \"example\"
+
+// README.md
+readme
+
+// src/routes.rs
+pub fn index() -> &'static str {
+    \"index\"
+}
+
+pub fn health() -> &'static str {
+    \"ok\"
+}";
+
+	let new = "// File structure
+// - Cargo.toml
+// - docs/
+// - src/
+//   - handlers.rs
+//   - main.rs
+
+// Cargo.toml
+[package]
+name = \"renamed\"
+
+// src/handlers.rs
+pub fn index() -> &'static str {
+    \"index\"
+}
+
+pub fn health() -> &'static str {
+    \"healthy\"
+}
+
+// src/main.rs
+fn main() {}";
+
+	let old = FileSystem::parse(old, Some("// "), false)?;
+	let new = FileSystem::parse(new, Some("// "), false)?;
+	let changes = diff::diff_filesystems(&old, &new);
+
+	assert!(changes.iter().any(|change| matches!(
+		change,
+		NodeChange::ContentsChanged { path, .. } if path == &PathBuf::from("Cargo.toml")
+	)));
+	assert!(changes.contains(&NodeChange::Removed {
+		path: "README.md".into(),
+		is_directory: false,
+	}));
+	assert!(changes.contains(&NodeChange::TypeChanged {
+		path: "docs".into(),
+		old_kind: NodeKind::File,
+		new_kind: NodeKind::Directory,
+	}));
+	assert!(changes.contains(&NodeChange::Added {
+		path: "src/main.rs".into(),
+		is_directory: false,
+	}));
+	assert!(changes.iter().any(|change| matches!(
+		change,
+		NodeChange::Renamed { from, to, .. }
+			if from == &PathBuf::from("src/routes.rs") && to == &PathBuf::from("src/handlers.rs")
+	)));
+	assert_eq!(changes.len(), 5);
+
+	// A file that became a symlink is reported as such
+	let old = FileSystem::parse("// - bin/\n// - current\n", Some("// "), false)?;
+	let new = FileSystem::parse("// - bin/\n// - current -> bin\n", Some("// "), false)?;
+	let changes = diff::diff_filesystems(&old, &new);
+	assert_eq!(
+		changes,
+		[NodeChange::TypeChanged {
+			path: "current".into(),
+			old_kind: NodeKind::File,
+			new_kind: NodeKind::Symlink,
+		}]
+	);
+	assert_eq!(NodeKind::Symlink.to_string(), "symlink");
+	Ok(())
+}
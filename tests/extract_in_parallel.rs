@@ -0,0 +1,72 @@
+use std::{fs, num::NonZeroUsize};
+
+use claudeai_bundle::{FileSystem, Journal, ParallelOptions, Result};
+
+#[test]
+fn test_extract_in_parallel() -> Result<()> {
+	let input = "// File structure
+// - a.txt
+// - b.txt
+// - src/
+//   - c.txt
+//   - d.txt
+
+// a.txt
+a
+
+// b.txt
+b
+
+// src/c.txt
+c
+
+// src/d.txt
+d";
+
+	let output_dir = std::env::temp_dir().join("claudeai-bundle-test-extract-in-parallel");
+	let _ = fs::remove_dir_all(&output_dir);
+	// A directory in the way of a file makes that one write fail
+	fs::create_dir_all(output_dir.join("b.txt"))?;
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let options = ParallelOptions {
+		workers: NonZeroUsize::new(4).unwrap(),
+		fail_fast: false,
+	};
+
+	// The failure is reported and the other files are still written
+	let failures = fs.write_to_disk_parallel(&output_dir, options)?;
+	assert_eq!(failures.len(), 1);
+	assert_eq!(failures[0].path.to_string_lossy(), "b.txt");
+	for path in ["a.txt", "src/c.txt", "src/d.txt"] {
+		assert!(output_dir.join(path).is_file());
+	}
+
+	// With fail-fast and a single worker nothing after the failure is written
+	fs::remove_dir_all(output_dir.join("src"))?;
+	let options = ParallelOptions {
+		workers: NonZeroUsize::MIN,
+		fail_fast: true,
+	};
+	let failures = fs.write_to_disk_parallel(&output_dir, options)?;
+	assert_eq!(failures.len(), 1);
+	assert!(output_dir.join("src").is_dir());
+	assert!(!output_dir.join("src/c.txt").exists());
+
+	// Journaled writes can be undone like sequential ones
+	fs::remove_dir_all(output_dir.join("b.txt"))?;
+	fs::remove_dir_all(output_dir.join("src"))?;
+	let mut journal = Journal::new(&output_dir)?;
+	let failures = journal.write_parallel(&fs, ParallelOptions::default())?;
+	journal.save()?;
+	assert!(failures.is_empty());
+	assert_eq!(fs::read_to_string(output_dir.join("src/d.txt"))?, "d");
+
+	Journal::load(&output_dir)?.undo()?;
+	assert!(!output_dir.join("src").exists());
+	assert!(!output_dir.join("b.txt").exists());
+	assert_eq!(fs::read_to_string(output_dir.join("a.txt"))?, "a\n");
+
+	fs::remove_dir_all(&output_dir)?;
+	Ok(())
+}
@@ -0,0 +1,92 @@
+use std::fs;
+
+use claudeai_bundle::{Error, FileSystem, Journal, JournalEntry, Result};
+
+#[test]
+fn test_extract_journal_undo() -> Result<()> {
+	let input = "// File structure
+// - README.md
+// - src/
+//   - main.rs
+
+// README.md
+new readme
+
+// src/main.rs
+fn main() {}";
+
+	let output_dir = std::env::temp_dir().join("claudeai-bundle-test-journal-undo");
+	let _ = fs::remove_dir_all(&output_dir);
+	fs::create_dir_all(&output_dir)?;
+	fs::write(output_dir.join("README.md"), "old readme")?;
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	Journal::extract(&fs, &output_dir)?;
+	assert!(fs::read_to_string(output_dir.join("README.md"))?.starts_with("new readme"));
+	assert!(output_dir.join("src/main.rs").exists());
+
+	// Undo refuses while an extracted file has local changes
+	fs::write(output_dir.join("src/main.rs"), "fn main() { edited(); }")?;
+	let result = Journal::load(&output_dir)?.undo();
+	assert!(matches!(result, Err(Error::ModifiedSinceExtraction(_))));
+	assert!(output_dir.join("src/main.rs").exists());
+
+	// Once the change is reverted, undo restores the original state
+	fs::write(output_dir.join("src/main.rs"), "fn main() {}")?;
+	Journal::load(&output_dir)?.undo()?;
+	assert_eq!(
+		fs::read_to_string(output_dir.join("README.md"))?,
+		"old readme"
+	);
+	assert!(!output_dir.join("src").exists());
+	assert!(!output_dir.join(".claudeai-bundle").exists());
+
+	// Files a follow-up marks deleted are removed, and undo brings them back
+	let follow_up = "// - NOTES.md
+
+// NOTES.md
+notes
+
+// README.md (deleted)
+";
+	let fs = FileSystem::parse(follow_up, Some("// "), false)?;
+	let journal = Journal::extract(&fs, &output_dir)?;
+	assert!(journal.entries().contains(&JournalEntry::RemovedFile {
+		path: "README.md".into(),
+		stash: "1".into()
+	}));
+	assert!(!output_dir.join("README.md").exists());
+	Journal::load(&output_dir)?.undo()?;
+	assert_eq!(
+		fs::read_to_string(output_dir.join("README.md"))?,
+		"old readme"
+	);
+	assert!(!output_dir.join("NOTES.md").exists());
+
+	// Paths with tabs survive the journal, so undo removes the right file
+	let tabbed = "// - a\tb.txt\n\n// a\tb.txt\ntabbed\n";
+	let fs = FileSystem::parse(tabbed, Some("// "), false)?;
+	Journal::extract(&fs, &output_dir)?;
+	assert!(output_dir.join("a\tb.txt").exists());
+	assert!(matches!(
+		&Journal::load(&output_dir)?.entries()[0],
+		JournalEntry::CreatedFile { path, .. } if path.to_str() == Some("a\tb.txt")
+	));
+	Journal::load(&output_dir)?.undo()?;
+	assert!(!output_dir.join("a\tb.txt").exists());
+
+	// A bundle cannot write into the journal directory
+	let forged = "// - .claudeai-bundle/
+//   - journal
+
+// .claudeai-bundle/journal
+remove\t0\tREADME.md
+";
+	let fs = FileSystem::parse(forged, Some("// "), false)?;
+	let result = Journal::extract(&fs, &output_dir);
+	assert!(matches!(result, Err(Error::UnsafePath(_))));
+	assert!(Journal::load(&output_dir)?.entries().is_empty());
+
+	fs::remove_dir_all(&output_dir)?;
+	Ok(())
+}
@@ -0,0 +1,187 @@
+use std::{fs, path::Path};
+
+use claudeai_bundle::{
+	ArchiveFormat, ArchiveOptions, ArchiveWriter, DiskTarget, Error, ExtractTarget, FileSystem,
+	Journal, JournalEntry, MemoryEntry, MemoryTarget, Result, archive::read_archive,
+};
+
+#[test]
+fn test_extract_targets() -> Result<()> {
+	let input = "// project/
+// ├── bin/
+// │   └── run
+// ├── latest -> bin/run
+// ├── [-rw-------]  notes.txt
+// └── main.rs
+
+// bin/run
+#!/bin/sh
+echo run
+
+// notes.txt
+private
+
+// main.rs
+fn main() {
+    run();
+}
+
+// ... rest of the code unchanged ...
+";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+
+	// Elided code is merged into a file already in memory
+	let mut memory = MemoryTarget::new();
+	memory.create_dir_all(Path::new("project"))?;
+	memory.write_file(
+		Path::new("project/main.rs"),
+		b"fn main() {\n    old();\n}\n\nfn run() {}\n",
+	)?;
+	fs.extract_to(&mut memory)?;
+	let paths: Vec<&Path> = memory.entries().map(|(path, _)| path).collect();
+	assert_eq!(
+		paths,
+		[
+			"project",
+			"project/bin",
+			"project/bin/run",
+			"project/latest",
+			"project/main.rs",
+			"project/notes.txt"
+		]
+		.map(Path::new)
+	);
+	assert_eq!(
+		memory.get("project/bin/run"),
+		Some(&MemoryEntry::File {
+			contents: b"#!/bin/sh\necho run\n".to_vec(),
+			mode: Some(0o755),
+		})
+	);
+	assert_eq!(
+		memory.get("project/notes.txt"),
+		Some(&MemoryEntry::File {
+			contents: b"private\n".to_vec(),
+			mode: Some(0o600),
+		})
+	);
+	assert_eq!(
+		memory.get("project/latest"),
+		Some(&MemoryEntry::Symlink {
+			target: "bin/run".into()
+		})
+	);
+	assert_eq!(
+		memory.read_file(Path::new("project/main.rs"))?,
+		Some(b"fn main() {\n    run();\n}\n\nfn run() {}\n".to_vec())
+	);
+
+	// Memory behaves like a directory on disk
+	let mut empty = MemoryTarget::new();
+	assert!(matches!(
+		empty.write_file(Path::new("src/lib.rs"), b""),
+		Err(Error::MissingParent(_))
+	));
+	assert!(matches!(
+		memory.write_file(Path::new("project/latest"), b""),
+		Err(Error::UnsafePath(_))
+	));
+	assert!(matches!(
+		fs.extract_to(&mut empty),
+		Err(Error::PartialFile(_))
+	));
+
+	// The disk target writes the same files
+	let dir = std::env::temp_dir().join("claudeai-bundle-test-extract-targets");
+	let _ = fs::remove_dir_all(&dir);
+	let mut disk = DiskTarget::new(&dir);
+	disk.create_dir_all(Path::new("project"))?;
+	disk.write_file(
+		Path::new("project/main.rs"),
+		b"fn main() {\n    old();\n}\n\nfn run() {}\n",
+	)?;
+	fs.extract_to(&mut disk)?;
+	assert!(disk.exists(Path::new("project/latest")));
+	assert_eq!(
+		fs::read_to_string(dir.join("project/notes.txt"))?,
+		"private\n"
+	);
+	assert_eq!(
+		fs::read_to_string(dir.join("project/main.rs"))?,
+		"fn main() {\n    run();\n}\n\nfn run() {}\n"
+	);
+	fs::remove_dir_all(&dir)?;
+
+	// The journal is a target too, recording what it writes to disk
+	fs::create_dir_all(dir.join("project"))?;
+	fs::write(
+		dir.join("project/main.rs"),
+		"fn main() {\n    old();\n}\n\nfn run() {}\n",
+	)?;
+	let mut journal = Journal::new(&dir)?;
+	fs.extract_to(&mut journal)?;
+	assert!(journal.entries().contains(&JournalEntry::CreatedLink {
+		path: "project/latest".into()
+	}));
+	journal.save()?;
+	Journal::load(&dir)?.undo()?;
+	assert!(!dir.join("project/notes.txt").exists());
+	assert_eq!(
+		fs::read_to_string(dir.join("project/main.rs"))?,
+		"fn main() {\n    old();\n}\n\nfn run() {}\n"
+	);
+	fs::remove_dir_all(dir.join("project"))?;
+
+	// Neither writes directories through a symlink already on disk
+	#[cfg(unix)]
+	{
+		std::os::unix::fs::symlink(std::env::temp_dir(), dir.join("project"))?;
+		for result in [
+			fs.extract_to(&mut Journal::new(&dir)?),
+			fs.write_to_disk(&dir),
+		] {
+			assert!(matches!(result, Err(Error::UnsafePath(_))));
+		}
+	}
+	fs::remove_dir_all(&dir)?;
+
+	// An archive keeps its entries until finished, so modes can still change
+	let mut archive = ArchiveWriter::new(Vec::new(), ArchiveFormat::Tar, ArchiveOptions::default());
+	archive.create_dir_all(Path::new("project/bin"))?;
+	archive.write_file(Path::new("project/bin/run"), b"#!/bin/sh\n")?;
+	archive.symlink(Path::new("project/run"), "bin/run")?;
+	archive.write_file(Path::new("project/notes.txt"), b"private\n")?;
+	archive.set_mode(Path::new("project/bin/run"), 0o700)?;
+	assert!(archive.exists(Path::new("project/bin")));
+	assert!(matches!(
+		archive.set_mode(Path::new("project/run"), 0o700),
+		Err(Error::UnsafePath(_))
+	));
+	// A file over a directory is refused the same way by every target
+	for target in [&mut archive as &mut dyn ExtractTarget, &mut memory] {
+		assert!(matches!(
+			target.write_file(Path::new("project/bin"), b""),
+			Err(Error::AlreadyExists(_))
+		));
+	}
+	let tar = archive.finish()?;
+	let read = read_archive(tar.as_slice(), ArchiveFormat::Tar)?;
+	let paths: Vec<String> = read
+		.nodes()
+		.map(|node| node.relative_location().to_string_lossy().into_owned())
+		.collect();
+	assert_eq!(
+		paths,
+		[
+			"",
+			"project",
+			"project/bin",
+			"project/bin/run",
+			"project/run",
+			"project/notes.txt"
+		]
+	);
+	assert_eq!(read.get_node("project/bin/run")?.mode(), Some(0o700));
+	assert_eq!(read.get_node("project/notes.txt")?.mode(), Some(0o644));
+	Ok(())
+}
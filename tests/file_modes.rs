@@ -0,0 +1,137 @@
+use std::fs;
+
+use claudeai_bundle::{
+	DiagnosticKind, Error, FileSystem, Journal, Result, check,
+	pack::{pack, read_directory},
+};
+
+#[test]
+fn test_file_modes() -> Result<()> {
+	let input = "// project/
+// ├── [drwx------]  secrets
+// │   └── [-rw-------]  token.txt
+// ├── bin/
+// │   ├── run
+// │   └── deploy.sh
+// ├── current -> bin
+// └── notes.txt
+
+Make the deploy script executable and keep the notes private:
+
+```bash
+chmod u+x,go-w bin/deploy.sh && chmod 600 ./notes.txt
+```
+
+// bin/run
+#!/bin/sh
+echo run
+
+// bin/deploy.sh
+echo deploy
+
+// notes.txt
+Deploy on Fridays.
+
+// secrets/token.txt
+abc
+";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let secrets = fs.get_node("secrets")?;
+	assert!(secrets.is_directory());
+	assert_eq!(secrets.mode(), Some(0o700));
+	assert_eq!(fs.get_node("secrets/token.txt")?.mode(), Some(0o600));
+	// A shebang makes a file executable, chmod in the prose sets the rest
+	assert_eq!(fs.get_node("bin/run")?.mode(), Some(0o755));
+	assert_eq!(fs.get_node("bin/deploy.sh")?.mode(), Some(0o744));
+	assert_eq!(fs.get_node("notes.txt")?.mode(), Some(0o600));
+	assert_eq!(fs.get_node("bin")?.mode(), None);
+	let current = fs.get_node("current")?;
+	assert_eq!(current.link_target(), Some("bin"));
+	assert_eq!(current.mode(), None);
+	assert!(fs.tree_output().contains("├── current -> bin\n"));
+
+	// Modes and links are written, and undone with the journal
+	let dir = std::env::temp_dir().join("claudeai-bundle-test-file-modes");
+	let _ = fs::remove_dir_all(&dir);
+	Journal::extract(&fs, &dir)?;
+	let project = dir.join("project");
+	assert_eq!(
+		fs::read_link(project.join("current"))?,
+		std::path::Path::new("bin")
+	);
+	assert_eq!(
+		fs::read_to_string(project.join("current/run"))?,
+		"#!/bin/sh\necho run\n"
+	);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+		let mode = |path: &str| -> Result<u32> {
+			Ok(fs::metadata(project.join(path))?.permissions().mode() & 0o7777)
+		};
+		assert_eq!(mode("bin/run")?, 0o755);
+		assert_eq!(mode("bin/deploy.sh")?, 0o744);
+		assert_eq!(mode("secrets")?, 0o700);
+		assert_eq!(mode("secrets/token.txt")?, 0o600);
+
+		// Pack lists the modes and links it finds on disk
+		let packed = pack(&read_directory(&project)?);
+		assert!(packed.contains("// │   ├── [-rwxr--r--]  deploy.sh\n"));
+		assert!(packed.contains("// ├── current -> bin\n"));
+		assert!(!packed.contains("]  run\n"));
+		let unpacked = FileSystem::parse(&packed, Some("// "), false)?;
+		assert_eq!(unpacked.get_node("bin/deploy.sh")?.mode(), Some(0o744));
+		assert_eq!(unpacked.get_node("current")?.link_target(), Some("bin"));
+	}
+	Journal::load(&dir)?.undo()?;
+	assert!(fs::symlink_metadata(project.join("current")).is_err());
+	fs::remove_dir_all(&dir)?;
+
+	// Links out of the output directory are reported and never written
+	let escaping = "- app/\n  - config -> ../../etc\n- passwd -> /etc/passwd\n";
+	let diagnostics = check::check(escaping, None);
+	assert_eq!(diagnostics.len(), 2);
+	assert!(
+		diagnostics
+			.iter()
+			.all(|d| d.kind == DiagnosticKind::UnsafeLink)
+	);
+	let fs = FileSystem::parse(escaping, None, false)?;
+	let dir = std::env::temp_dir().join("claudeai-bundle-test-file-modes-unsafe");
+	let _ = fs::remove_dir_all(&dir);
+	assert!(matches!(fs.write_to_disk(&dir), Err(Error::UnsafePath(_))));
+	assert!(fs::symlink_metadata(dir.join("passwd")).is_err());
+	let _ = fs::remove_dir_all(&dir);
+
+	// Setuid, setgid and sticky bits from the bundle are dropped
+	let special = "// - [-rwsr-sr-t]  tool
+// - helper
+
+```bash
+chmod 4755 helper
+```
+
+// tool
+a
+
+// helper
+b
+";
+	let fs = FileSystem::parse(special, Some("// "), false)?;
+	assert_eq!(fs.get_node("tool")?.mode(), Some(0o755));
+	assert_eq!(fs.get_node("helper")?.mode(), Some(0o755));
+
+	// A body section for a symlink is an error rather than a panic
+	let (fs, diagnostics) = FileSystem::parse_with_diagnostics(
+		"// - link -> a.txt\n// - a.txt\n\n// link\nhello\n",
+		Some("// "),
+		false,
+	);
+	assert_eq!(fs.get_node("link")?.link_target(), Some("a.txt"));
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!(diagnostics[0].kind, DiagnosticKind::InvalidSection);
+	assert!(diagnostics[0].is_error());
+	assert_eq!(diagnostics[0].line, 4);
+	Ok(())
+}
@@ -0,0 +1,70 @@
+use std::path::Path;
+
+use claudeai_bundle::{BuiltinFilter, BundleStream, FileSystem, FilterChain, Result};
+
+#[test]
+fn test_filter_contents() -> Result<()> {
+	let input = "// - config.yaml
+// - src/
+//   - main.rs
+
+// config.yaml
+server:
+\tport: 8080  \r
+\thost: local
+
+// src/main.rs
+fn main() {}
+";
+
+	// Built-ins are picked by name, in the order given
+	let filters: Vec<BuiltinFilter> = [
+		"line-endings",
+		"tabs-to-spaces=2",
+		"strip-trailing-whitespace",
+	]
+	.into_iter()
+	.map(str::parse)
+	.collect::<Result<_>>()?;
+	assert_eq!(filters[1], BuiltinFilter::TabsToSpaces(2));
+	assert!("tabs-to-spaces=0".parse::<BuiltinFilter>().is_err());
+	assert!("dedent=1".parse::<BuiltinFilter>().is_err());
+	assert!("unknown".parse::<BuiltinFilter>().is_err());
+
+	let mut chain: FilterChain = filters.into_iter().collect();
+	// Custom filters see the path of the file they rewrite
+	chain.push(|path: &Path, contents: &str| {
+		if path.extension().is_some_and(|ext| ext == "rs") {
+			format!("// generated\n{}", contents)
+		}
+		else {
+			contents.to_string()
+		}
+	});
+
+	let mut fs = FileSystem::parse(input, Some("// "), false)?;
+	fs.apply_filters(&chain);
+	assert_eq!(
+		fs.get_node("config.yaml")?.contents(),
+		Some("server:\n  port: 8080\n  host: local\n")
+	);
+	assert_eq!(
+		fs.get_node("src/main.rs")?.contents(),
+		Some("// generated\nfn main() {}\n")
+	);
+
+	// Streaming runs the same filters as each file is read
+	let mut stream = BundleStream::new(input.as_bytes(), Some("// "), false)?.with_filters(chain);
+	while let Some(node) = stream.next_file()? {
+		let filtered = fs.get_node(&node.relative_location().to_string_lossy())?;
+		assert_eq!(node.contents(), filtered.contents());
+	}
+
+	// Dedent and trailing-newline leave other contents alone
+	let mut chain = FilterChain::new();
+	chain.push(BuiltinFilter::Dedent);
+	chain.push(BuiltinFilter::TrailingNewline);
+	assert_eq!(chain.apply(Path::new("a"), "  a\n    b"), "a\n  b\n");
+	assert_eq!(chain.apply(Path::new("a"), "a\n"), "a\n");
+	Ok(())
+}
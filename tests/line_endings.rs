@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use claudeai_bundle::{BuiltinFilter, BundleStream, FileSystem, FilterChain, LineEnding, Result};
+
+#[test]
+fn test_line_endings() -> Result<()> {
+	// Saved on Windows, with a byte order mark and no final newline
+	let input = "\u{feff}// - app.py\r\n// - notes.txt\r\n\r\n// app.py\r\nimport os\r\nprint(os.name)\r\n\r\n// notes.txt\r\nfirst\n(second";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let app = fs.get_node("app.py")?;
+	assert_eq!(app.contents(), Some("import os\r\nprint(os.name)\r\n"));
+	assert_eq!(app.line_ending(), Some(LineEnding::Crlf));
+	let notes = fs.get_node("notes.txt")?;
+	assert_eq!(notes.contents(), Some("first\n(second"));
+	assert_eq!(notes.line_ending(), Some(LineEnding::Lf));
+
+	// Streaming reads the same files
+	let mut stream = BundleStream::new(input.as_bytes(), Some("// "), false)?;
+	while let Some(node) = stream.next_file()? {
+		let parsed = fs.get_node(&node.relative_location().to_string_lossy())?;
+		assert_eq!(node.contents(), parsed.contents());
+	}
+
+	// The most common ending wins, and files without line breaks have none
+	assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), Some(LineEnding::Crlf));
+	assert_eq!(LineEnding::detect("a"), None);
+	assert_eq!(LineEnding::Lf.convert("a\r\nb\rc\n"), "a\nb\nc\n");
+	assert_eq!(LineEnding::Crlf.convert("a\nb\r\n"), "a\r\nb\r\n");
+	assert_eq!("crlf".parse::<LineEnding>()?, LineEnding::Crlf);
+	assert_eq!(
+		"line-endings=crlf".parse::<BuiltinFilter>()?,
+		BuiltinFilter::LineEndings(LineEnding::Crlf)
+	);
+
+	// A final newline is added in the file's own style
+	let mut chain = FilterChain::new();
+	chain.push(BuiltinFilter::TrailingNewline);
+	assert_eq!(chain.apply(Path::new("a"), "a\r\nb"), "a\r\nb\r\n");
+	assert_eq!(chain.apply(Path::new("a"), "a"), "a\n");
+	assert_eq!(chain.apply(Path::new("a"), ""), "");
+
+	// A section cut off mid-line still looks cut off once the newline is added
+	let mut fs = FileSystem::parse(input, Some("// "), false)?;
+	assert!(fs.get_node("notes.txt")?.truncation().is_some());
+	fs.apply_filters(&chain);
+	let notes = fs.get_node("notes.txt")?;
+	assert_eq!(notes.contents(), Some("first\n(second\n"));
+	assert_eq!(
+		notes.truncation().as_deref(),
+		Some("1 unclosed parentheses, and the last line has no trailing newline")
+	);
+	let mut stream = BundleStream::new(input.as_bytes(), Some("// "), false)?.with_filters(chain);
+	while let Some(node) = stream.next_file()? {
+		assert_eq!(node.truncation().is_some(), node.name() == "notes.txt");
+	}
+	Ok(())
+}
@@ -0,0 +1,82 @@
+use std::fs;
+
+use claudeai_bundle::{Error, FileSystem, Result, elision};
+
+#[test]
+fn test_merge_elided_contents() -> Result<()> {
+	let input = "// File structure
+// - src/
+//   - main.rs
+//   - lib.rs
+
+// src/main.rs
+use std::io;
+
+// ... existing imports ...
+
+fn main() {
+    run();
+}
+
+// ... rest of the code unchanged ...
+
+// src/lib.rs
+// ... existing functions ...
+pub fn new() {}";
+
+	let existing_main = "use std::io;
+use std::fs;
+
+fn main() {
+    old();
+}
+
+fn helper() {}
+";
+
+	let output_dir = std::env::temp_dir().join("claudeai-bundle-test-merge-elided");
+	let _ = fs::remove_dir_all(&output_dir);
+	fs::create_dir_all(output_dir.join("src"))?;
+	fs::write(output_dir.join("src/main.rs"), existing_main)?;
+
+	assert!(elision::is_elision_marker("# ... existing imports ..."));
+	assert!(!elision::is_elision_marker("// see parse(...) for details"));
+	// Ordinary comments that trail off are not markers
+	for comment in [
+		"    # wait for more...",
+		"// try the other one...",
+		"-- previous value ...",
+		"let x = 1; // ... existing",
+	] {
+		assert!(!elision::is_elision_marker(comment), "{}", comment);
+	}
+	assert!(elision::is_elision_marker("  // ..."));
+	assert!(elision::is_elision_marker(
+		"<!-- … rest remains the same … -->"
+	));
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+
+	// Fragments are spliced into the existing file around the anchors
+	fs.get_node("src/main.rs")?.write_to_disk(&output_dir)?;
+	assert_eq!(
+		fs::read_to_string(output_dir.join("src/main.rs"))?,
+		"use std::io;
+use std::fs;
+
+fn main() {
+    run();
+}
+
+fn helper() {}
+"
+	);
+
+	// Without a file to merge into the fragment is refused
+	let result = fs.get_node("src/lib.rs")?.write_to_disk(&output_dir);
+	assert!(matches!(result, Err(Error::PartialFile(_))));
+	assert!(!output_dir.join("src/lib.rs").exists());
+
+	fs::remove_dir_all(&output_dir)?;
+	Ok(())
+}
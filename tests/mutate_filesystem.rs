@@ -0,0 +1,57 @@
+use claudeai_bundle::{Error, FileSystem, Result};
+
+#[test]
+fn test_mutate_filesystem() -> Result<()> {
+	let mut fs = FileSystem::new();
+
+	fs.create_dir_all("src/routes")?;
+	fs.add_file("src/main.rs", Some("fn main() {}".into()))?;
+	fs.add_file("src/routes/index.rs", None)?;
+	fs.add_file("Cargo.toml", None)?;
+
+	// Collisions and missing parents are typed errors
+	assert!(matches!(
+		fs.add_file("src/main.rs", None),
+		Err(Error::AlreadyExists(_))
+	));
+	assert!(matches!(
+		fs.add_file("tests/it.rs", None),
+		Err(Error::MissingParent(_))
+	));
+	assert!(matches!(
+		fs.create_dir_all("Cargo.toml/bin"),
+		Err(Error::AlreadyExists(_))
+	));
+
+	fs.rename("src/routes", "handlers")?;
+	assert!(fs.get_node("src/routes/index.rs").is_err());
+	let index = fs.get_node("src/handlers/index.rs")?;
+	assert_eq!(
+		index.relative_location().to_string_lossy(),
+		"src/handlers/index.rs"
+	);
+	assert!(matches!(
+		fs.rename("src/main.rs", "handlers"),
+		Err(Error::AlreadyExists(_))
+	));
+
+	fs.move_node("src/handlers", "")?;
+	assert!(fs.get_node("handlers/index.rs").is_ok());
+	assert!(matches!(
+		fs.move_node("handlers", "handlers"),
+		Err(Error::PathResolution(_))
+	));
+
+	fs.remove("handlers")?;
+	assert!(fs.get_node("handlers/index.rs").is_err());
+
+	assert_eq!(
+		fs.tree_output(),
+		"├── src/
+│   └── main.rs
+└── Cargo.toml
+"
+	);
+	assert_eq!(fs.len(), 4);
+	Ok(())
+}
@@ -0,0 +1,93 @@
+use claudeai_bundle::{
+	DiagnosticKind, FileSystem, ParseOptions, Result,
+	normalize::{Normalization, normalize},
+};
+
+#[test]
+fn test_normalize_contents() -> Result<()> {
+	let input = "// - gutter.rs
+// - quoted.rs
+// - indented.py
+// - plain.rs
+
+// gutter.rs
+  9 | fn main() {
+ 10 |     run();
+ 11 | }
+
+// quoted.rs
+> > fn main() {
+> >     run();
+> > }
+
+// indented.py
+    def main():
+        run()
+
+// plain.rs
+fn main() {
+    run();
+}
+";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	for path in ["gutter.rs", "quoted.rs", "plain.rs"] {
+		assert_eq!(
+			fs.get_node(path)?.contents(),
+			Some("fn main() {\n    run();\n}\n"),
+			"{}",
+			path
+		);
+	}
+	assert_eq!(
+		fs.get_node("indented.py")?.contents(),
+		Some("def main():\n    run()\n")
+	);
+
+	// Each removal is reported in the order it was made
+	assert_eq!(
+		normalize("> 1 | a\n> 2 | b\n").map(|(_, applied)| applied),
+		Some(vec![Normalization::Blockquote, Normalization::LineNumbers])
+	);
+	assert_eq!(
+		normalize("\tx\n\n\ty\n"),
+		Some(("x\n\ny\n".to_string(), vec![Normalization::Indentation(1)]))
+	);
+
+	// A right-aligned column of numbers is a gutter too
+	assert_eq!(
+		normalize(" 9  a\n10    b\n").map(|(contents, _)| contents),
+		Some("a\n  b\n".to_string())
+	);
+
+	// Numbers that do not count up are contents, as is a partial quote
+	assert_eq!(normalize("1 | first\n3 | third\n"), None);
+	assert_eq!(normalize("> quoted\nnot quoted\n"), None);
+
+	// Integer YAML keys and numbered lines are not gutters
+	assert_eq!(normalize("1: one\n2: two\n3: three\n"), None);
+	assert_eq!(normalize("1 apple\n2 pears\n"), None);
+
+	// Each removal is a warning pointing at the file's first line
+	let list = "// - list.txt\n\n// list.txt\n1 | one\n2 | two\n";
+	let (fs, diagnostics) = FileSystem::parse_with_diagnostics(list, Some("// "), false);
+	assert_eq!(fs.get_node("list.txt")?.contents(), Some("one\ntwo\n"));
+	assert_eq!(diagnostics.len(), 1);
+	assert_eq!(diagnostics[0].kind, DiagnosticKind::NormalizedContents);
+	assert!(!diagnostics[0].is_error());
+	assert_eq!(
+		(diagnostics[0].line, diagnostics[0].snippet.as_str()),
+		(4, "1 | one")
+	);
+	assert!(diagnostics[0].message.contains("line-number gutter"));
+
+	// and can be turned off for contents that only look pasted
+	let options = ParseOptions { normalize: false };
+	let (fs, diagnostics) = FileSystem::parse_with_options(list, Some("// "), false, options);
+	assert_eq!(
+		fs.get_node("list.txt")?.contents(),
+		Some("1 | one\n2 | two\n")
+	);
+	assert!(diagnostics.is_empty());
+	Ok(())
+}
@@ -0,0 +1,77 @@
+use std::path::Path;
+
+use claudeai_bundle::{ExtractTarget, FileSystem, MemoryTarget, Result};
+
+#[test]
+fn test_overlay_bundles() -> Result<()> {
+	let initial = "// File structure
+// - Cargo.toml
+// - src/
+//   - main.rs
+//   - old.rs
+
+// Cargo.toml
+[package]
+name = \"example\"
+
+// src/main.rs
+fn main() {}
+
+// src/old.rs
+pub fn old() {}";
+
+	let follow_up = "// File structure
+// - Cargo.toml
+// - src/
+//   - main.rs
+//   - routes/
+//     - mod.rs
+
+// src/main.rs
+mod routes;
+fn main() {}
+
+// src/routes/mod.rs
+pub fn index() {}
+
+// src/old.rs (deleted)";
+
+	let mut fs = FileSystem::parse(initial, Some("// "), false)?;
+	let follow_up = FileSystem::parse(follow_up, Some("// "), false)?;
+	assert_eq!(follow_up.deletions(), ["src/old.rs"]);
+	fs.overlay(&follow_up)?;
+
+	// Files only listed in the follow-up keep their earlier contents
+	let cargo = fs.get_node("Cargo.toml")?.contents().unwrap_or_default();
+	assert!(cargo.contains("name = \"example\""));
+
+	// Resent files are replaced and new ones added
+	let main = fs.get_node("src/main.rs")?.contents().unwrap_or_default();
+	assert!(main.starts_with("mod routes;"));
+	assert!(fs.get_node("src/routes/mod.rs").is_ok());
+
+	// Deleted files are gone from both the tree and the node list
+	assert!(fs.get_node("src/old.rs").is_err());
+	assert!(!fs.nodes().any(|node| node.name() == "old.rs"));
+	assert_eq!(fs.len(), 6);
+
+	// Elided code in a follow-up is filled in from the earlier bundle, so
+	// the overlaid tree extracts whole
+	let elided = "// - src/
+//   - main.rs
+
+// src/main.rs
+mod config;
+mod routes;
+// ... rest of the code unchanged ...
+";
+	let elided = FileSystem::parse(elided, Some("// "), false)?;
+	fs.overlay(&elided)?;
+	let mut memory = MemoryTarget::new();
+	fs.extract_to(&mut memory)?;
+	assert_eq!(
+		memory.read_file(Path::new("src/main.rs"))?,
+		Some(b"mod config;\nmod routes;\nfn main() {}\n".to_vec())
+	);
+	Ok(())
+}
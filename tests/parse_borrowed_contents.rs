@@ -0,0 +1,32 @@
+use claudeai_bundle::{FileSystem, Result};
+
+#[test]
+fn test_parse_borrowed_contents() -> Result<()> {
+	let input = String::from(
+		"// File structure
+// - src/
+//   - main.rs
+
+// src/main.rs
+fn main() {}
+",
+	);
+
+	let owned = {
+		let fs = FileSystem::parse(&input, Some("// "), false)?;
+
+		// Contents are slices of the input rather than copies
+		let contents = fs.get_node("src/main.rs")?.contents().unwrap();
+		assert_eq!(contents, "fn main() {}\n");
+		let input_range = input.as_bytes().as_ptr_range();
+		assert!(input_range.contains(&contents.as_ptr()));
+
+		fs.into_owned()
+	};
+
+	// The owned filesystem outlives the input it was parsed from
+	drop(input);
+	let main = owned.get_node("src/main.rs")?;
+	assert_eq!(main.contents(), Some("fn main() {}\n"));
+	Ok(())
+}
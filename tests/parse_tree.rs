@@ -14,7 +14,7 @@ fn test_parse_tree() -> Result<()> {
 
 	let file3 = fs.get_node("dir1/dir2/file3").expect("file3 should exist");
 	assert_eq!(
-		file3.borrow().relative_location().to_string_lossy(),
+		file3.relative_location().to_string_lossy(),
 		"dir1/dir2/file3"
 	);
 	Ok(())
@@ -0,0 +1,61 @@
+use claudeai_bundle::{DiagnosticKind, Error, FileSystem, Result, Severity};
+
+#[test]
+fn test_parse_with_diagnostics() -> Result<()> {
+	let input = "// - src/
+//   - main.rs
+//     - nested.rs
+//   - main.rs
+//    - odd.rs
+// - README.md
+// - lib/
+
+// src/main.rs
+fn main() {}
+
+// docs/guide.md
+guide
+
+// lib
+not a file";
+
+	let (fs, diagnostics) = FileSystem::parse_with_diagnostics(input, Some("// "), false);
+
+	let found: Vec<_> = diagnostics
+		.iter()
+		.map(|diagnostic| (diagnostic.line, diagnostic.kind))
+		.collect();
+	assert_eq!(
+		found,
+		vec![
+			(3, DiagnosticKind::NestedUnderFile),
+			(3, DiagnosticKind::MissingContents),
+			(4, DiagnosticKind::DuplicatePath),
+			(5, DiagnosticKind::AmbiguousIndentation),
+			(5, DiagnosticKind::MissingContents),
+			(6, DiagnosticKind::MissingContents),
+			(12, DiagnosticKind::OrphanSection),
+			(15, DiagnosticKind::DirectoryContents),
+		]
+	);
+	assert_eq!(diagnostics[7].severity, Severity::Error);
+	assert!(
+		diagnostics[..7]
+			.iter()
+			.all(|d| d.severity == Severity::Warning)
+	);
+
+	// The best tree is still built around the problems
+	assert_eq!(
+		fs.get_node("src/main.rs")?.contents(),
+		Some("fn main() {}\n")
+	);
+	assert!(fs.get_node("src/nested.rs").is_ok());
+	assert!(fs.get_node("lib")?.is_directory());
+	assert_eq!(fs.len(), 7);
+
+	// Plain parsing fails on the first error
+	let result = FileSystem::parse(input, Some("// "), false);
+	assert!(matches!(result, Err(Error::Syntax(err)) if err.line == 15));
+	Ok(())
+}
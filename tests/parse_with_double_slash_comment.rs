@@ -31,7 +31,7 @@ fn main() {
 
 	// Check file contents
 	if let Ok(main) = fs.get_node("src/main.rs") {
-		let main_content = main.borrow().contents().map(|c| c.to_string()).unwrap_or_default();
+		let main_content = main.contents().map(|c| c.to_string()).unwrap_or_default();
 		assert!(main_content.contains("use std::net::SocketAddr;"));
 	}
 	Ok(())
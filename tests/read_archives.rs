@@ -0,0 +1,138 @@
+use claudeai_bundle::{
+	ArchiveFormat, ArchiveOptions, Error, FileSystem, Result,
+	archive::{read_archive, write_archive},
+	pack::pack,
+};
+
+// Builds a tar entry by hand, so names the tar crate refuses can be tested
+fn tar_entry(
+	builder: &mut tar::Builder<Vec<u8>>,
+	name: &str,
+	entry_type: tar::EntryType,
+	mode: u32,
+	contents: &[u8],
+) -> Result<()> {
+	let mut header = tar::Header::new_gnu();
+	header.as_gnu_mut().ok_or(Error::InvalidNodeType)?.name[..name.len()]
+		.copy_from_slice(name.as_bytes());
+	header.set_entry_type(entry_type);
+	header.set_mode(mode);
+	header.set_size(contents.len() as u64);
+	header.set_cksum();
+	builder.append(&header, contents)?;
+	Ok(())
+}
+
+#[test]
+fn test_read_archives() -> Result<()> {
+	// Entries with `./`, without their directories and of kinds left out
+	let mut builder = tar::Builder::new(Vec::new());
+	tar_entry(&mut builder, "./", tar::EntryType::Directory, 0o755, b"")?;
+	tar_entry(
+		&mut builder,
+		"./src/main.rs",
+		tar::EntryType::Regular,
+		0o644,
+		b"fn main() {}\n",
+	)?;
+	tar_entry(
+		&mut builder,
+		"./run.sh",
+		tar::EntryType::Regular,
+		0o6750,
+		b"#!/bin/sh\n",
+	)?;
+	tar_entry(&mut builder, "./hard", tar::EntryType::Link, 0o644, b"")?;
+	let mut link = tar::Header::new_gnu();
+	link.set_entry_type(tar::EntryType::Symlink);
+	link.set_size(0);
+	builder.append_link(&mut link, "main", "src/main.rs")?;
+	let tar = builder.into_inner()?;
+	assert_eq!(ArchiveFormat::detect(&tar), Some(ArchiveFormat::Tar));
+
+	let fs = read_archive(tar.as_slice(), ArchiveFormat::Tar)?;
+	let paths: Vec<String> = fs
+		.nodes()
+		.map(|node| node.relative_location().to_string_lossy().into_owned())
+		.collect();
+	assert_eq!(paths, ["", "src", "src/main.rs", "run.sh", "main"]);
+	assert_eq!(
+		fs.get_node("src/main.rs")?.contents(),
+		Some("fn main() {}\n")
+	);
+	assert_eq!(fs.get_node("src/main.rs")?.mode(), Some(0o644));
+	// Setuid and setgid are dropped
+	assert_eq!(fs.get_node("run.sh")?.mode(), Some(0o750));
+	assert_eq!(fs.get_node("main")?.link_target(), Some("src/main.rs"));
+
+	// What is written comes back the same from every format
+	let input = "// project/
+// ├── bin/
+// │   └── [-rwx------]  run
+// ├── logo.png
+// └── latest -> bin/run
+
+// bin/run
+#!/bin/sh
+
+// logo.png (hex)
+89 50 4e 47 00 ff
+";
+	let bundle = FileSystem::parse(input, Some("// "), false)?;
+	for format in [ArchiveFormat::Tar, ArchiveFormat::TarGz, ArchiveFormat::Zip] {
+		let archive = write_archive(&bundle, Vec::new(), format, ArchiveOptions::default())?;
+		assert_eq!(ArchiveFormat::detect(&archive), Some(format));
+		let fs = read_archive(archive.as_slice(), format)?;
+		assert_eq!(fs.get_node("project/bin/run")?.mode(), Some(0o700));
+		assert_eq!(
+			fs.get_node("project/logo.png")?.bytes(),
+			Some(&b"\x89PNG\x00\xff"[..])
+		);
+		assert_eq!(
+			fs.get_node("project/latest")?.link_target(),
+			Some("bin/run")
+		);
+
+		// And can be packed back into a bundle
+		let packed = pack(&fs);
+		let packed = FileSystem::parse(&packed, Some("// "), false)?;
+		assert_eq!(packed.get_node("project/bin/run")?.mode(), Some(0o700));
+		assert_eq!(
+			packed.get_node("project/logo.png")?.bytes(),
+			Some(&b"\x89PNG\x00\xff"[..])
+		);
+	}
+
+	// Paths and links out of the extraction directory are refused
+	for (name, entry_type, contents) in [
+		("../evil.sh", tar::EntryType::Regular, &b"rm -rf ~\n"[..]),
+		("/etc/cron.d/evil", tar::EntryType::Regular, &b""[..]),
+		("src/../../evil", tar::EntryType::Directory, &b""[..]),
+	] {
+		let mut builder = tar::Builder::new(Vec::new());
+		tar_entry(&mut builder, name, entry_type, 0o644, contents)?;
+		let tar = builder.into_inner()?;
+		assert!(
+			matches!(
+				read_archive(tar.as_slice(), ArchiveFormat::Tar),
+				Err(Error::UnsafePath(_))
+			),
+			"{} was read",
+			name
+		);
+	}
+	let mut builder = tar::Builder::new(Vec::new());
+	let mut link = tar::Header::new_gnu();
+	link.set_entry_type(tar::EntryType::Symlink);
+	link.set_size(0);
+	builder.append_link(&mut link, "etc", "../../etc")?;
+	let tar = builder.into_inner()?;
+	assert!(matches!(
+		read_archive(tar.as_slice(), ArchiveFormat::Tar),
+		Err(Error::UnsafePath(_))
+	));
+
+	// Bundles are not mistaken for archives
+	assert_eq!(ArchiveFormat::detect(input.as_bytes()), None);
+	Ok(())
+}
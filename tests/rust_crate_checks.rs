@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use claudeai_bundle::{DiagnosticKind, FileSystem, Result, RustIssueKind, Severity, check, rust};
+
+#[test]
+fn test_rust_crate_checks() -> Result<()> {
+	let input = "// - Cargo.toml
+// - src/
+//   - main.rs
+//   - config.rs
+//   - net/
+//     - mod.rs
+//     - client.rs
+//   - unused.rs
+
+// Cargo.toml
+[package]
+name = \"app\"
+
+[[bin]]
+name = \"tool\"
+path = \"src/bin/tool.rs\"
+
+// src/main.rs
+mod config;
+pub(crate) mod net;
+mod inline {
+	// mod commented;
+	mod nested;
+}
+
+fn main() {}
+
+// src/config.rs
+#[path = \"net/client.rs\"]
+mod client;
+mod missing;
+
+// src/net/mod.rs
+pub mod client;
+
+// src/net/client.rs
+pub fn connect() {}
+
+// src/unused.rs
+pub fn unused() {}
+";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let issues: Vec<_> = rust::check_crates(&fs)
+		.into_iter()
+		.map(|issue| (issue.kind, issue.path, issue.line))
+		.collect();
+	assert_eq!(
+		issues,
+		vec![
+			(
+				RustIssueKind::MissingTarget,
+				PathBuf::from("Cargo.toml"),
+				Some(6)
+			),
+			(
+				RustIssueKind::UnresolvedModule,
+				PathBuf::from("src/main.rs"),
+				Some(5)
+			),
+			(
+				RustIssueKind::UnresolvedModule,
+				PathBuf::from("src/config.rs"),
+				Some(3)
+			),
+			(
+				RustIssueKind::OrphanSourceFile,
+				PathBuf::from("src/unused.rs"),
+				None
+			),
+		]
+	);
+
+	// check maps them onto the lines of the bundle
+	let found: Vec<_> = check::check(input, Some("// "))
+		.into_iter()
+		.map(|diagnostic| (diagnostic.line, diagnostic.kind, diagnostic.severity))
+		.collect();
+	assert_eq!(
+		found,
+		vec![
+			(8, DiagnosticKind::OrphanSourceFile, Severity::Warning),
+			(16, DiagnosticKind::MissingTarget, Severity::Error),
+			(23, DiagnosticKind::UnresolvedModule, Severity::Error),
+			(31, DiagnosticKind::UnresolvedModule, Severity::Error),
+		]
+	);
+
+	// Braces in literals and comments do not open or close blocks
+	let input = "// - src/
+//   - lib.rs
+//   - outer.rs
+
+// src/lib.rs
+pub fn braces() -> usize {
+	let s = \"}}\";
+	let c = '}';
+	/* } */ s.len()
+}
+mod outer;
+mod inline {
+	// {
+	const OPEN: &str = \"{\";
+}
+mod missing;
+";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let issues: Vec<_> = rust::check_crates(&fs)
+		.into_iter()
+		.map(|issue| (issue.kind, issue.line, issue.column, issue.message))
+		.collect();
+	assert_eq!(issues.len(), 1);
+	assert_eq!(issues[0].0, RustIssueKind::UnresolvedModule);
+	assert_eq!((issues[0].1, issues[0].2), (Some(11), 5));
+	assert!(issues[0].3.contains("src/missing.rs"));
+
+	// Modules in block comments and raw strings are skipped, and every
+	// module on a line is found
+	let input = "// - src/
+//   - lib.rs
+//   - a/
+//     - b.rs
+
+// src/lib.rs
+/*
+mod commented;
+*/
+const RAW: &str = r#\"mod raw; \"quoted\"\"#;
+mod a { mod b; mod c; }
+";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let issues: Vec<_> = rust::check_crates(&fs)
+		.into_iter()
+		.map(|issue| (issue.kind, issue.line, issue.column, issue.message))
+		.collect();
+	assert_eq!(issues.len(), 1);
+	assert_eq!(issues[0].0, RustIssueKind::UnresolvedModule);
+	assert_eq!((issues[0].1, issues[0].2), (Some(5), 20));
+	assert!(issues[0].3.contains("src/a/c.rs"));
+
+	// A bundle that only lists its files cannot be judged
+	let input = "// - Cargo.toml
+// - src/
+//   - main.rs
+//   - extra.rs
+";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	assert!(rust::check_crates(&fs).is_empty());
+	Ok(())
+}
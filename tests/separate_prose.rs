@@ -0,0 +1,136 @@
+use claudeai_bundle::{BundleStream, FileSystem, Result};
+
+#[test]
+fn test_separate_prose() -> Result<()> {
+	let input = "// - src/
+//   - main.rs
+//   - routes.rs
+// - NOTES.md
+
+Here's the project:
+
+// src/main.rs
+```rust
+mod routes;
+
+fn main() {}
+```
+
+Now let's create the routes module:
+
+// src/routes.rs
+pub fn serve() {}
+
+This module only serves one route. It can grow later.
+
+// NOTES.md
+Here's the notes file:
+
+# Notes
+
+This sentence stays in a Markdown file.
+";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	assert_eq!(
+		fs.get_node("src/main.rs")?.contents(),
+		Some("mod routes;\n\nfn main() {}\n")
+	);
+	assert_eq!(
+		fs.get_node("src/routes.rs")?.contents(),
+		Some("pub fn serve() {}\n")
+	);
+	assert_eq!(
+		fs.get_node("NOTES.md")?.contents(),
+		Some("# Notes\n\nThis sentence stays in a Markdown file.\n")
+	);
+
+	// Spans point at the contents rather than the text around them
+	assert_eq!(fs.get_node("src/main.rs")?.span().body_lines, Some(10..13));
+
+	let dropped: Vec<_> = fs
+		.dropped_text()
+		.iter()
+		.map(|dropped| (dropped.line, dropped.text.as_str()))
+		.collect();
+	let expected = vec![
+		(6, "Here's the project:"),
+		(9, "```rust"),
+		(13, "```\n\nNow let's create the routes module:"),
+		(20, "This module only serves one route. It can grow later."),
+		(23, "Here's the notes file:"),
+	];
+	assert_eq!(dropped, expected);
+
+	// Streaming drops the same text
+	let mut stream = BundleStream::new(input.as_bytes(), Some("// "), false)?;
+	while let Some(node) = stream.next_file()? {
+		let parsed = fs.get_node(&node.relative_location().to_string_lossy())?;
+		assert_eq!(node.contents(), parsed.contents());
+	}
+	assert_eq!(stream.filesystem().dropped_text(), fs.dropped_text());
+
+	// Fences in the contents themselves are kept, and only fences around
+	// them or a stray one after them end the contents
+	let input = "// - fixture.rs
+// - wrapped.rs
+// - stray.rs
+// - config.yaml
+
+// fixture.rs
+const INPUT: &str = \"
+```bash
+cargo run
+```
+\";
+
+// wrapped.rs
+```rust
+const INPUT: &str = \"
+```bash
+cargo run
+```
+\";
+```
+
+Run it with the usual command.
+
+// stray.rs
+fn stray() {}
+```
+
+This function does nothing at all.
+
+// config.yaml
+usage: |
+  ```bash
+  cargo run
+  ```
+
+Everything here is part of the file.
+";
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+	let fixture = "const INPUT: &str = \"\n```bash\ncargo run\n```\n\";\n";
+	assert_eq!(fs.get_node("fixture.rs")?.contents(), Some(fixture));
+	assert_eq!(fs.get_node("wrapped.rs")?.contents(), Some(fixture));
+	assert_eq!(fs.get_node("stray.rs")?.contents(), Some("fn stray() {}\n"));
+	assert!(
+		fs.get_node("config.yaml")?
+			.contents()
+			.is_some_and(|contents| contents.ends_with("Everything here is part of the file.\n"))
+	);
+	let dropped: Vec<_> = fs
+		.dropped_text()
+		.iter()
+		.map(|dropped| (dropped.line, dropped.text.as_str()))
+		.collect();
+	assert_eq!(
+		dropped,
+		[
+			(14, "```rust"),
+			(20, "```\n\nRun it with the usual command."),
+			(26, "```\n\nThis function does nothing at all."),
+		]
+	);
+	Ok(())
+}
@@ -0,0 +1,45 @@
+use std::{sync::Arc, thread};
+
+use claudeai_bundle::{FileSystem, Result};
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_share_across_threads() -> Result<()> {
+	assert_send_sync::<FileSystem>();
+
+	let input = "// File structure
+// - src/
+//   - main.rs
+
+// src/main.rs
+fn main() {}";
+
+	// Parse on one thread and read on others
+	let fs = thread::spawn(move || FileSystem::parse(input, Some("// "), false))
+		.join()
+		.expect("parser thread panicked")?;
+	let fs = Arc::new(fs);
+
+	let readers: Vec<_> = (0..4)
+		.map(|_| {
+			let fs = Arc::clone(&fs);
+			thread::spawn(move || {
+				let main = fs
+					.get_node("src/main.rs")
+					.expect("src/main.rs should exist");
+				(
+					main.relative_location().display().to_string(),
+					main.parent().map(|parent| parent.name().to_string()),
+				)
+			})
+		})
+		.collect();
+
+	for reader in readers {
+		let (path, parent) = reader.join().expect("reader thread panicked");
+		assert_eq!(path, "src/main.rs");
+		assert_eq!(parent.as_deref(), Some("src"));
+	}
+	Ok(())
+}
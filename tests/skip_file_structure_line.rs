@@ -25,6 +25,6 @@ fn test_skip_file_structure_line() -> Result<()> {
 
 	// There should be at least 3 nodes (root + 2 files) which means parsing
 	// happened
-	assert!(fs.nodes().len() >= 3, "Should have at least 3 nodes");
+	assert!(fs.len() >= 3, "Should have at least 3 nodes");
 	Ok(())
 }
@@ -0,0 +1,53 @@
+use claudeai_bundle::{Error, FileSystem, Result};
+
+#[test]
+fn test_source_spans() -> Result<()> {
+	let input = "// File structure
+// - src/
+//   - main.rs
+//   - lib.rs
+
+// src/main.rs
+fn main() {
+	println!(\"hello\");
+}
+
+// src/lib.rs
+pub fn lib() {}";
+
+	let fs = FileSystem::parse(input, Some("// "), false)?;
+
+	let src = fs.get_node("src")?;
+	assert_eq!(src.span().header_line, Some(2));
+	assert_eq!(src.span().body_lines, None);
+
+	let main = fs.get_node("src/main.rs")?;
+	assert_eq!(main.span().header_line, Some(3));
+	assert_eq!(main.span().body_lines, Some(7..10));
+
+	let lib = fs.get_node("src/lib.rs")?;
+	assert_eq!(lib.span().header_line, Some(4));
+	assert_eq!(lib.span().body_lines, Some(12..13));
+
+	// A body section for a directory points at the offending marker
+	let input = "// - src/
+//   - main.rs
+
+//  src
+fn main() {}";
+	let Err(Error::Syntax(err)) = FileSystem::parse(input, Some("// "), false)
+	else {
+		panic!("expected a syntax error");
+	};
+	assert_eq!((err.line, err.column), (4, 5));
+	assert_eq!(err.snippet, "//  src");
+	assert_eq!(
+		err.to_string(),
+		"Parse error: `src` is a directory and cannot have contents
+ --> line 4, column 5
+  |
+4 | //  src
+  |     ^"
+	);
+	Ok(())
+}
@@ -0,0 +1,45 @@
+use std::io::Cursor;
+
+use claudeai_bundle::{BundleStream, Result};
+
+#[test]
+fn test_stream_bundle() -> Result<()> {
+	let input = "// File structure
+// - Cargo.toml
+// - README.md
+// - src/
+//   - main.rs
+
+// src/main.rs
+fn main() {}
+
+// Cargo.toml
+[package]
+name = \"example\"
+";
+
+	let mut stream = BundleStream::new(Cursor::new(input), Some("// "), false)?;
+
+	// The whole structure is known before any contents are read
+	assert_eq!(stream.filesystem().len(), 5);
+	assert!(stream.filesystem().get_node("src")?.is_directory());
+
+	// Files come in the order their sections end
+	let main = stream.next_file()?.unwrap();
+	assert_eq!(main.relative_location().to_string_lossy(), "src/main.rs");
+	assert_eq!(main.contents(), Some("fn main() {}\n"));
+
+	let cargo = stream.next_file()?.unwrap();
+	assert_eq!(cargo.relative_location().to_string_lossy(), "Cargo.toml");
+	assert_eq!(cargo.contents(), Some("[package]\nname = \"example\"\n"));
+
+	// Earlier contents are dropped once the next file is read
+	let readme = stream.next_file()?.unwrap();
+	assert_eq!(readme.relative_location().to_string_lossy(), "README.md");
+	assert_eq!(readme.contents(), None);
+	let main = stream.filesystem().get_node("src/main.rs")?;
+	assert_eq!(main.contents(), None);
+
+	assert!(stream.next_file()?.is_none());
+	Ok(())
+}